@@ -0,0 +1,165 @@
+//! Link pipeline benchmarks, on objects from the test emitter
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use std::path::PathBuf;
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use common::*;
+use linker::bench::Inputs;
+use linker::config::Config;
+
+/// Objects with a section per function, each calling the next one
+fn many_small_functions(objects: usize, functions: usize) -> Vec<Object> {
+    (0..objects)
+        .map(|o| {
+            let mut object = Object::x86_64();
+            for f in 0..functions {
+                let code = if o + 1 == objects && f + 1 == functions {
+                    Code::new().exit(0)
+                } else if f + 1 == functions {
+                    Code::new().call(sym(&format!("f{}_0", o + 1))).ret()
+                } else {
+                    Code::new().call(sym(&format!("f{o}_{}", f + 1))).ret()
+                };
+                let text = object.text(&format!(".text.f{o}_{f}"), code);
+                object.func(&format!("f{o}_{f}"), text, 0);
+            }
+            object
+        })
+        .collect()
+}
+
+/// Objects with a large read-only section each, and a few functions loading from them
+fn few_huge_sections(objects: usize, size: usize) -> Vec<Object> {
+    (0..objects)
+        .map(|o| {
+            let mut object = Object::x86_64();
+            let data = object.rodata(".rodata", 16, &vec![o as u8; size]);
+            object.object(&format!("data{o}"), data, 0, size as u64);
+            let mut code = Code::new();
+            for i in 0..objects {
+                code = code.load_edi(sym(&format!("data{i}")), (size / 2) as i64);
+            }
+            let code = if o == 0 { code.exit_edi() } else { code.ret() };
+            let text = object.text(".text", code);
+            if o == 0 {
+                object.func("_start", text, 0);
+            } else {
+                object.func(&format!("load{o}"), text, 0);
+            }
+            object
+        })
+        .collect()
+}
+
+/// A table of pointers to a few functions, with a relocation per entry
+fn thousands_of_relocations(relocations: usize) -> Vec<Object> {
+    let mut object = Object::exiting(0);
+    for f in 0..16 {
+        let text = object.text(&format!(".text.f{f}"), Code::new().ret());
+        object.func(&format!("f{f}"), text, 0);
+    }
+    let table = object.data(".data", 8, &vec![0; 8 * relocations]);
+    for i in 0..relocations {
+        let target = sym(&format!("f{}", i % 16));
+        object.reloc(table, 8 * i as u64, R_X86_64_64, target, 0);
+    }
+    vec![object]
+}
+
+/// Objects defining many global and local symbols in one section each
+fn symbol_heavy(objects: usize, symbols: usize) -> Vec<Object> {
+    (0..objects)
+        .map(|o| {
+            let mut object = if o == 0 {
+                Object::exiting(0)
+            } else {
+                Object::x86_64()
+            };
+            let text = object.text(".text.symbols", Code::new().raw(&vec![0xc3; symbols]));
+            for i in 0..symbols {
+                object.func(&format!("global{o}_{i}"), text, i as u64);
+                object.local(&format!("local{i}"), text, i as u64);
+            }
+            object
+        })
+        .collect()
+}
+
+/// Written inputs of a scenario, linked with default flags
+struct Scenario {
+    _dir: TempDir,
+    config: Config,
+    inputs: Vec<PathBuf>,
+}
+
+impl Scenario {
+    fn new(objects: &[Object], flags: &[&str]) -> Self {
+        let dir = TempDir::new();
+        let objects: Vec<&Object> = objects.iter().collect();
+        let (config, inputs) = prepare(&dir, &objects, flags).unwrap();
+        Scenario {
+            _dir: dir,
+            config,
+            inputs,
+        }
+    }
+
+    fn read(&self) -> Inputs {
+        Inputs::read(&self.config, &self.inputs).unwrap()
+    }
+}
+
+fn scenarios() -> Vec<(&'static str, Scenario)> {
+    vec![
+        (
+            "many_small_functions",
+            Scenario::new(&many_small_functions(64, 64), &["-e", "f0_0"]),
+        ),
+        (
+            "few_huge_sections",
+            Scenario::new(&few_huge_sections(4, 4 << 20), &[]),
+        ),
+        (
+            "thousands_of_relocations",
+            Scenario::new(&thousands_of_relocations(16384), &[]),
+        ),
+    ]
+}
+
+fn link(c: &mut Criterion) {
+    for (name, scenario) in scenarios() {
+        c.bench_function(&format!("link/{name}"), |b| {
+            b.iter(|| linker::link(&scenario.config, &scenario.inputs).unwrap())
+        });
+    }
+}
+
+fn stages(c: &mut Criterion) {
+    for (name, scenario) in scenarios() {
+        let layout = scenario.read().lay_out().unwrap();
+        c.bench_function(&format!("iter_with_positions/{name}"), |b| {
+            b.iter(|| layout.chunk_positions())
+        });
+        c.bench_function(&format!("apply_relocations/{name}"), |b| {
+            b.iter_batched(
+                || layout.program(),
+                |mut program| layout.apply_relocations(&mut program).unwrap(),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+}
+
+fn extract_globals(c: &mut Criterion) {
+    let scenario = Scenario::new(&symbol_heavy(4, 16384), &[]);
+    let inputs = scenario.read();
+    c.bench_function("extract_globals/symbol_heavy", |b| {
+        b.iter(|| inputs.extract_globals().unwrap())
+    });
+}
+
+criterion_group!(benches, link, stages, extract_globals);
+criterion_main!(benches);
@@ -0,0 +1,64 @@
+//! AArch64 relocations
+//!
+//! Unlike on x86-64, most relocated fields are immediates inside an
+//! instruction, so the patch is computed from the original instruction bytes.
+
+use goblin::elf::reloc::*;
+
+use crate::endian::{Endian, ToBytes};
+
+/// Patched bytes for a relocation, with `symbol` (S), `addend` (A) and `place` (P)
+/// already resolved. `instruction` is the original word at the patch position.
+/// Returns `None` for unknown relocation types.
+pub fn patch(
+    mode: u32,
+    endian: Endian,
+    symbol: i128,
+    addend: i128,
+    place: i128,
+    instruction: u32,
+    overflow: impl Fn(i128) -> anyhow::Error,
+) -> anyhow::Result<Option<Vec<u8>>> {
+    let bytes = match mode {
+        R_AARCH64_ABS64 => ((symbol + addend) as u64).to_bytes(endian),
+        // `bl` and `b`, with a 26-bit word offset
+        R_AARCH64_CALL26 | R_AARCH64_JUMP26 => {
+            let value = symbol + addend - place;
+            if value % 4 != 0 || !(-(1 << 27)..(1 << 27)).contains(&value) {
+                return Err(overflow(value));
+            }
+            let imm26 = ((value >> 2) as u32) & 0x03ff_ffff;
+            with_immediate(instruction, 0x03ff_ffff, imm26)
+        }
+        // `adrp`, with a 21-bit offset in 4 KiB pages split into immlo and immhi
+        R_AARCH64_ADR_PREL_PG_HI21 => {
+            let value = page(symbol + addend) - page(place);
+            let pages = value >> 12;
+            if !(-(1 << 20)..(1 << 20)).contains(&pages) {
+                return Err(overflow(value));
+            }
+            let pages = pages as u32;
+            let immlo = (pages & 0b11) << 29;
+            let immhi = ((pages >> 2) & 0x7_ffff) << 5;
+            with_immediate(instruction, 0x6000_0000 | 0x00ff_ffe0, immlo | immhi)
+        }
+        // `add`, with the low 12 bits of the address, usually after an `adrp`
+        R_AARCH64_ADD_ABS_LO12_NC => {
+            let lo12 = ((symbol + addend) as u32) & 0xfff;
+            with_immediate(instruction, 0xfff << 10, lo12 << 10)
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(bytes))
+}
+
+/// Start of the 4 KiB page containing the address
+fn page(address: i128) -> i128 {
+    address & !0xfff
+}
+
+/// Replace the bits selected by `mask` in the instruction, which is
+/// little-endian regardless of the data byte order
+fn with_immediate(instruction: u32, mask: u32, immediate: u32) -> Vec<u8> {
+    ((instruction & !mask) | immediate).to_le_bytes().to_vec()
+}
@@ -0,0 +1,53 @@
+//! Annotated listing of the executable segment, for low-level debugging
+
+use std::io::Write;
+
+use crate::{
+    config::Config,
+    endian::{read_int, Endian},
+    open_files::InputCache,
+    section::LinkedProgram,
+    target::Target,
+};
+
+/// Lists every executable chunk with its virtual address range and origin,
+/// followed by the bytes patched into it by relocations.
+pub fn write<T: Write>(
+    config: &Config,
+    inputs: &InputCache,
+    linked: &LinkedProgram,
+    target: &mut T,
+) -> anyhow::Result<()> {
+    for it in linked.iter_with_positions(config) {
+        if !it.segment.permissions().execute {
+            continue;
+        }
+
+        let start = config.base_addr + it.chunk_start;
+        let end = start + it.chunk.size();
+        writeln!(
+            target,
+            "{start:#010x}..{end:#010x} {} from {}",
+            it.section.name,
+            inputs.description(it.chunk.input)
+        )?;
+
+        for (offset, bytes) in it.chunk.patches() {
+            let addr = start + offset as u64;
+            // Show the patch as a number, in the byte order it was written in.
+            // AArch64 instructions, the only 4-byte patches there, are always little-endian.
+            let endian = match config.target {
+                Target::Aarch64 if bytes.len() == 4 => Endian::Little,
+                _ => config.endian,
+            };
+            let value = read_int(bytes, false, endian) as u64;
+            writeln!(
+                target,
+                "    {addr:#010x}: patched {} bytes = {value:#0width$x}",
+                bytes.len(),
+                width = 2 + 2 * bytes.len(),
+            )?;
+        }
+    }
+    Ok(())
+}
@@ -1,62 +1,549 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+use crate::{math::parse_int, open_files::STDIN};
+
+/// Output path that writes to stdout
+pub const STDOUT: &str = "-";
 
 #[derive(Debug, Clone)]
 pub struct Args {
     pub library_paths: Vec<PathBuf>,
     pub inputs: Vec<PathBuf>,
-    pub output: PathBuf,
+    /// Required unless `--check-only` is given
+    pub output: Option<PathBuf>,
+    /// Unix permission bits for the output file
+    pub output_mode: u32,
+    /// Entry symbol or address, from `-e`/`--entry`
+    pub entry: Option<String>,
+    /// Load address of the image, from `--image-base`
+    pub image_base: Option<u64>,
+    /// Output format name, from `--oformat`
+    pub oformat: Option<String>,
+    /// Write an annotated listing of the executable segment here
+    pub annotate: Option<PathBuf>,
+    /// Print a link map to stdout, `-M`
+    pub print_map: bool,
+    /// Write a link map here, `--Map=FILE`
+    pub map: Option<PathBuf>,
+    pub gc_sections: bool,
+    pub section_headers: bool,
+    /// `-s`/`--strip-all`
+    pub strip_all: bool,
+    /// `-S`/`--strip-debug`
+    pub strip_debug: bool,
+    pub no_rosegment: bool,
+    pub check_symbol_bounds: bool,
+    pub guard_pages: bool,
+    pub sort_by_address: bool,
+    pub relro: bool,
+    pub eh_frame_hdr: bool,
+    /// `--icf=all|safe|none`
+    pub icf: Option<String>,
+    /// Symbols whose sections `--icf` must not fold
+    pub keep_unique: Vec<String>,
+    /// Let `--icf` fold read-only data as well as code
+    pub ignore_data_address_equality: bool,
+    /// Only resolve symbols and relocations, without writing any output
+    pub check_only: bool,
+    /// Requested stack size, from `-z stack-size=N`
+    pub stack_size: Option<u64>,
+    /// Segment alignment in memory, from `-z max-page-size=N`
+    pub max_page_size: Option<u64>,
+    /// Segment alignment in the file, from `--file-align N`
+    pub file_align: Option<u64>,
+    pub exec_stack: bool,
+    /// Digest style from `--build-id[=STYLE]`
+    pub build_id: Option<String>,
+    /// `-pie`, a position-independent executable
+    pub pie: bool,
+    pub demangle: bool,
+    /// Optimization level from `-O0` to `-O3`
+    pub opt_level: u8,
+    /// Files of `name address` lines, defining absolute symbols
+    pub symbol_maps: Vec<PathBuf>,
+    /// `--defsym SYM=VALUE`, as (SYM, VALUE)
+    pub defsyms: Vec<(String, String)>,
+    /// Linker script, `--script` or `-T`
+    pub script: Option<PathBuf>,
+    /// Options that were accepted for compatibility, but have no effect
+    pub ignored: Vec<String>,
 }
 
+/// Arguments of the current process
 pub fn read() -> Args {
-    let mut args = std::env::args().skip(1);
+    parse(std::env::args().skip(1).collect())
+}
+
+/// Command line arguments, without the program name. Invalid ones panic.
+pub fn parse(args: Vec<String>) -> Args {
+    let mut args = expand_response_files(args, 0).into_iter();
 
     let mut library_paths = Vec::new();
     let mut inputs = Vec::new();
     let mut output = None;
+    let mut output_mode = 0o755;
+    let mut annotate = None;
+    let mut print_map = false;
+    let mut map = None;
+    let mut entry = None;
+    let mut oformat = None;
+    let mut image_base = None;
+    let mut gc_sections = false;
+    let mut section_headers = false;
+    let mut strip_all = false;
+    let mut strip_debug = false;
+    let mut no_rosegment = false;
+    let mut check_symbol_bounds = false;
+    let mut guard_pages = false;
+    let mut sort_by_address = false;
+    let mut relro = false;
+    let mut eh_frame_hdr = false;
+    let mut check_only = false;
+    let mut icf = None;
+    let mut keep_unique = Vec::new();
+    let mut ignore_data_address_equality = false;
+    let mut stack_size = None;
+    let mut max_page_size = None;
+    let mut file_align = None;
+    let mut exec_stack = false;
+    let mut build_id = None;
+    let mut pie = false;
+    let mut demangle = false;
+    let mut opt_level = 1;
+    let mut symbol_maps = Vec::new();
+    let mut defsyms = Vec::new();
+    let mut script = None;
+    let mut ignored = Vec::new();
+    let mut print_ignored = false;
+
+    while let Some(mut arg) = args.next() {
+        // Normalize the two-argument form of `-z keyword`
+        if arg == "-z" {
+            let keyword = args.next().expect("-z value missing");
+            arg = format!("-z{keyword}");
+        }
 
-    while let Some(arg) = args.next() {
         match arg.as_str() {
             // Known options
             "-L" => {
                 let path = args.next().expect("-L value missing");
-                let path = PathBuf::try_from(path).expect("Invalid path");
+                let path = PathBuf::from(path);
+                if path.is_dir() {
+                    library_paths.push(path);
+                } else {
+                    log::debug!("Ignoring a non-dir -L path {path:?}");
+                }
+            }
+            _ if arg.starts_with("-L") => {
+                let path = PathBuf::from(&arg[2..]);
                 if path.is_dir() {
                     library_paths.push(path);
                 } else {
                     log::debug!("Ignoring a non-dir -L path {path:?}");
                 }
             }
+            "-l" => {
+                let name = args.next().expect("-l value missing");
+                inputs.push(InputArg::Library(name));
+            }
+            _ if arg.starts_with("-l") => {
+                inputs.push(InputArg::Library(arg[2..].to_owned()));
+            }
             "-o" => {
                 let path = args.next().expect("-o value missing");
-                let path = PathBuf::try_from(path).expect("Invalid path");
+                let path = PathBuf::from(path);
                 output = Some(path);
             }
-            // Ignore: Single-dash, single-value options
-            "-flavor" => {
-                let _ = args.next().expect("-flavor value missing");
+            "-e" | "--entry" => {
+                entry = Some(args.next().unwrap_or_else(|| panic!("{arg} value missing")));
+            }
+            _ if arg.starts_with("--entry=") => {
+                entry = Some(arg.trim_start_matches("--entry=").to_owned());
+            }
+            "--image-base" => {
+                let value = args.next().expect("--image-base value missing");
+                image_base = Some(parse_image_base(&value));
+            }
+            _ if arg.starts_with("--image-base=") => {
+                image_base = Some(parse_image_base(arg.trim_start_matches("--image-base=")));
+            }
+            "--oformat" => {
+                oformat = Some(args.next().expect("--oformat value missing"));
+            }
+            _ if arg.starts_with("--oformat=") => {
+                oformat = Some(arg.trim_start_matches("--oformat=").to_owned());
+            }
+            "--output-mode" => {
+                let mode = args.next().expect("--output-mode value missing");
+                output_mode = parse_mode(&mode);
+            }
+            _ if arg.starts_with("--output-mode=") => {
+                output_mode = parse_mode(arg.trim_start_matches("--output-mode="));
+            }
+            _ if arg.starts_with("--annotate=") => {
+                let path = arg.trim_start_matches("--annotate=");
+                let path = PathBuf::from(path);
+                annotate = Some(path);
+            }
+            "-M" | "--print-map" => print_map = true,
+            "-Map" | "--Map" => {
+                let path = args.next().unwrap_or_else(|| panic!("{arg} value missing"));
+                map = Some(PathBuf::from(path));
+            }
+            _ if arg.starts_with("-Map=") || arg.starts_with("--Map=") => {
+                let path = arg.split_once('=').unwrap().1;
+                map = Some(PathBuf::from(path));
+            }
+            "--symbol-map" => {
+                let path = args.next().expect("--symbol-map value missing");
+                let path = PathBuf::from(path);
+                symbol_maps.push(path);
+            }
+            _ if arg.starts_with("--symbol-map=") => {
+                let path = arg.trim_start_matches("--symbol-map=");
+                let path = PathBuf::from(path);
+                symbol_maps.push(path);
+            }
+            "--defsym" => {
+                let value = args.next().expect("--defsym value missing");
+                defsyms.push(parse_defsym(&value));
+            }
+            _ if arg.starts_with("--defsym=") => {
+                defsyms.push(parse_defsym(arg.trim_start_matches("--defsym=")));
+            }
+            "--script" | "-T" => {
+                let path = args.next().unwrap_or_else(|| panic!("{arg} value missing"));
+                let path = PathBuf::from(path);
+                script = Some(path);
+            }
+            _ if arg.starts_with("--script=") => {
+                let path = arg.trim_start_matches("--script=");
+                let path = PathBuf::from(path);
+                script = Some(path);
+            }
+            "--file-align" => {
+                let value = args.next().expect("--file-align value missing");
+                file_align = Some(parse_file_align(&value));
+            }
+            _ if arg.starts_with("--file-align=") => {
+                file_align = Some(parse_file_align(arg.trim_start_matches("--file-align=")));
+            }
+            "--gc-sections" => gc_sections = true,
+            "--section-headers" => section_headers = true,
+            "-s" | "--strip-all" => strip_all = true,
+            "-S" | "--strip-debug" => strip_debug = true,
+            "--no-rosegment" => no_rosegment = true,
+            "--rosegment" => no_rosegment = false,
+            "--check-symbol-bounds" => check_symbol_bounds = true,
+            "--guard-page-between-segments" => guard_pages = true,
+            "--sort-by-address" => sort_by_address = true,
+            "-zrelro" => relro = true,
+            "-znorelro" => relro = false,
+            "--eh-frame-hdr" => eh_frame_hdr = true,
+            "--no-eh-frame-hdr" => eh_frame_hdr = false,
+            "-zexecstack" => exec_stack = true,
+            "-znoexecstack" => exec_stack = false,
+            "--build-id" => build_id = Some("sha1".to_owned()),
+            "-pie" | "--pie" | "-static-pie" => pie = true,
+            "-no-pie" | "--no-pie" => pie = false,
+            _ if arg.starts_with("--build-id=") => {
+                build_id = Some(arg.trim_start_matches("--build-id=").to_owned());
+            }
+            "--check-only" => check_only = true,
+            _ if arg.starts_with("--icf=") => {
+                icf = Some(arg.trim_start_matches("--icf=").to_owned());
+            }
+            "--keep-unique" => {
+                keep_unique.push(args.next().expect("--keep-unique value missing"));
+            }
+            _ if arg.starts_with("--keep-unique=") => {
+                keep_unique.push(arg.trim_start_matches("--keep-unique=").to_owned());
+            }
+            "--ignore-data-address-equality" => ignore_data_address_equality = true,
+            "--demangle" => demangle = true,
+            "--no-demangle" => demangle = false,
+            "-O0" => opt_level = 0,
+            "-O1" => opt_level = 1,
+            "-O2" => opt_level = 2,
+            "-O3" => opt_level = 3,
+            _ if arg.starts_with("-zstack-size=") => {
+                stack_size = Some(parse_stack_size(arg.trim_start_matches("-zstack-size=")));
+            }
+            _ if arg.starts_with("-zmax-page-size=") => {
+                let value = arg.trim_start_matches("-zmax-page-size=");
+                max_page_size = Some(parse_page_size(value));
+            }
+            "--print-ignored-flags" => print_ignored = true,
+            // Ignore: Single-value options
+            "-flavor" | "-m" | "-plugin" | "-plugin-opt" | "-dynamic-linker"
+            | "--dynamic-linker" | "-soname" | "-h" | "-rpath" | "-rpath-link" | "--sysroot"
+            | "--version-script" | "-version-script" => {
+                let value = args.next().unwrap_or_else(|| panic!("{arg} value missing"));
+                ignored.push(format!("{arg} {value}"));
             }
             // Ignore: No-value options
-            "-nmagic" | "-Bstatic" | "-Bdynamic" | "-Wl,--as-needed" | "--as-needed"
-            | "--eh-frame-hdr" | "-znoexecstack" | "--gc-sections" | "-O1" | "-pie" => {}
+            "-nmagic"
+            | "-Bstatic"
+            | "-Bdynamic"
+            | "-Wl,--as-needed"
+            | "--as-needed"
+            | "--no-as-needed"
+            | "-static"
+            | "--no-dynamic-linker"
+            | "-nostdlib"
+            | "-nodefaultlibs"
+            | "--start-group"
+            | "--end-group"
+            | "-("
+            | "-)"
+            | "--whole-archive"
+            | "--no-whole-archive"
+            | "--no-undefined"
+            | "--fatal-warnings"
+            | "--color-diagnostics"
+            | "--discard-all"
+            | "-X"
+            | "-x"
+            | "--export-dynamic"
+            | "-E"
+            | "--enable-new-dtags"
+            | "--disable-new-dtags"
+            | "-znow"
+            | "-zlazy"
+            | "-ztext"
+            | "-znotext"
+            | "-zseparate-code"
+            | "-znoseparate-code"
+            | "-zdefs"
+            | "-zorigin"
+            | "-zcombreloc"
+            | "--no-gc-sections"
+            | "--push-state"
+            | "--pop-state" => {
+                ignored.push(arg);
+            }
             // Ignore: Known equals-options
-            _ if arg.starts_with("--script=") => {}
-            _ if arg.starts_with("-z") && arg.contains('=') => {}
+            _ if arg.starts_with("-plugin-opt=")
+                || arg.starts_with("--plugin-opt=")
+                || arg.starts_with("--hash-style=")
+                || arg.starts_with("--sysroot=")
+                || arg.starts_with("--version-script=")
+                || arg.starts_with("--dynamic-linker=")
+                || arg.starts_with("-soname=")
+                || arg.starts_with("--compress-debug-sections=")
+                || arg.starts_with("--color-diagnostics=")
+                || arg.starts_with("-melf")
+                || arg.starts_with("-Wl,")
+                || arg.starts_with("-z") && arg.contains('=') =>
+            {
+                ignored.push(arg);
+            }
+            "-r" | "--relocatable" | "-i" => {
+                panic!("Relocatable output ({arg}) is not supported, only static executables")
+            }
             // Not supported yet
-            other if arg.starts_with('-') => {
+            other if arg.starts_with('-') && other != STDIN => {
                 panic!("Unknown option {other:?}");
             }
-            // Input files
+            // Input files, or a single one read from stdin
             _ => {
-                let path = PathBuf::try_from(arg).expect("Invalid path");
-                assert!(path.is_file(), "input path must be a file ({path:?})");
-                inputs.push(path);
+                let path = PathBuf::from(arg);
+                if path == Path::new(STDIN) {
+                    assert!(
+                        !inputs
+                            .iter()
+                            .any(|i| matches!(i, InputArg::Path(p) if p == &path)),
+                        "stdin can only be read once"
+                    );
+                } else {
+                    assert!(path.is_file(), "input path must be a file ({path:?})");
+                }
+                inputs.push(InputArg::Path(path));
             }
         }
     }
 
+    // Libraries are resolved after all `-L` paths are known, keeping the input order
+    let inputs = inputs
+        .into_iter()
+        .map(|input| match input {
+            InputArg::Path(path) => path,
+            InputArg::Library(name) => find_library(&library_paths, &name),
+        })
+        .collect();
+
+    if print_ignored {
+        for flag in &ignored {
+            eprintln!("ignored flag: {flag}");
+        }
+    }
+
+    assert!(output.is_some() || check_only, "Output path missing");
+    assert!(
+        !(print_map && output.as_deref() == Some(Path::new(STDOUT))),
+        "-M can't be used with -o -, as both write to stdout"
+    );
+
     Args {
         library_paths,
         inputs,
-        output: output.expect("Output path missing"),
+        output,
+        output_mode,
+        entry,
+        image_base,
+        oformat,
+        annotate,
+        print_map,
+        map,
+        gc_sections,
+        section_headers,
+        strip_all,
+        strip_debug,
+        no_rosegment,
+        check_symbol_bounds,
+        guard_pages,
+        sort_by_address,
+        relro,
+        eh_frame_hdr,
+        check_only,
+        icf,
+        keep_unique,
+        ignore_data_address_equality,
+        stack_size,
+        max_page_size,
+        file_align,
+        exec_stack,
+        build_id,
+        pie,
+        demangle,
+        opt_level,
+        symbol_maps,
+        defsyms,
+        script,
+        ignored,
+    }
+}
+
+enum InputArg {
+    Path(PathBuf),
+    /// `-lNAME`
+    Library(String),
+}
+
+/// Nested `@file` arguments are expanded up to this depth, to stop reference cycles
+const MAX_RESPONSE_FILE_DEPTH: usize = 16;
+
+/// Replace `@file` arguments with the arguments listed in the file.
+/// Like in GNU ld, a missing file leaves the argument as-is.
+fn expand_response_files(args: Vec<String>, depth: usize) -> Vec<String> {
+    let mut result = Vec::with_capacity(args.len());
+    for arg in args {
+        let Some(path) = arg.strip_prefix('@') else {
+            result.push(arg);
+            continue;
+        };
+        let Ok(contents) = std::fs::read_to_string(path) else {
+            result.push(arg);
+            continue;
+        };
+        assert!(
+            depth < MAX_RESPONSE_FILE_DEPTH,
+            "Response files nested too deeply at {path:?}"
+        );
+        result.extend(expand_response_files(
+            split_response_file(&contents),
+            depth + 1,
+        ));
+    }
+    result
+}
+
+/// Split on whitespace, except within single or double quotes.
+/// A backslash escapes the next character anywhere.
+fn split_response_file(contents: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut current: Option<String> = None;
+    let mut quote: Option<char> = None;
+    let mut chars = contents.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                let escaped = chars.next().expect("Response file ends with a backslash");
+                current.get_or_insert_with(String::new).push(escaped);
+            }
+            _ if Some(c) == quote => quote = None,
+            '"' | '\'' if quote.is_none() => {
+                quote = Some(c);
+                // An empty quoted string is still an argument
+                current.get_or_insert_with(String::new);
+            }
+            _ if c.is_whitespace() && quote.is_none() => result.extend(current.take()),
+            _ => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+    assert!(quote.is_none(), "Unterminated quote in response file");
+    result.extend(current);
+    result
+}
+
+/// Search `libNAME.a` and `libNAME.rlib` in the library paths, in order
+fn find_library(library_paths: &[PathBuf], name: &str) -> PathBuf {
+    for dir in library_paths {
+        for file_name in [format!("lib{name}.a"), format!("lib{name}.rlib")] {
+            let path = dir.join(file_name);
+            if path.is_file() {
+                return path;
+            }
+        }
     }
+    panic!("Library -l{name} not found, searched in {library_paths:?}");
+}
+
+/// Image base address, decimal or `0x`-prefixed hex. It needn't be page-aligned,
+/// as the headers are mapped from the page it is in.
+fn parse_image_base(addr: &str) -> u64 {
+    parse_int(addr).unwrap_or_else(|| panic!("Invalid image base {addr:?}"))
+}
+
+/// Stack size in bytes, decimal or `0x`-prefixed hex.
+/// Must be nonzero, page-granular and at most 1 GiB.
+fn parse_stack_size(size: &str) -> u64 {
+    parse_int(size)
+        .filter(|size| *size != 0 && *size % 0x1000 == 0 && *size <= 1 << 30)
+        .unwrap_or_else(|| panic!("Invalid stack size {size:?}"))
+}
+
+/// `SYM=VALUE`, split at the first `=`
+fn parse_defsym(value: &str) -> (String, String) {
+    let (name, value) = value
+        .split_once('=')
+        .filter(|(name, value)| !name.is_empty() && !value.is_empty())
+        .unwrap_or_else(|| panic!("Invalid --defsym {value:?}, expected SYM=VALUE"));
+    (name.trim().to_owned(), value.trim().to_owned())
+}
+
+/// Power of two of at least `min`, decimal or `0x`-prefixed hex
+fn parse_power_of_two(value: &str, min: u64, what: &str) -> u64 {
+    parse_int(value)
+        .filter(|value| value.is_power_of_two() && *value >= min)
+        .unwrap_or_else(|| panic!("Invalid {what} {value:?}"))
+}
+
+/// Page size, at least 4 KiB
+fn parse_page_size(size: &str) -> u64 {
+    parse_power_of_two(size, 0x1000, "max page size")
+}
+
+/// Alignment of segments in the file, independent of the page size
+fn parse_file_align(align: &str) -> u64 {
+    parse_power_of_two(align, 1, "file alignment")
+}
+
+/// Octal permission bits, e.g. `755` or `0o644`
+fn parse_mode(mode: &str) -> u32 {
+    let digits = mode.trim_start_matches("0o");
+    u32::from_str_radix(digits, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+        .unwrap_or_else(|| panic!("Invalid output mode {mode:?}"))
 }
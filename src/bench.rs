@@ -0,0 +1,75 @@
+//! Stages of the link exposed separately for the benchmarks in `benches/`.
+//! Not a stable interface.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::{
+    config::Config, extract_section_names, name_resolution, open_files::InputCache, read_inputs,
+    relocation, resolve_globals, section, section::LinkedProgram, GlobalLocation,
+};
+
+/// Inputs read, before symbol resolution
+pub struct Inputs {
+    config: Config,
+    inputs: InputCache,
+}
+
+impl Inputs {
+    pub fn read(config: &Config, paths: &[PathBuf]) -> anyhow::Result<Self> {
+        let (config, inputs) = read_inputs(config, paths)?;
+        Ok(Inputs { config, inputs })
+    }
+
+    /// Number of global symbols defined by the inputs
+    pub fn extract_globals(&self) -> anyhow::Result<usize> {
+        Ok(name_resolution::extract_globals(&self.config, &self.inputs)?.len())
+    }
+
+    /// Lay out the inputs without relocating them
+    pub fn lay_out(self) -> anyhow::Result<Layout> {
+        let section_names = extract_section_names(&self.inputs)?;
+        let mut globals = resolve_globals(&self.config, &self.inputs)?;
+        let linked = section::lay_out(
+            &self.config,
+            &self.inputs,
+            &section_names,
+            &[],
+            &mut globals,
+        )?;
+        Ok(Layout {
+            config: self.config,
+            inputs: self.inputs,
+            linked,
+            globals,
+        })
+    }
+}
+
+/// Inputs laid out, before relocation
+pub struct Layout {
+    config: Config,
+    inputs: InputCache,
+    linked: LinkedProgram,
+    globals: HashMap<String, GlobalLocation>,
+}
+
+impl Layout {
+    /// Walk every chunk with its final address, returning the number of chunks
+    pub fn chunk_positions(&self) -> usize {
+        self.linked.iter_with_positions(&self.config).count()
+    }
+
+    /// Copy of the laid out program, to be relocated
+    pub fn program(&self) -> Program {
+        Program(self.linked.clone())
+    }
+
+    /// Patch a copy of the laid out program
+    pub fn apply_relocations(&self, program: &mut Program) -> anyhow::Result<()> {
+        relocation::apply_relocations(&self.config, &self.inputs, &mut program.0, &self.globals)
+    }
+}
+
+/// Laid out program, see [`Layout::program`]
+pub struct Program(LinkedProgram);
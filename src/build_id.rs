@@ -0,0 +1,205 @@
+//! `.note.gnu.build-id`, a digest of the output identifying it for symbol
+//! servers and crash reporters (`--build-id`)
+//!
+//! The note is laid out with a zeroed digest, which is filled in once the
+//! loadable contents are final. The digest covers those contents, not the headers.
+
+use crate::{
+    config::{BuildId, Config},
+    endian::ToBytes,
+    open_files::InputCache,
+    permissions::Permissions,
+    section::{ChunkSource, LinkedProgram, Section, SectionChunk, BUILD_ID_SECTION_INDEX},
+};
+
+pub const BUILD_ID: &str = ".note.gnu.build-id";
+
+const NT_GNU_BUILD_ID: u32 = 3;
+const NOTE_NAME: &[u8; 4] = b"GNU\0";
+/// Name size, descriptor size and type, then the name
+const HEADER_SIZE: usize = 12 + NOTE_NAME.len();
+
+impl BuildId {
+    fn digest_size(self) -> usize {
+        match self {
+            BuildId::None => 0,
+            BuildId::Md5 => 16,
+            BuildId::Sha1 => 20,
+        }
+    }
+}
+
+/// Add the note with a zeroed digest, unless disabled
+pub fn build(config: &Config, sections: &mut Vec<Section>) {
+    if config.build_id == BuildId::None {
+        return;
+    }
+    let Some(input) = sections
+        .iter()
+        .find_map(|section| section.chunks.first())
+        .map(|chunk| chunk.input)
+    else {
+        return;
+    };
+    let permissions = Permissions {
+        read: true,
+        write: false,
+        execute: false,
+    };
+    sections.push(Section {
+        name: BUILD_ID.to_owned(),
+        chunks: vec![SectionChunk::generated(
+            input,
+            BUILD_ID_SECTION_INDEX,
+            4,
+            permissions,
+            vec![0; HEADER_SIZE + config.build_id.digest_size()],
+        )],
+        address: None,
+    });
+}
+
+/// Digest the final contents of the loadable sections, with the digest
+/// itself still zeroed, and write the note
+pub fn fill(
+    config: &Config,
+    inputs: &InputCache,
+    linked: &mut LinkedProgram,
+) -> anyhow::Result<()> {
+    if config.build_id == BuildId::None {
+        return Ok(());
+    }
+
+    let mut contents = Vec::new();
+    for it in linked.iter_with_positions(config) {
+        if !it.chunk.is_nobits() {
+            it.chunk.write_finalized(inputs, &mut contents)?;
+        }
+    }
+    let digest = match config.build_id {
+        BuildId::None => unreachable!(),
+        BuildId::Md5 => md5(&contents).to_vec(),
+        BuildId::Sha1 => sha1(&contents).to_vec(),
+    };
+
+    let endian = config.endian;
+    let mut note = Vec::with_capacity(HEADER_SIZE + digest.len());
+    note.extend((NOTE_NAME.len() as u32).to_bytes(endian));
+    note.extend((digest.len() as u32).to_bytes(endian));
+    note.extend(NT_GNU_BUILD_ID.to_bytes(endian));
+    note.extend(NOTE_NAME);
+    note.extend(digest);
+
+    let chunk = linked
+        .segments
+        .iter_mut()
+        .flat_map(|segment| segment.sections.iter_mut())
+        .flat_map(|section| section.chunks.iter_mut())
+        .find(|chunk| chunk.section_index == BUILD_ID_SECTION_INDEX)
+        .expect("Build ID note missing");
+    assert_eq!(chunk.size(), note.len() as u64, "Build ID size changed");
+    chunk.source = ChunkSource::Generated(note);
+    Ok(())
+}
+
+/// Message padded to whole 64-byte blocks, with its bit length at the end
+fn padded(message: &[u8], big_endian_length: bool) -> Vec<u8> {
+    let bits = (message.len() as u64).wrapping_mul(8);
+    let mut result = message.to_vec();
+    result.push(0x80);
+    while result.len() % 64 != 56 {
+        result.push(0);
+    }
+    if big_endian_length {
+        result.extend(bits.to_be_bytes());
+    } else {
+        result.extend(bits.to_le_bytes());
+    }
+    result
+}
+
+/// SHA-1, see RFC 3174
+fn sha1(message: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    for block in padded(message, true).chunks_exact(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+
+    let mut result = [0; 20];
+    for (out, h) in result.chunks_exact_mut(4).zip(h) {
+        out.copy_from_slice(&h.to_be_bytes());
+    }
+    result
+}
+
+/// MD5, see RFC 1321
+fn md5(message: &[u8]) -> [u8; 16] {
+    const SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+    // Integer parts of the sines of the integers 1 to 64, scaled by 2^32
+    let k: Vec<u32> = (1..=64)
+        .map(|i: i32| ((i as f64).sin().abs() * 4294967296.0) as u32)
+        .collect();
+
+    let mut h: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+    for block in padded(message, false).chunks_exact(64) {
+        let mut m = [0u32; 16];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            m[i] = u32::from_le_bytes(word.try_into().unwrap());
+        }
+
+        let [mut a, mut b, mut c, mut d] = h;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let shift = SHIFTS[(i / 16) * 4 + i % 4];
+            let f = f.wrapping_add(a).wrapping_add(k[i]).wrapping_add(m[g]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(f.rotate_left(shift));
+        }
+        for (h, v) in h.iter_mut().zip([a, b, c, d]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+
+    let mut result = [0; 16];
+    for (out, h) in result.chunks_exact_mut(4).zip(h) {
+        out.copy_from_slice(&h.to_le_bytes());
+    }
+    result
+}
@@ -0,0 +1,130 @@
+//! Common symbols (`SHN_COMMON`), tentative definitions with a size and
+//! alignment but no section
+//!
+//! Each is allocated space in `.bss`, with the largest size and alignment seen
+//! for its name. A real definition in another input overrides them.
+
+use std::collections::HashMap;
+
+use goblin::elf64::section_header::SHN_COMMON;
+
+use crate::{
+    config::Config,
+    math::align_up,
+    open_files::{InputCache, InputId},
+    permissions::Permissions,
+    section::{ChunkSource, LinkedProgram, Section, SectionChunk, COMMON_SECTION_INDEX},
+    GlobalLocation,
+};
+
+const BSS: &str = ".bss";
+
+#[derive(Debug, Clone, Default)]
+pub struct Commons {
+    /// Offset in the chunk of each common symbol, by (input, symbol table index)
+    offsets: HashMap<(InputId, u32), u64>,
+    /// Input the generated chunk is attributed to
+    input: Option<InputId>,
+}
+
+impl Commons {
+    /// Final virtual address of a common symbol
+    pub fn address(
+        &self,
+        config: &Config,
+        linked: &LinkedProgram,
+        input: InputId,
+        symtab_index: u32,
+    ) -> Option<u64> {
+        let offset = *self.offsets.get(&(input, symtab_index))?;
+        let start = linked.input_section_start(self.input?, COMMON_SECTION_INDEX)?;
+        Some(config.base_addr + start + offset)
+    }
+}
+
+/// Whether a symbol table entry is a common symbol
+pub fn is_common(inputs: &InputCache, input: InputId, symtab_index: u32) -> bool {
+    inputs
+        .get_elf(input)
+        .syms
+        .get(symtab_index as usize)
+        .is_some_and(|sym| sym.st_shndx == SHN_COMMON as usize)
+}
+
+/// Allocate the common symbols that won name resolution, and add them
+/// as a zeroed chunk at the end of `.bss`
+pub fn build(
+    inputs: &InputCache,
+    globals: &HashMap<String, GlobalLocation>,
+    sections: &mut Vec<Section>,
+) -> Commons {
+    let mut commons = Commons::default();
+
+    // The strictest alignment requested by any input, stored in `st_value`
+    let mut alignments: HashMap<&str, u64> = HashMap::new();
+    for input in inputs.iter_ids() {
+        let elf = inputs.get_elf(input);
+        for sym in elf.syms.iter() {
+            if sym.st_shndx == SHN_COMMON as usize {
+                let name = elf.strtab.get_at(sym.st_name).unwrap_or("");
+                let alignment = alignments.entry(name).or_insert(1);
+                *alignment = (*alignment).max(sym.st_value);
+            }
+        }
+    }
+
+    let mut symbols: Vec<_> = globals
+        .iter()
+        .filter_map(|(name, glob)| match *glob {
+            GlobalLocation::Symbol {
+                input,
+                symtab_index,
+                ..
+            } if is_common(inputs, input, symtab_index) => Some((name, input, symtab_index)),
+            _ => None,
+        })
+        .collect();
+    symbols.sort();
+
+    let mut size = 0;
+    let mut chunk_alignment = 1;
+    for (name, input, symtab_index) in symbols {
+        let sym = inputs
+            .get_elf(input)
+            .syms
+            .get(symtab_index as usize)
+            .unwrap();
+        let alignment = alignments[name.as_str()];
+        size = align_up(size, alignment);
+        commons.offsets.insert((input, symtab_index), size);
+        commons.input.get_or_insert(input);
+        size += sym.st_size;
+        chunk_alignment = chunk_alignment.max(alignment);
+    }
+
+    let Some(input) = commons.input else {
+        return commons;
+    };
+    let permissions = Permissions {
+        read: true,
+        write: true,
+        execute: false,
+    };
+    let mut chunk = SectionChunk::generated(
+        input,
+        COMMON_SECTION_INDEX,
+        chunk_alignment,
+        permissions,
+        Vec::new(),
+    );
+    chunk.source = ChunkSource::Zeroed(size);
+    match sections.iter_mut().find(|s| s.name == BSS) {
+        Some(bss) => bss.chunks.push(chunk),
+        None => sections.push(Section {
+            name: BSS.to_owned(),
+            chunks: vec![chunk],
+            address: None,
+        }),
+    }
+    commons
+}
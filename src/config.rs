@@ -1,9 +1,282 @@
+use std::path::PathBuf;
+
+use crate::{
+    args::Args,
+    endian::Endian,
+    linker_script::{self, LinkerScript, OutputSection},
+    math::parse_int,
+    target::Target,
+    DEFAULT_BASE_ADDR, ENTRYPOINT,
+};
+
+/// Default for both the page size and the file alignment of segments
+const DEFAULT_PAGE_SIZE: u64 = 0x1000;
+
+/// Where execution starts
+#[derive(Debug, Clone)]
+pub enum Entry {
+    /// Address of a global symbol
+    Symbol(String),
+    /// Fixed virtual address
+    Address(u64),
+}
+
+impl Entry {
+    /// Symbol name, or a hex (`0x`) or decimal address
+    pub fn parse(value: &str) -> anyhow::Result<Self> {
+        if !value.starts_with(|c: char| c.is_ascii_digit()) {
+            return Ok(Entry::Symbol(value.to_owned()));
+        }
+        let address = parse_int(value);
+        address
+            .map(Entry::Address)
+            .ok_or_else(|| anyhow::anyhow!("Invalid entry address {value:?}"))
+    }
+}
+
+/// Value of a `--defsym` symbol
+#[derive(Debug, Clone)]
+pub enum DefsymValue {
+    /// Alias of another symbol
+    Symbol(String),
+    /// Absolute address
+    Address(u64),
+}
+
+impl DefsymValue {
+    /// Symbol name, or a hex (`0x`) or decimal address
+    pub fn parse(value: &str) -> anyhow::Result<Self> {
+        if !value.starts_with(|c: char| c.is_ascii_digit()) {
+            return Ok(DefsymValue::Symbol(value.to_owned()));
+        }
+        let address = parse_int(value);
+        address
+            .map(DefsymValue::Address)
+            .ok_or_else(|| anyhow::anyhow!("Invalid --defsym value {value:?}"))
+    }
+}
+
+/// Digest of `--build-id`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildId {
+    None,
+    Md5,
+    Sha1,
+}
+
+impl BuildId {
+    /// Value of `--build-id=STYLE`, where a bare `--build-id` means SHA-1
+    pub fn parse(value: &str) -> anyhow::Result<Self> {
+        Ok(match value {
+            "none" => BuildId::None,
+            "md5" => BuildId::Md5,
+            "sha1" => BuildId::Sha1,
+            _ => anyhow::bail!("Unsupported build ID style {value:?}"),
+        })
+    }
+}
+
+/// Format of the output file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Static ELF executable, for the target and byte order of the inputs,
+    /// which must match those named by `--oformat` if given
+    Elf(Option<(Target, Endian)>),
+    /// Raw memory image without headers, for bootloaders and embedded targets
+    Binary,
+}
+
+impl OutputFormat {
+    /// Value of `--oformat`
+    pub fn parse(value: &str) -> anyhow::Result<Self> {
+        let elf = |target, endian| OutputFormat::Elf(Some((target, endian)));
+        Ok(match value {
+            "binary" => OutputFormat::Binary,
+            "elf64-x86-64" => elf(Target::X86_64, Endian::Little),
+            "elf32-i386" => elf(Target::I386, Endian::Little),
+            "elf64-littleaarch64" => elf(Target::Aarch64, Endian::Little),
+            "elf64-bigaarch64" => elf(Target::Aarch64, Endian::Big),
+            _ => anyhow::bail!("Unsupported output format {value:?}"),
+        })
+    }
+}
+
+/// Identical code folding, see `icf`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Icf {
+    None,
+    /// Only sections whose address is never taken, so function pointers stay unique
+    Safe,
+    All,
+}
+
+impl Icf {
+    /// Value of `--icf=MODE`
+    pub fn parse(value: &str) -> anyhow::Result<Self> {
+        Ok(match value {
+            "none" => Icf::None,
+            "safe" => Icf::Safe,
+            "all" => Icf::All,
+            _ => anyhow::bail!("Unsupported --icf mode {value:?}"),
+        })
+    }
+}
+
+/// Symbol information to leave out of the output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strip {
+    None,
+    /// Debug sections, which are never copied to the output anyway
+    Debug,
+    /// The symbol table, `-s`
+    All,
+}
+
 #[derive(Debug, Clone)]
 pub struct Config {
     /// Where the program should be loaded
     pub base_addr: u64,
+    /// Entry point, `_start` by default
+    pub entry: Entry,
+    pub output_format: OutputFormat,
+    /// Architecture of the inputs and the output
+    pub target: Target,
+    /// Byte order of the inputs and the output
+    pub endian: Endian,
     /// Alignment of segments in the file
     pub segment_file_align: u64,
     /// Alignment of diffrently-permissioned segments in memory
     pub page_size: u64,
+    /// Drop input sections not reachable from the entry point
+    pub gc_sections: bool,
+    /// Emit a section header table with a symbol table after the segment contents
+    pub section_headers: bool,
+    /// Symbol information to leave out of the section header table
+    pub strip: Strip,
+    /// Place read-only data in the executable segment instead of a separate one
+    pub no_rosegment: bool,
+    /// Warn about relocations that refer outside of their target symbol
+    pub check_symbol_bounds: bool,
+    /// Leave an unmapped page between consecutive segments
+    pub guard_pages: bool,
+    /// Order segments by address, instead of rejecting fixed addresses below earlier segments
+    pub sort_by_address: bool,
+    /// Emit a `PT_GNU_RELRO` header covering `.data.rel.ro` and `.dynamic`
+    pub relro: bool,
+    /// Emit `.eh_frame_hdr` with a `PT_GNU_EH_FRAME` header pointing at it
+    pub eh_frame_hdr: bool,
+    /// Stack size to request from the loader with `PT_GNU_STACK`
+    pub stack_size: Option<u64>,
+    /// Mark the stack executable in `PT_GNU_STACK`
+    pub exec_stack: bool,
+    /// Emit `.note.gnu.build-id` with a `PT_NOTE` header
+    pub build_id: BuildId,
+    /// Position-independent executable, with relative relocations for its startup code
+    pub pie: bool,
+    /// Section placement and symbol definitions from `--script`
+    pub script: Option<LinkerScript>,
+    /// Output sections placed after those of the linker script, in order.
+    /// Input sections matching none of them are placed by their permissions.
+    pub output_sections: Vec<OutputSection>,
+    /// Show demangled symbol names in diagnostics
+    pub demangle: bool,
+    /// Files of `name address` lines, defining absolute symbols
+    pub symbol_maps: Vec<PathBuf>,
+    /// Symbols defined by `--defsym`, in command line order
+    pub defsyms: Vec<(String, DefsymValue)>,
+    /// Fold identical input sections
+    pub icf: Icf,
+    /// Symbols whose sections are never folded
+    pub keep_unique: Vec<String>,
+    /// Fold read-only data too, giving up on distinct addresses for equal constants
+    pub ignore_data_address_equality: bool,
+    /// Optimization level, gating passes that trade link time for output size:
+    /// * 0: straight concatenation of input sections
+    /// * 1 (default): also merging identical strings of `SHF_MERGE` sections
+    /// * 2 and 3: also merging strings into those they are a suffix of
+    pub opt_level: u8,
+}
+
+impl Config {
+    /// Everything set by the command line, with defaults for the rest
+    pub fn from_args(args: &Args) -> anyhow::Result<Self> {
+        let config = Config {
+            base_addr: args
+                .image_base
+                .unwrap_or(if args.pie { 0 } else { DEFAULT_BASE_ADDR }),
+            entry: args
+                .entry
+                .as_deref()
+                .map(Entry::parse)
+                .transpose()?
+                .unwrap_or(Entry::Symbol(ENTRYPOINT.to_owned())),
+            output_format: args
+                .oformat
+                .as_deref()
+                .map(OutputFormat::parse)
+                .transpose()?
+                .unwrap_or(OutputFormat::Elf(None)),
+            // Known once the inputs are read
+            target: Target::X86_64,
+            endian: Endian::Little,
+            segment_file_align: args.file_align.unwrap_or(DEFAULT_PAGE_SIZE),
+            page_size: args.max_page_size.unwrap_or(DEFAULT_PAGE_SIZE),
+            gc_sections: args.gc_sections,
+            section_headers: args.section_headers,
+            strip: if args.strip_all {
+                Strip::All
+            } else if args.strip_debug {
+                Strip::Debug
+            } else {
+                Strip::None
+            },
+            no_rosegment: args.no_rosegment,
+            check_symbol_bounds: args.check_symbol_bounds,
+            guard_pages: args.guard_pages,
+            sort_by_address: args.sort_by_address,
+            relro: args.relro,
+            eh_frame_hdr: args.eh_frame_hdr,
+            stack_size: args.stack_size,
+            exec_stack: args.exec_stack,
+            pie: args.pie,
+            build_id: args
+                .build_id
+                .as_deref()
+                .map(BuildId::parse)
+                .transpose()?
+                .unwrap_or(BuildId::None),
+            script: args
+                .script
+                .as_deref()
+                .map(linker_script::read)
+                .transpose()?,
+            output_sections: linker_script::default_output_sections(),
+            demangle: args.demangle,
+            symbol_maps: args.symbol_maps.clone(),
+            defsyms: args
+                .defsyms
+                .iter()
+                .map(|(name, value)| Ok((name.clone(), DefsymValue::parse(value)?)))
+                .collect::<anyhow::Result<_>>()?,
+            icf: args
+                .icf
+                .as_deref()
+                .map(Icf::parse)
+                .transpose()?
+                .unwrap_or(Icf::None),
+            keep_unique: args.keep_unique.clone(),
+            ignore_data_address_equality: args.ignore_data_address_equality,
+            opt_level: args.opt_level,
+        };
+
+        for (what, align) in [
+            ("page size", config.page_size),
+            ("segment file alignment", config.segment_file_align),
+        ] {
+            if !align.is_power_of_two() {
+                anyhow::bail!("The {what} {align:#x} is not a power of two");
+            }
+        }
+        Ok(config)
+    }
 }
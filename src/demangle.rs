@@ -0,0 +1,30 @@
+//! Demangling of symbol names for diagnostics (`--demangle`)
+//!
+//! Rust symbols, both legacy and v0 (`_R...`), and Itanium C++ ones are
+//! understood. Anything else is displayed as-is.
+
+use crate::config::Config;
+
+/// Name to display for a symbol. Matching must always use the raw name.
+pub fn display(config: &Config, name: &str) -> String {
+    if config.demangle {
+        if let Some(demangled) = demangle(name) {
+            return demangled;
+        }
+    }
+    name.to_owned()
+}
+
+fn demangle(name: &str) -> Option<String> {
+    // Without the crate hash, which the alternate form leaves out
+    if let Ok(demangled) = rustc_demangle::try_demangle(name) {
+        return Some(format!("{demangled:#}"));
+    }
+    // Mach-O style names have an extra leading underscore
+    let name = name
+        .strip_prefix('_')
+        .filter(|n| n.starts_with("_Z"))
+        .unwrap_or(name);
+    let symbol = cpp_demangle::Symbol::new(name).ok()?;
+    symbol.demangle(&Default::default()).ok()
+}
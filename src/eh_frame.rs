@@ -0,0 +1,311 @@
+//! `.eh_frame_hdr`, the binary search table unwinders use to find the
+//! frame description entry (FDE) of an address (`--eh-frame-hdr`)
+//!
+//! The table is sized before layout, since the number of FDEs doesn't depend
+//! on relocations, and filled once `.eh_frame` has its final contents.
+
+use std::ops::Range;
+
+use anyhow::bail;
+
+use crate::{
+    config::Config,
+    endian::{Endian, ToBytes},
+    open_files::InputCache,
+    permissions::Permissions,
+    section::{ChunkSource, LinkedProgram, Section, SectionChunk, EH_FRAME_HDR_SECTION_INDEX},
+};
+
+pub const EH_FRAME: &str = ".eh_frame";
+pub const EH_FRAME_HDR: &str = ".eh_frame_hdr";
+
+/// Version, then the encodings of `eh_frame_ptr`, `fde_count` and the table
+const HEADER: [u8; 4] = [
+    1,
+    DW_EH_PE_PCREL | DW_EH_PE_SDATA4,
+    DW_EH_PE_UDATA4,
+    DW_EH_PE_DATAREL | DW_EH_PE_SDATA4,
+];
+/// Size of the fixed part, before the table
+const HEADER_SIZE: usize = 12;
+/// Size of a table entry, as (initial location, FDE address)
+const ENTRY_SIZE: usize = 8;
+
+// Pointer encodings, see the LSB "DWARF Extensions" chapter
+const DW_EH_PE_ABSPTR: u8 = 0x00;
+const DW_EH_PE_UDATA2: u8 = 0x02;
+const DW_EH_PE_UDATA4: u8 = 0x03;
+const DW_EH_PE_UDATA8: u8 = 0x04;
+const DW_EH_PE_SDATA2: u8 = 0x0a;
+const DW_EH_PE_SDATA4: u8 = 0x0b;
+const DW_EH_PE_SDATA8: u8 = 0x0c;
+const DW_EH_PE_PCREL: u8 = 0x10;
+const DW_EH_PE_DATAREL: u8 = 0x30;
+
+/// Common information entry (CIE) or FDE in an `.eh_frame` chunk
+struct Record {
+    /// Offset of the length field
+    offset: usize,
+    /// Zero for CIEs. For FDEs, the distance from this field back to the CIE.
+    cie_pointer: u32,
+    /// Contents after the CIE pointer
+    body: Range<usize>,
+}
+
+fn read_uint(bytes: &[u8], endian: Endian) -> u64 {
+    let fold = |acc: u64, b: &u8| acc << 8 | *b as u64;
+    match endian {
+        Endian::Little => bytes.iter().rev().fold(0, fold),
+        Endian::Big => bytes.iter().fold(0, fold),
+    }
+}
+
+fn records(bytes: &[u8], endian: Endian) -> anyhow::Result<Vec<Record>> {
+    let mut result = Vec::new();
+    let mut offset = 0;
+    while offset + 4 <= bytes.len() {
+        let length = read_uint(&bytes[offset..offset + 4], endian) as usize;
+        // Zero terminator, e.g. from `crtend.o`
+        if length == 0 {
+            break;
+        }
+        if length == 0xffff_ffff {
+            bail!("64-bit DWARF records in {EH_FRAME} are not supported");
+        }
+        let end = offset + 4 + length;
+        if length < 4 || end > bytes.len() {
+            bail!("Truncated {EH_FRAME} record at offset {offset:#x}");
+        }
+        result.push(Record {
+            offset,
+            cie_pointer: read_uint(&bytes[offset + 4..offset + 8], endian) as u32,
+            body: offset + 8..end,
+        });
+        offset = end;
+    }
+    Ok(result)
+}
+
+fn read_uleb128(bytes: &[u8], pos: &mut usize) -> anyhow::Result<u64> {
+    let mut result = 0;
+    let mut shift = 0;
+    loop {
+        let Some(&byte) = bytes.get(*pos) else {
+            bail!("Truncated LEB128 value in {EH_FRAME}");
+        };
+        *pos += 1;
+        if shift < 64 {
+            result |= ((byte & 0x7f) as u64) << shift;
+        }
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(result);
+        }
+    }
+}
+
+/// Size of a pointer with the encoding, and whether it's signed
+fn encoded_size(encoding: u8, is_64: bool) -> anyhow::Result<(usize, bool)> {
+    Ok(match encoding & 0x0f {
+        DW_EH_PE_ABSPTR => (if is_64 { 8 } else { 4 }, false),
+        DW_EH_PE_UDATA2 => (2, false),
+        DW_EH_PE_UDATA4 => (4, false),
+        DW_EH_PE_UDATA8 => (8, false),
+        DW_EH_PE_SDATA2 => (2, true),
+        DW_EH_PE_SDATA4 => (4, true),
+        DW_EH_PE_SDATA8 => (8, true),
+        _ => bail!("Unsupported pointer encoding {encoding:#x} in {EH_FRAME}"),
+    })
+}
+
+/// Decode a pointer at `pos`, whose final address is `field_address`
+fn read_encoded(
+    bytes: &[u8],
+    pos: usize,
+    encoding: u8,
+    endian: Endian,
+    is_64: bool,
+    field_address: u64,
+) -> anyhow::Result<u64> {
+    let (size, signed) = encoded_size(encoding, is_64)?;
+    let Some(field) = bytes.get(pos..pos + size) else {
+        bail!("Truncated pointer in {EH_FRAME}");
+    };
+    let mut value = read_uint(field, endian);
+    if signed && size < 8 {
+        let shift = 64 - 8 * size as u32;
+        value = (((value << shift) as i64) >> shift) as u64;
+    }
+    match encoding & 0x70 {
+        0 => Ok(value),
+        DW_EH_PE_PCREL => Ok(field_address.wrapping_add(value)),
+        _ => bail!("Unsupported pointer encoding {encoding:#x} in {EH_FRAME}"),
+    }
+}
+
+/// Encoding of the initial locations in the FDEs of a CIE, from its `R` augmentation
+fn fde_encoding(bytes: &[u8], cie: &Record, is_64: bool) -> anyhow::Result<u8> {
+    let mut pos = cie.body.start;
+    let version = bytes[pos];
+    pos += 1;
+    let augmentation_end = bytes[pos..cie.body.end]
+        .iter()
+        .position(|b| *b == 0)
+        .map(|len| pos + len)
+        .ok_or_else(|| anyhow::anyhow!("Unterminated CIE augmentation in {EH_FRAME}"))?;
+    let augmentation = &bytes[pos..augmentation_end];
+    pos = augmentation_end + 1;
+    if augmentation.first() != Some(&b'z') {
+        if !augmentation.is_empty() {
+            bail!("Unsupported CIE augmentation {augmentation:?} in {EH_FRAME}");
+        }
+        return Ok(DW_EH_PE_ABSPTR);
+    }
+
+    // Code alignment, data alignment and the return address register
+    read_uleb128(bytes, &mut pos)?;
+    read_uleb128(bytes, &mut pos)?;
+    if version == 1 {
+        pos += 1;
+    } else {
+        read_uleb128(bytes, &mut pos)?;
+    }
+    read_uleb128(bytes, &mut pos)?;
+
+    for c in &augmentation[1..] {
+        let Some(&encoding) = bytes.get(pos) else {
+            bail!("Truncated CIE augmentation data in {EH_FRAME}");
+        };
+        match c {
+            b'R' => return Ok(encoding),
+            // Personality routine pointer
+            b'P' => pos += 1 + encoded_size(encoding, is_64)?.0,
+            // LSDA encoding
+            b'L' => pos += 1,
+            // Signal frame, and AArch64 pointer authentication
+            b'S' | b'B' => {}
+            _ => bail!("Unsupported CIE augmentation {augmentation:?} in {EH_FRAME}"),
+        }
+    }
+    Ok(DW_EH_PE_ABSPTR)
+}
+
+/// Add an empty `.eh_frame_hdr` section sized for the FDEs in `.eh_frame`,
+/// if there are any
+pub fn build(
+    config: &Config,
+    inputs: &InputCache,
+    sections: &mut Vec<Section>,
+) -> anyhow::Result<()> {
+    let Some(eh_frame) = sections.iter().find(|s| s.name == EH_FRAME) else {
+        return Ok(());
+    };
+
+    let mut fde_count = 0;
+    for chunk in &eh_frame.chunks {
+        let bytes = chunk.unpatched_bytes(inputs);
+        let records = records(bytes, config.endian)
+            .map_err(|err| anyhow::anyhow!("{}: {err}", inputs.description(chunk.input)))?;
+        fde_count += records.iter().filter(|r| r.cie_pointer != 0).count();
+    }
+
+    let permissions = Permissions {
+        read: true,
+        write: false,
+        execute: false,
+    };
+    sections.push(Section {
+        name: EH_FRAME_HDR.to_owned(),
+        chunks: vec![SectionChunk::generated(
+            eh_frame.chunks[0].input,
+            EH_FRAME_HDR_SECTION_INDEX,
+            4,
+            permissions,
+            vec![0; HEADER_SIZE + fde_count * ENTRY_SIZE],
+        )],
+        address: None,
+    });
+    Ok(())
+}
+
+/// Fill the generated `.eh_frame_hdr` chunk from the relocated `.eh_frame`,
+/// with the table sorted by initial location
+pub fn fill(
+    config: &Config,
+    inputs: &InputCache,
+    linked: &mut LinkedProgram,
+) -> anyhow::Result<()> {
+    let endian = config.endian;
+    let is_64 = config.target.is_64();
+
+    let mut hdr_address = None;
+    let mut eh_frame_address = None;
+    // (initial location, FDE address)
+    let mut table: Vec<(u64, u64)> = Vec::new();
+    for it in linked.iter_with_positions(config) {
+        if it.chunk.section_index == EH_FRAME_HDR_SECTION_INDEX {
+            hdr_address = Some(config.base_addr + it.chunk_start);
+        }
+        if it.section.name != EH_FRAME {
+            continue;
+        }
+        let start = config.base_addr + it.chunk_start;
+        eh_frame_address.get_or_insert(start);
+
+        let mut bytes = Vec::new();
+        it.chunk.write_finalized(inputs, &mut bytes)?;
+        let context =
+            |err: anyhow::Error| anyhow::anyhow!("{}: {err}", inputs.description(it.chunk.input));
+        let records = records(&bytes, endian).map_err(context)?;
+        for fde in records.iter().filter(|r| r.cie_pointer != 0) {
+            let cie = (fde.offset + 4)
+                .checked_sub(fde.cie_pointer as usize)
+                .and_then(|offset| {
+                    records
+                        .iter()
+                        .find(|r| r.offset == offset && r.cie_pointer == 0)
+                })
+                .ok_or_else(|| anyhow::anyhow!("FDE at {:#x} has no CIE", fde.offset))
+                .map_err(context)?;
+            let encoding = fde_encoding(&bytes, cie, is_64).map_err(context)?;
+            let pos = fde.body.start;
+            let initial_location =
+                read_encoded(&bytes, pos, encoding, endian, is_64, start + pos as u64)
+                    .map_err(context)?;
+            table.push((initial_location, start + fde.offset as u64));
+        }
+    }
+
+    let (Some(hdr_address), Some(eh_frame_address)) = (hdr_address, eh_frame_address) else {
+        return Ok(());
+    };
+    table.sort();
+
+    let relative = |address: u64, to: u64| -> anyhow::Result<i32> {
+        let offset = address as i128 - to as i128;
+        offset.try_into().map_err(|_| {
+            anyhow::anyhow!(
+                "{EH_FRAME} at {address:#x} is out of range of {EH_FRAME_HDR} at {hdr_address:#x}"
+            )
+        })
+    };
+    let mut contents = HEADER.to_vec();
+    contents.extend(relative(eh_frame_address, hdr_address + 4)?.to_bytes(endian));
+    contents.extend((table.len() as u32).to_bytes(endian));
+    for (initial_location, fde) in &table {
+        contents.extend(relative(*initial_location, hdr_address)?.to_bytes(endian));
+        contents.extend(relative(*fde, hdr_address)?.to_bytes(endian));
+    }
+
+    let chunk = linked
+        .segments
+        .iter_mut()
+        .flat_map(|segment| segment.sections.iter_mut())
+        .flat_map(|section| section.chunks.iter_mut())
+        .find(|chunk| chunk.section_index == EH_FRAME_HDR_SECTION_INDEX);
+    if let Some(chunk) = chunk {
+        assert_eq!(chunk.size(), contents.len() as u64, "FDE count changed");
+        chunk.source = ChunkSource::Generated(contents);
+    }
+    Ok(())
+}
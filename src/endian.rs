@@ -0,0 +1,40 @@
+//! Byte order of the output, following the inputs
+
+pub use goblin::container::Endian;
+
+/// Integers serialized in either byte order
+pub trait ToBytes {
+    fn to_bytes(self, endian: Endian) -> Vec<u8>;
+}
+
+macro_rules! impl_to_bytes {
+    ($($t:ty),*) => {
+        $(
+            impl ToBytes for $t {
+                fn to_bytes(self, endian: Endian) -> Vec<u8> {
+                    match endian {
+                        Endian::Little => self.to_le_bytes().to_vec(),
+                        Endian::Big => self.to_be_bytes().to_vec(),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_to_bytes!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+/// Read a value of up to 64 bits, e.g. an implicit addend
+pub fn read_int(bytes: &[u8], signed: bool, endian: Endian) -> i64 {
+    let fold = |acc: u64, b: &u8| acc << 8 | *b as u64;
+    let value = match endian {
+        Endian::Little => bytes.iter().rev().fold(0, fold),
+        Endian::Big => bytes.iter().fold(0, fold),
+    };
+    let shift = 64 - 8 * bytes.len() as u32;
+    if signed && shift < 64 {
+        ((value << shift) as i64) >> shift
+    } else {
+        value as i64
+    }
+}
@@ -0,0 +1,167 @@
+//! Garbage collection of unreferenced input sections (`--gc-sections`)
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::bail;
+use goblin::{elf::Elf, elf64::section_header::SHN_COMMON};
+
+use crate::{
+    config::{Config, Entry},
+    open_files::{InputCache, InputId},
+    relocation::RelativeTo,
+    section::{Section, INIT_FINI_ARRAYS},
+    write_elf64, GlobalLocation,
+};
+
+/// Input section, identified by its origin file and section header index
+type InputSection = (InputId, u32);
+
+/// Find the section defining a symbol, as seen from a relocation in `input`
+pub fn symbol_section(
+    inputs: &InputCache,
+    globals: &HashMap<String, GlobalLocation>,
+    input: InputId,
+    name: &str,
+) -> Option<InputSection> {
+    let elf = inputs.get_elf(input);
+    if let Some(section) = defined_in(elf, name) {
+        return Some((input, section));
+    }
+
+    match *globals.get(name)? {
+        GlobalLocation::Symbol {
+            input,
+            symtab_index,
+            ..
+        } => {
+            let sym = inputs.get_elf(input).syms.get(symtab_index as usize)?;
+            Some((input, sym.st_shndx.try_into().ok()?))
+        }
+        GlobalLocation::Absolute(_) => None,
+    }
+}
+
+/// Section index of a defined symbol in the file itself.
+/// Common symbols have none, and may be overridden by another input.
+fn defined_in(elf: &Elf, name: &str) -> Option<u32> {
+    elf.syms
+        .iter()
+        .find(|sym| {
+            sym.st_shndx != 0
+                && sym.st_shndx != SHN_COMMON as usize
+                && elf.strtab.get_at(sym.st_name) == Some(name)
+        })
+        .and_then(|sym| sym.st_shndx.try_into().ok())
+}
+
+fn input_section_name(inputs: &InputCache, input: InputId, index: u32) -> &str {
+    let elf = inputs.get_elf(input);
+    elf.section_headers
+        .get(index as usize)
+        .and_then(|sh| elf.shdr_strtab.get_at(sh.sh_name))
+        .unwrap_or("")
+}
+
+/// Drop all chunks not reachable from the entry point or `KEEP(...)` via relocations.
+/// Sections left without any chunks are removed as well.
+pub fn collect_garbage(
+    config: &Config,
+    inputs: &InputCache,
+    globals: &HashMap<String, GlobalLocation>,
+    sections: &mut Vec<Section>,
+) -> anyhow::Result<()> {
+    // The entry point is always a root, even if nothing references it
+    let Entry::Symbol(entry_name) = &config.entry else {
+        bail!("--gc-sections needs an entry symbol to start from, not a fixed address");
+    };
+    let Some(&GlobalLocation::Symbol {
+        input: entry_input,
+        symtab_index,
+        ..
+    }) = globals.get(entry_name)
+    else {
+        bail!(
+            "Entry symbol {entry_name:?} is undefined, so --gc-sections has no root to start from"
+        );
+    };
+    let entry_sym = inputs
+        .get_elf(entry_input)
+        .syms
+        .get(symtab_index as usize)
+        .expect("Missing symbol");
+    let entry_section: InputSection = (entry_input, entry_sym.st_shndx.try_into()?);
+
+    let chunks: HashMap<InputSection, _> = sections
+        .iter()
+        .flat_map(|s| s.chunks.iter())
+        .map(|c| ((c.input, c.section_index), c))
+        .collect();
+
+    if !chunks.contains_key(&entry_section) {
+        bail!(
+            "Entry symbol {entry_name:?} in {} is not in any loadable section",
+            inputs.description(entry_input)
+        );
+    }
+
+    let mut live: HashSet<InputSection> = HashSet::new();
+    let mut queue = vec![entry_section];
+    // Constructors are only called through the arrays, never referenced,
+    // and notes are only read by the loader and other tools
+    queue.extend(
+        sections
+            .iter()
+            .filter(|s| {
+                INIT_FINI_ARRAYS.contains(&s.name.as_str()) || write_elf64::is_note_section(&s.name)
+            })
+            .flat_map(|s| s.chunks.iter())
+            .map(|c| (c.input, c.section_index)),
+    );
+    // Sections matching `KEEP(...)` in the linker script
+    if let Some(script) = &config.script {
+        queue.extend(
+            chunks
+                .keys()
+                .filter(|&&(input, index)| script.keeps(input_section_name(inputs, input, index)))
+                .copied(),
+        );
+    }
+    while let Some(current) = queue.pop() {
+        if !live.insert(current) {
+            continue;
+        }
+
+        let Some(chunk) = chunks.get(&current) else {
+            continue;
+        };
+
+        for reloc in &chunk.relocations {
+            let target = match &reloc.relative_to {
+                RelativeTo::Section { index } => match inputs.kept_section(chunk.input, *index) {
+                    Some((input, index)) => (input, index.try_into()?),
+                    // Reported by the relocation pass
+                    None => continue,
+                },
+                RelativeTo::Symbol(name) => {
+                    // Unresolved names are reported by the relocation pass
+                    match symbol_section(inputs, globals, chunk.input, name) {
+                        Some(target) => target,
+                        None => continue,
+                    }
+                }
+            };
+            if !live.contains(&target) {
+                queue.push(target);
+            }
+        }
+    }
+
+    for section in sections.iter_mut() {
+        section
+            .chunks
+            .retain(|c| live.contains(&(c.input, c.section_index)));
+    }
+    sections.retain(|s| !s.chunks.is_empty());
+
+    Ok(())
+}
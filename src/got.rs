@@ -0,0 +1,144 @@
+//! Global offset table, synthesized for GOT-relative relocations
+//!
+//! Static linking knows all addresses, so the table is simply filled with the
+//! final addresses of the referenced symbols during relocation. Loads of symbols
+//! defined in the output are relaxed to `lea` instead, like GNU ld does.
+
+use std::collections::HashMap;
+
+use goblin::elf64::section_header::{SHN_ABS, SHN_UNDEF};
+
+use crate::{
+    config::Config,
+    endian::{Endian, ToBytes},
+    name_resolution::defining_symbol,
+    open_files::{InputCache, InputId},
+    permissions::Permissions,
+    relocation::{RelativeTo, LEA_OPCODE},
+    section::{ChunkSource, LinkedProgram, Section, SectionChunk, GOT_SECTION_INDEX},
+    target::Target,
+    GlobalLocation,
+};
+
+const SLOT_SIZE: u64 = 8;
+
+#[derive(Debug, Clone, Default)]
+pub struct Got {
+    /// Symbol of each slot, with the first input referencing it through the GOT
+    slots: Vec<(InputId, String)>,
+    /// Slot index of each symbol
+    index: HashMap<String, usize>,
+}
+
+impl Got {
+    pub fn slots(&self) -> impl Iterator<Item = (InputId, &str)> + '_ {
+        self.slots
+            .iter()
+            .map(|(input, name)| (*input, name.as_str()))
+    }
+
+    /// Final virtual address of the slot for a symbol
+    pub fn slot_address(&self, config: &Config, linked: &LinkedProgram, name: &str) -> Option<u64> {
+        let slot = *self.index.get(name)?;
+        // The generated chunk is attributed to the first slot's input
+        let (input, _) = *self.slots.first()?;
+        let start = linked.input_section_start(input, GOT_SECTION_INDEX)?;
+        Some(config.base_addr + start + slot as u64 * SLOT_SIZE)
+    }
+}
+
+/// Rewrite relaxable GOT loads into `lea` when the symbol is defined in a section
+/// of the output. Absolute symbols keep their slot, as they may be out of range.
+fn relax_got_loads(
+    inputs: &InputCache,
+    globals: &HashMap<String, GlobalLocation>,
+    chunk: &mut SectionChunk,
+) {
+    let bytes = chunk.unpatched_bytes(inputs);
+    let relaxable: Vec<(usize, usize)> = chunk
+        .relocations
+        .iter()
+        .enumerate()
+        .filter_map(|(i, reloc)| {
+            let opcode = reloc.relaxable_got_load(bytes)?;
+            let RelativeTo::Symbol(name) = &reloc.relative_to else {
+                return None;
+            };
+            let (input, symtab_index) = defining_symbol(inputs, globals, chunk.input, name)?;
+            let sym = inputs.get_elf(input).syms.get(symtab_index as usize)?;
+            let shndx = sym.st_shndx as u32;
+            (shndx != SHN_UNDEF && shndx != SHN_ABS).then_some((i, opcode))
+        })
+        .collect();
+    for (i, opcode) in relaxable {
+        chunk
+            .patch(opcode, vec![LEA_OPCODE])
+            .expect("Invalid patch");
+        chunk.relocations[i].relax_to_pc32();
+    }
+}
+
+/// Allocate a slot for every symbol referenced by a GOT-relative relocation,
+/// and add the table as a `.got` section
+pub fn build(
+    config: &Config,
+    inputs: &InputCache,
+    globals: &HashMap<String, GlobalLocation>,
+    sections: &mut Vec<Section>,
+) -> anyhow::Result<Got> {
+    let mut got = Got::default();
+    for chunk in sections.iter_mut().flat_map(|s| s.chunks.iter_mut()) {
+        if config.target == Target::X86_64 && !chunk.is_nobits() {
+            relax_got_loads(inputs, globals, chunk);
+        }
+        for reloc in chunk.relocations.iter().filter(|r| r.uses_got()) {
+            let RelativeTo::Symbol(name) = &reloc.relative_to else {
+                // Assemblers always reference a symbol through the GOT
+                anyhow::bail!(
+                    "GOT-relative relocation against a section in {}",
+                    inputs.description(chunk.input)
+                );
+            };
+            if !got.index.contains_key(name) {
+                got.index.insert(name.clone(), got.slots.len());
+                got.slots.push((chunk.input, name.clone()));
+            }
+        }
+    }
+
+    if let Some(&(input, _)) = got.slots.first() {
+        let permissions = Permissions {
+            read: true,
+            write: true,
+            execute: false,
+        };
+        sections.push(Section {
+            name: ".got".to_owned(),
+            chunks: vec![SectionChunk::generated(
+                input,
+                GOT_SECTION_INDEX,
+                SLOT_SIZE,
+                permissions,
+                vec![0; got.slots.len() * SLOT_SIZE as usize],
+            )],
+            address: None,
+        });
+    }
+
+    Ok(got)
+}
+
+/// Fill the generated `.got` chunk with the final address of each slot's symbol
+pub fn fill(linked: &mut LinkedProgram, endian: Endian, addresses: &[u64]) {
+    let contents: Vec<u8> = addresses.iter().flat_map(|a| a.to_bytes(endian)).collect();
+    let chunk = linked
+        .segments
+        .iter_mut()
+        .flat_map(|segment| segment.sections.iter_mut())
+        .flat_map(|section| section.chunks.iter_mut())
+        .find(|chunk| chunk.section_index == GOT_SECTION_INDEX);
+    if let Some(chunk) = chunk {
+        assert_eq!(chunk.size(), contents.len() as u64, "GOT size changed");
+        chunk.source = ChunkSource::Generated(contents);
+    }
+}
@@ -0,0 +1,260 @@
+//! Identical code folding (`--icf=all|safe`)
+//!
+//! Input sections with the same contents and equivalent relocations are
+//! folded into the first copy. References to the folded sections are
+//! redirected to that copy through [`Folded::resolve`].
+
+use std::collections::{HashMap, HashSet};
+
+use goblin::elf::section_header::{SHN_ABS, SHN_LORESERVE};
+
+use crate::{
+    config::{Config, Icf},
+    gc,
+    name_resolution::{resolve_name, NameResolved},
+    open_files::{InputCache, InputId},
+    relocation::{RelativeTo, Relocate},
+    section::{ChunkSource, Section, SectionChunk},
+    GlobalLocation,
+};
+
+/// Input section, identified by its origin file and section header index
+type InputSection = (InputId, u32);
+
+#[derive(Debug, Clone, Default)]
+pub struct Folded {
+    /// Folded input sections, and the copy that replaced each
+    sections: HashMap<InputSection, InputSection>,
+}
+
+impl Folded {
+    /// Section that holds the contents of an input section in the output
+    pub fn resolve(&self, input: InputId, section_index: u32) -> InputSection {
+        self.sections
+            .get(&(input, section_index))
+            .copied()
+            .unwrap_or((input, section_index))
+    }
+}
+
+/// What a relocation refers to, comparable across inputs
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Target {
+    /// Offset in a section that may be folded, by its current class
+    Class(usize, u64),
+    /// Offset in a section that is never folded
+    Section(InputSection, u64),
+    /// Global symbol outside of any section, which resolves the same from every input
+    Global(String),
+    Absolute(u64),
+}
+
+fn relocation_target(
+    inputs: &InputCache,
+    globals: &HashMap<String, GlobalLocation>,
+    input: InputId,
+    reloc: &Relocate,
+    classes: &HashMap<InputSection, usize>,
+) -> Target {
+    let (section, offset) = match &reloc.relative_to {
+        RelativeTo::Section { index } => {
+            let section = inputs
+                .kept_section(input, *index)
+                .unwrap_or((input, *index));
+            ((section.0, section.1 as u32), 0)
+        }
+        RelativeTo::Symbol(name) => {
+            let elf = inputs.get_elf(input);
+            let (input, symtab_index) = match resolve_name(elf, name) {
+                Some(NameResolved::Local(symtab_index)) => (input, symtab_index),
+                _ => match globals.get(name) {
+                    Some(&GlobalLocation::Symbol {
+                        input,
+                        symtab_index,
+                        ..
+                    }) => (input, symtab_index),
+                    _ => return Target::Global(name.clone()),
+                },
+            };
+            let sym = inputs
+                .get_elf(input)
+                .syms
+                .get(symtab_index as usize)
+                .expect("Missing symbol");
+            if sym.st_shndx == SHN_ABS as usize {
+                return Target::Absolute(sym.st_value);
+            }
+            // Common symbols are told apart by their name
+            if sym.st_shndx == 0 || sym.st_shndx >= SHN_LORESERVE as usize {
+                return Target::Global(name.clone());
+            }
+            ((input, sym.st_shndx as u32), sym.st_value)
+        }
+    };
+    match classes.get(&section) {
+        Some(&class) => Target::Class(class, offset),
+        None => Target::Section(section, offset),
+    }
+}
+
+/// Sections whose address is used for something else than calling them,
+/// e.g. stored in a function pointer. `--icf=safe` keeps them unique.
+fn address_taken(
+    config: &Config,
+    inputs: &InputCache,
+    globals: &HashMap<String, GlobalLocation>,
+    sections: &[Section],
+) -> HashSet<InputSection> {
+    let mut result = HashSet::new();
+    // Unwind tables refer to every function, without taking part in its identity
+    for section in sections.iter().filter(|s| s.name != ".eh_frame") {
+        for chunk in &section.chunks {
+            let bytes = match chunk.is_nobits() {
+                true => &[][..],
+                false => chunk.unpatched_bytes(inputs),
+            };
+            for reloc in &chunk.relocations {
+                if chunk.permissions.execute && reloc.is_branch(config.target, bytes) {
+                    continue;
+                }
+                let target = match &reloc.relative_to {
+                    RelativeTo::Section { index } => Some((chunk.input, *index as u32)),
+                    RelativeTo::Symbol(name) => {
+                        gc::symbol_section(inputs, globals, chunk.input, name)
+                    }
+                };
+                result.extend(target);
+            }
+        }
+    }
+    result
+}
+
+/// Sections defining the `--keep-unique` symbols
+fn keep_unique(
+    config: &Config,
+    inputs: &InputCache,
+    globals: &HashMap<String, GlobalLocation>,
+) -> HashSet<InputSection> {
+    let mut result = HashSet::new();
+    for name in &config.keep_unique {
+        let section = match globals.get(name) {
+            Some(&GlobalLocation::Symbol {
+                input,
+                symtab_index,
+                ..
+            }) => inputs
+                .get_elf(input)
+                .syms
+                .get(symtab_index as usize)
+                .map(|sym| (input, sym.st_shndx as u32)),
+            _ => None,
+        };
+        match section {
+            Some(section) => {
+                result.insert(section);
+            }
+            None => eprintln!("warning: --keep-unique symbol {name:?} is not defined"),
+        }
+    }
+    result
+}
+
+/// Code, and with `--ignore-data-address-equality` read-only data,
+/// read from the inputs as-is
+fn is_candidate(config: &Config, section: &Section, chunk: &SectionChunk) -> bool {
+    let permissions = chunk.permissions;
+    let kind = permissions.execute || config.ignore_data_address_equality;
+    kind && !permissions.write
+        && matches!(chunk.source, ChunkSource::Input(_))
+        && chunk.address.is_none()
+        && section.name != ".entry"
+        && section.name != ".eh_frame"
+}
+
+/// Fold identical sections into their first copy, removing the others.
+/// Sections are compared optimistically: ones with identical contents are
+/// assumed equal, and split until the sections they refer to are equal too.
+pub fn fold(
+    config: &Config,
+    inputs: &InputCache,
+    globals: &HashMap<String, GlobalLocation>,
+    sections: &mut Vec<Section>,
+) -> Folded {
+    if config.icf == Icf::None {
+        return Folded::default();
+    }
+    let mut excluded = keep_unique(config, inputs, globals);
+    if config.icf == Icf::Safe {
+        excluded.extend(address_taken(config, inputs, globals, sections));
+    }
+
+    let candidates: Vec<&SectionChunk> = sections
+        .iter()
+        .flat_map(|section| section.chunks.iter().map(move |chunk| (section, chunk)))
+        .filter(|(section, chunk)| is_candidate(config, section, chunk))
+        .map(|(_, chunk)| chunk)
+        .filter(|chunk| !excluded.contains(&(chunk.input, chunk.section_index)))
+        .collect();
+
+    // Contents of each candidate, the part that never changes
+    let mut classes: HashMap<InputSection, usize> = HashMap::new();
+    let mut ids = HashMap::new();
+    for chunk in &candidates {
+        let relocations: Vec<_> = chunk.relocations.iter().map(Relocate::shape).collect();
+        let key = (
+            chunk.unpatched_bytes(inputs),
+            chunk.alignment,
+            chunk.permissions,
+            relocations,
+        );
+        let next = ids.len();
+        let class = *ids.entry(key).or_insert(next);
+        classes.insert((chunk.input, chunk.section_index), class);
+    }
+
+    // Split the classes by their relocation targets, until that changes nothing
+    loop {
+        let mut ids = HashMap::new();
+        let mut refined = HashMap::new();
+        for chunk in &candidates {
+            let key = (chunk.input, chunk.section_index);
+            let targets: Vec<Target> = chunk
+                .relocations
+                .iter()
+                .map(|reloc| relocation_target(inputs, globals, chunk.input, reloc, &classes))
+                .collect();
+            let next = ids.len();
+            let class = *ids.entry((classes[&key], targets)).or_insert(next);
+            refined.insert(key, class);
+        }
+        let count =
+            |classes: &HashMap<InputSection, usize>| classes.values().collect::<HashSet<_>>().len();
+        let done = count(&refined) == count(&classes);
+        classes = refined;
+        if done {
+            break;
+        }
+    }
+
+    // The first candidate of each class, in layout order, is kept
+    let mut kept: HashMap<usize, InputSection> = HashMap::new();
+    let mut folded = Folded::default();
+    for chunk in &candidates {
+        let key = (chunk.input, chunk.section_index);
+        let copy = *kept.entry(classes[&key]).or_insert(key);
+        if copy != key {
+            folded.sections.insert(key, copy);
+        }
+    }
+
+    for section in sections.iter_mut() {
+        section.chunks.retain(|chunk| {
+            !folded
+                .sections
+                .contains_key(&(chunk.input, chunk.section_index))
+        });
+    }
+    sections.retain(|section| !section.chunks.is_empty());
+    folded
+}
@@ -0,0 +1,257 @@
+//! Indirect functions (`STT_GNU_IFUNC`), whose address is chosen at startup by a resolver
+//!
+//! References to an IFUNC resolve to a stub in `.iplt`, which jumps through a slot
+//! in `.got.plt`. Startup code fills the slots by running the `R_X86_64_IRELATIVE`
+//! relocations in `.rela.plt`, found through `__rela_iplt_start` and `__rela_iplt_end`.
+
+use std::collections::HashMap;
+
+use goblin::elf64::{reloc::R_X86_64_IRELATIVE, sym::STT_GNU_IFUNC};
+
+use crate::{
+    config::Config,
+    endian::ToBytes,
+    name_resolution::{defining_symbol, global_address},
+    open_files::{InputCache, InputId},
+    permissions::Permissions,
+    relocation::RelativeTo,
+    section::{
+        ChunkSource, LinkedProgram, Section, SectionChunk, GOT_PLT_SECTION_INDEX,
+        IPLT_SECTION_INDEX, RELA_PLT_SECTION_INDEX,
+    },
+    target::Target,
+    GlobalLocation,
+};
+
+pub const RELA_PLT: &str = ".rela.plt";
+
+/// `jmp *slot(%rip)`, padded with `int3`
+const STUB_SIZE: u64 = 16;
+const SLOT_SIZE: u64 = 8;
+/// Size of an `Elf64_Rela`
+pub const RELA_SIZE: u64 = 24;
+
+#[derive(Debug, Clone, Default)]
+pub struct Ifuncs {
+    /// Defining symbol of each IFUNC, as (input, symbol table index)
+    symbols: Vec<(InputId, u32)>,
+    /// Stub index of each IFUNC
+    index: HashMap<(InputId, u32), usize>,
+    /// Input the generated chunks are attributed to
+    input: Option<InputId>,
+}
+
+impl Ifuncs {
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    /// Final virtual address of the stub of an IFUNC, which references to it use
+    /// instead of the resolver. `None` for other symbols.
+    pub fn stub_address(
+        &self,
+        config: &Config,
+        linked: &LinkedProgram,
+        glob: &GlobalLocation,
+    ) -> Option<u64> {
+        let GlobalLocation::Symbol {
+            input,
+            symtab_index,
+            ..
+        } = *glob
+        else {
+            return None;
+        };
+        let stub = *self.index.get(&(input, symtab_index))?;
+        let start = linked.input_section_start(self.input?, IPLT_SECTION_INDEX)?;
+        Some(config.base_addr + start + stub as u64 * STUB_SIZE)
+    }
+}
+
+/// Defining symbol of a relocation target, if it's an IFUNC
+fn ifunc_target(
+    inputs: &InputCache,
+    globals: &HashMap<String, GlobalLocation>,
+    input: InputId,
+    name: &str,
+) -> Option<(InputId, u32)> {
+    let (input, symtab_index) = defining_symbol(inputs, globals, input, name)?;
+    let sym = inputs.get_elf(input).syms.get(symtab_index as usize)?;
+    (sym.st_type() == STT_GNU_IFUNC).then_some((input, symtab_index))
+}
+
+/// Allocate a stub, a slot and an `R_X86_64_IRELATIVE` relocation for every
+/// referenced IFUNC, and add them as the `.iplt`, `.got.plt` and `.rela.plt` sections
+pub fn build(
+    config: &Config,
+    inputs: &InputCache,
+    globals: &HashMap<String, GlobalLocation>,
+    sections: &mut Vec<Section>,
+) -> anyhow::Result<Ifuncs> {
+    let mut ifuncs = Ifuncs::default();
+    for chunk in sections.iter().flat_map(|s| s.chunks.iter()) {
+        for reloc in &chunk.relocations {
+            let RelativeTo::Symbol(name) = &reloc.relative_to else {
+                continue;
+            };
+            let Some(symbol) = ifunc_target(inputs, globals, chunk.input, name) else {
+                continue;
+            };
+            if !ifuncs.index.contains_key(&symbol) {
+                ifuncs.index.insert(symbol, ifuncs.symbols.len());
+                ifuncs.symbols.push(symbol);
+                ifuncs.input.get_or_insert(chunk.input);
+            }
+        }
+    }
+
+    let Some(input) = ifuncs.input else {
+        return Ok(ifuncs);
+    };
+    if config.target != Target::X86_64 {
+        anyhow::bail!(
+            "IFUNC symbols are only supported on x86-64, referenced from {}",
+            inputs.description(input)
+        );
+    }
+
+    let count = ifuncs.symbols.len() as u64;
+    for (name, index, alignment, entry_size, write, execute) in [
+        (
+            ".iplt",
+            IPLT_SECTION_INDEX,
+            STUB_SIZE,
+            STUB_SIZE,
+            false,
+            true,
+        ),
+        (
+            ".got.plt",
+            GOT_PLT_SECTION_INDEX,
+            SLOT_SIZE,
+            SLOT_SIZE,
+            true,
+            false,
+        ),
+        (RELA_PLT, RELA_PLT_SECTION_INDEX, 8, RELA_SIZE, false, false),
+    ] {
+        let permissions = Permissions {
+            read: true,
+            write,
+            execute,
+        };
+        sections.push(Section {
+            name: name.to_owned(),
+            chunks: vec![SectionChunk::generated(
+                input,
+                index,
+                alignment,
+                permissions,
+                vec![0; (count * entry_size) as usize],
+            )],
+            address: None,
+        });
+    }
+    Ok(ifuncs)
+}
+
+/// Define `__rela_iplt_start` and `__rela_iplt_end` around `.rela.plt`, if referenced.
+/// Without IFUNCs, the range is empty.
+pub fn define_symbols(
+    config: &Config,
+    inputs: &InputCache,
+    linked: &LinkedProgram,
+    globals: &mut HashMap<String, GlobalLocation>,
+) {
+    let range = linked
+        .ifuncs
+        .input
+        .and_then(|input| linked.input_section_start(input, RELA_PLT_SECTION_INDEX))
+        .map_or(0..0, |start| {
+            start..start + linked.ifuncs.symbols.len() as u64 * RELA_SIZE
+        });
+    for (name, addr) in [
+        ("__rela_iplt_start", range.start),
+        ("__rela_iplt_end", range.end),
+    ] {
+        if inputs.is_referenced(name) {
+            globals
+                .entry(name.to_owned())
+                .or_insert(GlobalLocation::Absolute(config.base_addr + addr));
+        }
+    }
+}
+
+/// Fill the stubs, the slots and the relocations once the layout is final.
+/// The slots initially hold the resolver address.
+pub fn fill(
+    config: &Config,
+    inputs: &InputCache,
+    linked: &mut LinkedProgram,
+) -> anyhow::Result<()> {
+    let Some(input) = linked.ifuncs.input else {
+        return Ok(());
+    };
+    let endian = config.endian;
+    let start = |index| {
+        config.base_addr
+            + linked
+                .input_section_start(input, index)
+                .expect("IFUNC section missing")
+    };
+    let (iplt, got_plt) = (start(IPLT_SECTION_INDEX), start(GOT_PLT_SECTION_INDEX));
+
+    let mut stubs = Vec::new();
+    let mut slots = Vec::new();
+    let mut relocations = Vec::new();
+    for (i, &(input, symtab_index)) in linked.ifuncs.symbols.iter().enumerate() {
+        let sym = inputs
+            .get_elf(input)
+            .syms
+            .get(symtab_index as usize)
+            .unwrap();
+        let glob = GlobalLocation::Symbol {
+            input,
+            symtab_index,
+            binding: sym.st_bind(),
+        };
+        let resolver = global_address(config, inputs, linked, &glob).ok_or_else(|| {
+            anyhow::anyhow!(
+                "IFUNC resolver in {} is not in any loadable section",
+                inputs.description(input)
+            )
+        })?;
+        let stub = iplt + i as u64 * STUB_SIZE;
+        let slot = got_plt + i as u64 * SLOT_SIZE;
+
+        let offset: i32 = (slot as i128 - (stub + 6) as i128)
+            .try_into()
+            .map_err(|_| anyhow::anyhow!(".got.plt is out of range of .iplt"))?;
+        stubs.extend([0xff, 0x25]);
+        stubs.extend(offset.to_bytes(endian));
+        stubs.resize(stubs.len() + STUB_SIZE as usize - 6, 0xcc);
+
+        slots.extend(resolver.to_bytes(endian));
+
+        relocations.extend(slot.to_bytes(endian));
+        relocations.extend((R_X86_64_IRELATIVE as u64).to_bytes(endian));
+        relocations.extend(resolver.to_bytes(endian));
+    }
+
+    for (index, contents) in [
+        (IPLT_SECTION_INDEX, stubs),
+        (GOT_PLT_SECTION_INDEX, slots),
+        (RELA_PLT_SECTION_INDEX, relocations),
+    ] {
+        let chunk = linked
+            .segments
+            .iter_mut()
+            .flat_map(|segment| segment.sections.iter_mut())
+            .flat_map(|section| section.chunks.iter_mut())
+            .find(|chunk| chunk.section_index == index)
+            .expect("IFUNC section missing");
+        assert_eq!(chunk.size(), contents.len() as u64, "IFUNC count changed");
+        chunk.source = ChunkSource::Generated(contents);
+    }
+    Ok(())
+}
@@ -0,0 +1,287 @@
+#![feature(drain_filter)]
+#![deny(unused_must_use)]
+
+//! Static ELF linker, usable in-process through [`link`]
+
+mod aarch64;
+mod annotate;
+pub mod args;
+#[doc(hidden)]
+pub mod bench;
+mod build_id;
+mod common;
+pub mod config;
+mod demangle;
+mod eh_frame;
+pub mod endian;
+mod gc;
+mod got;
+mod icf;
+mod ifunc;
+pub mod linker_script;
+mod map;
+mod math;
+mod merge;
+mod name_resolution;
+mod open_files;
+mod permissions;
+mod pie;
+mod relocation;
+mod section;
+pub mod target;
+mod write_binary;
+mod write_elf32;
+mod write_elf64;
+
+use config::{Config, Entry, OutputFormat};
+use goblin::elf64::header::{ET_CORE, ET_DYN, ET_EXEC, ET_REL};
+use goblin::elf64::section_header::{
+    SHF_ALLOC, SHT_FINI_ARRAY, SHT_INIT_ARRAY, SHT_NOBITS, SHT_NOTE, SHT_PROGBITS,
+    SHT_X86_64_UNWIND,
+};
+use open_files::{InputCache, InputId};
+use section::LinkedProgram;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::PathBuf;
+
+pub const ENTRYPOINT: &str = "_start";
+pub const DEFAULT_BASE_ADDR: u64 = 0x40_0000;
+
+/// Location of a global symbol
+#[derive(Debug, Clone)]
+pub(crate) enum GlobalLocation {
+    /// Defined in the symbol table of an input, with binding like `STB_WEAK`
+    Symbol {
+        input: InputId,
+        symtab_index: u32,
+        binding: u8,
+    },
+    /// Fixed virtual address, defined by the linker itself
+    Absolute(u64),
+}
+
+/// Only relocatable objects can be linked statically. The architecture,
+/// ELF class and byte order are checked by `target::detect`.
+fn verify_inputs(inputs: &InputCache) -> anyhow::Result<()> {
+    for input in inputs.iter_ids() {
+        let e_type = inputs.get_elf(input).header.e_type;
+        let kind = match e_type {
+            ET_REL => continue,
+            ET_EXEC => "an executable",
+            ET_DYN => "a shared library",
+            ET_CORE => "a core dump",
+            _ => "not an object file",
+        };
+        anyhow::bail!(
+            "{} is {kind} (e_type {e_type}), only relocatable objects can be linked",
+            inputs.description(input)
+        );
+    }
+    Ok(())
+}
+
+/// Names of the loadable input sections. Unnamed sections have the empty name,
+/// and are placed like other sections with an unfamiliar name.
+fn extract_section_names(inputs: &InputCache) -> anyhow::Result<HashSet<String>> {
+    let mut sections = HashSet::new();
+    for input in inputs.iter_ids() {
+        let elf = inputs.get_elf(input);
+        for sh in &elf.section_headers {
+            if matches!(
+                sh.sh_type,
+                SHT_PROGBITS
+                    | SHT_NOBITS
+                    | SHT_NOTE
+                    | SHT_INIT_ARRAY
+                    | SHT_FINI_ARRAY
+                    | SHT_X86_64_UNWIND
+            ) && sh.sh_flags & SHF_ALLOC as u64 != 0
+            {
+                let Some(name) = elf.shdr_strtab.get_at(sh.sh_name) else {
+                    anyhow::bail!(
+                        "Section name offset {:#x} is out of bounds in {}",
+                        sh.sh_name,
+                        inputs.description(input)
+                    );
+                };
+                sections.insert(name.to_owned());
+            }
+        }
+    }
+    Ok(sections)
+}
+
+fn build_binary(
+    config: &Config,
+    inputs: &InputCache,
+    linked: &LinkedProgram,
+    entry_point: u64,
+    symbols: &[(String, u64, u64)],
+) -> anyhow::Result<Vec<u8>> {
+    let mut result = Vec::new();
+    match config.output_format {
+        OutputFormat::Elf(_) => {
+            write_elf64::write(config, inputs, &mut result, linked, entry_point, symbols)?
+        }
+        OutputFormat::Binary => write_binary::write(config, inputs, &mut result, linked)?,
+    }
+    Ok(result)
+}
+
+/// Warn if the image maps the null page, where null pointer dereferences
+/// would then silently succeed instead of faulting
+fn check_null_page(config: &Config, linked: &LinkedProgram) {
+    // Position-independent executables are moved by the loader
+    if config.pie {
+        return;
+    }
+    // The first segment also maps the headers, below its contents
+    let Some(lowest) = linked.load_ranges(config).first().map(|range| range.start) else {
+        return;
+    };
+    if lowest < config.page_size {
+        log::warn!(
+            "image base {:#x} maps the null page, use a nonzero base address such as {:#x}",
+            lowest,
+            DEFAULT_BASE_ADDR,
+        );
+    }
+}
+
+/// Rewrites the contents of input sections before layout, e.g. for instrumentation
+/// or obfuscation. Relocations are applied to the new contents afterwards, at their
+/// original offsets, so a transform must leave the relocated fields where they were.
+pub trait SectionTransform {
+    /// New contents for an input section going to the output section `section`,
+    /// from the input described by `input`, or `None` to keep it as is
+    fn transform(&self, section: &str, input: &str, bytes: &[u8]) -> Option<Vec<u8>>;
+}
+
+/// Inputs laid out and relocated, ready to be serialized
+pub struct Link {
+    /// With the target and byte order detected from the inputs
+    config: Config,
+    inputs: InputCache,
+    linked: LinkedProgram,
+    globals: HashMap<String, GlobalLocation>,
+    entry_point: u64,
+}
+
+impl Link {
+    /// Contents of the output file
+    pub fn bytes(&self) -> anyhow::Result<Vec<u8>> {
+        let symbols = self.resolved_symbols();
+        build_binary(
+            &self.config,
+            &self.inputs,
+            &self.linked,
+            self.entry_point,
+            &symbols,
+        )
+    }
+
+    /// Every global symbol in the output as (name, virtual address, size),
+    /// sorted by address and then name
+    pub fn resolved_symbols(&self) -> Vec<(String, u64, u64)> {
+        name_resolution::resolved_symbols(&self.linked, &self.config, &self.inputs, &self.globals)
+    }
+
+    /// Annotated listing of the executable segment, see `--annotate`
+    pub fn write_annotation<T: Write>(&self, target: &mut T) -> anyhow::Result<()> {
+        annotate::write(&self.config, &self.inputs, &self.linked, target)
+    }
+
+    /// Input that provided the byte at a final virtual address, as its description,
+    /// the output section name and the offset within the input section
+    pub fn provenance(&self, vaddr: u64) -> Option<(String, &str, u64)> {
+        let (input, section, offset) = section::provenance(&self.linked, &self.config, vaddr)?;
+        Some((self.inputs.description(input), section, offset))
+    }
+
+    /// Placement of every input section and the symbol addresses, see `-M`
+    pub fn write_map<T: Write>(&self, target: &mut T) -> anyhow::Result<()> {
+        map::write(
+            &self.config,
+            &self.inputs,
+            &self.linked,
+            &self.globals,
+            target,
+        )
+    }
+}
+
+/// Read the inputs, detecting the target and byte order from them
+fn read_inputs(config: &Config, paths: &[PathBuf]) -> anyhow::Result<(Config, InputCache)> {
+    let mut config = config.clone();
+    if paths.is_empty() {
+        anyhow::bail!("No input files");
+    }
+    let mut inputs = InputCache::default();
+    inputs.read_all(paths)?;
+    match &config.entry {
+        Entry::Symbol(name) => inputs.select_archive_members(&[name]),
+        Entry::Address(_) => inputs.select_archive_members(&[]),
+    }
+    inputs.discard_duplicate_groups()?;
+
+    verify_inputs(&inputs)?;
+    config.target = target::detect(&inputs)?;
+    config.endian = target::detect_endian(&inputs, config.target)?;
+    if let OutputFormat::Elf(Some((target, endian))) = config.output_format {
+        if (target, endian) != (config.target, config.endian) {
+            anyhow::bail!(
+                "Output format for {target:?} ({endian:?}-endian) doesn't match the inputs, which are {:?} ({:?}-endian)",
+                config.target,
+                config.endian,
+            );
+        }
+    }
+    Ok((config, inputs))
+}
+
+/// Global symbols of the inputs and of the command line
+fn resolve_globals(
+    config: &Config,
+    inputs: &InputCache,
+) -> anyhow::Result<HashMap<String, GlobalLocation>> {
+    let mut globals = name_resolution::extract_globals(config, inputs)?;
+    for path in &config.symbol_maps {
+        name_resolution::define_from_symbol_map(config, inputs, path, &mut globals)?;
+    }
+    name_resolution::define_defsyms(config, inputs, &mut globals)?;
+    name_resolution::check_entry(config, &globals)?;
+    Ok(globals)
+}
+
+/// Read, resolve and lay out the inputs, without serializing the output
+pub fn link_program(config: &Config, inputs: &[PathBuf]) -> anyhow::Result<Link> {
+    link_program_with(config, inputs, &[])
+}
+
+/// Like [`link_program`], rewriting the input sections with the transforms in order
+pub fn link_program_with(
+    config: &Config,
+    inputs: &[PathBuf],
+    transforms: &[Box<dyn SectionTransform>],
+) -> anyhow::Result<Link> {
+    let (config, inputs) = read_inputs(config, inputs)?;
+    let section_names = extract_section_names(&inputs)?;
+    let mut globals = resolve_globals(&config, &inputs)?;
+    let linked = section::build(&config, &inputs, &section_names, transforms, &mut globals)?;
+    let entry_point = name_resolution::entry_point(&config, &inputs, &linked, &globals)?;
+    check_null_page(&config, &linked);
+
+    Ok(Link {
+        config,
+        inputs,
+        linked,
+        globals,
+        entry_point,
+    })
+}
+
+/// Link the input files, returning the contents of the output file
+pub fn link(config: &Config, inputs: &[PathBuf]) -> anyhow::Result<Vec<u8>> {
+    link_program(config, inputs)?.bytes()
+}
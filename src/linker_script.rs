@@ -0,0 +1,389 @@
+//! Minimal subset of GNU linker scripts (`--script=FILE`)
+//!
+//! Only `MEMORY { ... }` and `SECTIONS { ... }` blocks are understood:
+//! * memory regions, like `RAM (rwx) : ORIGIN = 0x600000, LENGTH = 64K`
+//! * output sections with input patterns, like `.text : { *(.text .text.*) }`,
+//!   optionally placed in a memory region with `> RAM`
+//! * location counter assignments with a number, like `. = 0x500000;`
+//! * symbol definitions at the location counter, like `__text_end = .;`
+//!
+//! Everything else is rejected, as silently ignoring a directive would
+//! produce a binary with an unexpected layout.
+
+use std::fs;
+use std::path::Path;
+
+use crate::math::parse_int;
+
+#[derive(Debug, Clone, Default)]
+pub struct LinkerScript {
+    /// Output sections, in the order they are placed
+    pub sections: Vec<OutputSection>,
+    /// Symbols defined by the script, in definition order
+    pub symbols: Vec<(String, Location)>,
+    /// Memory regions of `MEMORY`, in definition order
+    pub memory: Vec<MemoryRegion>,
+    /// Input section patterns inside `KEEP(...)`, whose sections are
+    /// garbage collection roots
+    pub keep: Vec<String>,
+}
+
+/// Address range that output sections are placed in with `> NAME`
+#[derive(Debug, Clone)]
+pub struct MemoryRegion {
+    pub name: String,
+    pub origin: u64,
+    pub length: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct OutputSection {
+    pub name: String,
+    /// Fixed virtual address, from a preceding `. = ADDR;`
+    pub address: Option<u64>,
+    /// Input section name patterns, where `*` and `?` are wildcards
+    pub patterns: Vec<String>,
+    /// Index of the memory region it's placed in. The first non-empty section
+    /// of a region starts at its origin, unless it has a fixed address.
+    pub region: Option<usize>,
+}
+
+/// Value of the location counter at a symbol definition
+#[derive(Debug, Clone)]
+pub enum Location {
+    Absolute(u64),
+    /// Start of the output section at this index
+    SectionStart(usize),
+    /// End of the output section at this index
+    SectionEnd(usize),
+}
+
+impl LinkerScript {
+    /// The input section is kept by `--gc-sections` even if nothing references it
+    pub fn keeps(&self, input_section: &str) -> bool {
+        self.keep
+            .iter()
+            .any(|pattern| glob_matches(pattern, input_section))
+    }
+}
+
+impl OutputSection {
+    pub fn matches(&self, input_section: &str) -> bool {
+        self.patterns
+            .iter()
+            .any(|pattern| glob_matches(pattern, input_section))
+    }
+}
+
+/// Output sections of the built-in layout, each collecting the input sections
+/// with its name or a suffixed one, like `.text.main`
+pub fn default_output_sections() -> Vec<OutputSection> {
+    [
+        ".entry",
+        ".text",
+        ".rodata",
+        ".data.rel.ro",
+        ".tdata",
+        ".tbss",
+        ".data",
+        ".bss",
+    ]
+    .into_iter()
+    .map(|name| OutputSection {
+        name: name.to_owned(),
+        address: None,
+        patterns: vec![name.to_owned(), format!("{name}.*")],
+        region: None,
+    })
+    .collect()
+}
+
+pub fn read(path: &Path) -> anyhow::Result<LinkerScript> {
+    let text = fs::read_to_string(path)?;
+    parse(&text).map_err(|err| anyhow::anyhow!("{}: {err}", path.display()))
+}
+
+pub fn parse(text: &str) -> anyhow::Result<LinkerScript> {
+    let tokens = tokenize(text)?;
+    let mut tokens = Tokens { tokens, pos: 0 };
+    let mut script = LinkerScript::default();
+    while let Some(token) = tokens.next() {
+        match token {
+            "MEMORY" => {
+                tokens.expect("{")?;
+                parse_memory(&mut tokens, &mut script)?;
+            }
+            "SECTIONS" => {
+                tokens.expect("{")?;
+                parse_sections(&mut tokens, &mut script)?;
+            }
+            other => anyhow::bail!("Unsupported linker script directive {other:?}"),
+        }
+    }
+    Ok(script)
+}
+
+/// Contents of `MEMORY { ... }`, after the opening brace
+fn parse_memory(tokens: &mut Tokens, script: &mut LinkerScript) -> anyhow::Result<()> {
+    loop {
+        let name = match tokens.next() {
+            Some("}") => return Ok(()),
+            Some(name) if is_section_name(name) => name,
+            Some(other) => anyhow::bail!("Unsupported memory region {other:?}"),
+            None => anyhow::bail!("Unterminated MEMORY"),
+        };
+        if script.memory.iter().any(|region| region.name == name) {
+            anyhow::bail!("Memory region {name:?} is defined twice");
+        }
+        // Attributes only matter for placing orphan sections, which never go to a region
+        if tokens.peek() == Some("(") {
+            tokens.expect("(")?;
+            tokens.next();
+            tokens.expect(")")?;
+        }
+        tokens.expect(":")?;
+        let origin = parse_region_attribute(tokens, &["ORIGIN", "org", "o"])?;
+        tokens.expect(",")?;
+        let length = parse_region_attribute(tokens, &["LENGTH", "len", "l"])?;
+        origin
+            .checked_add(length)
+            .ok_or_else(|| anyhow::anyhow!("Memory region {name:?} ends past the address space"))?;
+        script.memory.push(MemoryRegion {
+            name: name.to_owned(),
+            origin,
+            length,
+        });
+    }
+}
+
+/// `ORIGIN = 0x1000` or `LENGTH = 64K`, by any of its names
+fn parse_region_attribute(tokens: &mut Tokens, names: &[&str]) -> anyhow::Result<u64> {
+    match tokens.next() {
+        Some(name) if names.contains(&name) => {}
+        Some(other) => anyhow::bail!("Expected {:?}, found {other:?}", names[0]),
+        None => anyhow::bail!("Expected {:?}, found end of script", names[0]),
+    }
+    tokens.expect("=")?;
+    let value = tokens.next().unwrap_or("");
+    let (digits, multiplier) = match value.as_bytes().last() {
+        Some(b'K' | b'k') => (&value[..value.len() - 1], 1 << 10),
+        Some(b'M' | b'm') => (&value[..value.len() - 1], 1 << 20),
+        _ => (value, 1),
+    };
+    parse_int(digits)
+        .and_then(|value| value.checked_mul(multiplier))
+        .ok_or_else(|| anyhow::anyhow!("Unsupported {} value {value:?}", names[0]))
+}
+
+/// Contents of `SECTIONS { ... }`, after the opening brace
+fn parse_sections(tokens: &mut Tokens, script: &mut LinkerScript) -> anyhow::Result<()> {
+    // The location counter is only known after an assignment or an output section
+    let mut dot: Option<Location> = None;
+    // Symbols defined before the first output section or assignment
+    let mut pending: Vec<String> = Vec::new();
+
+    loop {
+        let Some(token) = tokens.next() else {
+            anyhow::bail!("Unterminated SECTIONS");
+        };
+        match token {
+            "}" => break,
+            "." => {
+                tokens.expect("=")?;
+                let value = tokens.next().unwrap_or("");
+                let address = parse_int(value).ok_or_else(|| {
+                    anyhow::anyhow!("Unsupported location counter expression {value:?}")
+                })?;
+                tokens.expect(";")?;
+                for name in pending.drain(..) {
+                    script.symbols.push((name, Location::Absolute(address)));
+                }
+                dot = Some(Location::Absolute(address));
+            }
+            name if tokens.peek() == Some("=") => {
+                tokens.expect("=")?;
+                let value = tokens.next().unwrap_or("");
+                if value != "." {
+                    anyhow::bail!("Unsupported value {value:?} for symbol {name:?}");
+                }
+                tokens.expect(";")?;
+                match &dot {
+                    Some(location) => script.symbols.push((name.to_owned(), location.clone())),
+                    None => pending.push(name.to_owned()),
+                }
+            }
+            name if tokens.peek() == Some(":") && is_section_name(name) => {
+                tokens.expect(":")?;
+                tokens.expect("{")?;
+                let patterns = parse_input_patterns(tokens, &mut script.keep)?;
+                let region = if tokens.peek() == Some(">") {
+                    tokens.expect(">")?;
+                    let region = tokens.next().unwrap_or("");
+                    let index = script.memory.iter().position(|r| r.name == region);
+                    Some(index.ok_or_else(|| {
+                        anyhow::anyhow!("Output section {name} is placed in the undefined memory region {region:?}")
+                    })?)
+                } else {
+                    None
+                };
+
+                let index = script.sections.len();
+                let address = match dot {
+                    Some(Location::Absolute(address)) => Some(address),
+                    _ => None,
+                };
+                for name in pending.drain(..) {
+                    script.symbols.push((name, Location::SectionStart(index)));
+                }
+                script.sections.push(OutputSection {
+                    name: name.to_owned(),
+                    address,
+                    patterns,
+                    region,
+                });
+                dot = Some(Location::SectionEnd(index));
+            }
+            other => anyhow::bail!("Unsupported linker script directive {other:?} in SECTIONS"),
+        }
+    }
+
+    if let Some(name) = pending.first() {
+        anyhow::bail!("Symbol {name:?} is not followed by an output section or an address");
+    }
+    Ok(())
+}
+
+/// Input section descriptions of an output section, after the opening brace.
+/// Patterns inside `KEEP(...)` are also added to `keep`.
+fn parse_input_patterns(
+    tokens: &mut Tokens,
+    keep: &mut Vec<String>,
+) -> anyhow::Result<Vec<String>> {
+    let mut patterns = Vec::new();
+    loop {
+        match tokens.next() {
+            Some("}") => return Ok(patterns),
+            Some("KEEP") => {
+                tokens.expect("(")?;
+                let file = tokens.next().unwrap_or("");
+                let start = patterns.len();
+                parse_input_file_pattern(tokens, file, &mut patterns)?;
+                tokens.expect(")")?;
+                keep.extend_from_slice(&patterns[start..]);
+            }
+            Some(file) if tokens.peek() == Some("(") => {
+                parse_input_file_pattern(tokens, file, &mut patterns)?;
+            }
+            Some(other) => {
+                anyhow::bail!("Unsupported input section description {other:?}")
+            }
+            None => anyhow::bail!("Unterminated output section"),
+        }
+    }
+}
+
+/// `*(pattern pattern ...)`, only the `*` file pattern is supported
+fn parse_input_file_pattern(
+    tokens: &mut Tokens,
+    file: &str,
+    patterns: &mut Vec<String>,
+) -> anyhow::Result<()> {
+    if file != "*" {
+        anyhow::bail!("Unsupported input file pattern {file:?}");
+    }
+    tokens.expect("(")?;
+    loop {
+        match tokens.next() {
+            Some(")") => return Ok(()),
+            Some(pattern) if is_section_name(pattern) => patterns.push(pattern.to_owned()),
+            Some(other) => anyhow::bail!("Unsupported input section pattern {other:?}"),
+            None => anyhow::bail!("Unterminated input section pattern list"),
+        }
+    }
+}
+
+fn is_section_name(token: &str) -> bool {
+    !token.is_empty() && !token.starts_with('/') && !is_punctuation(token.as_bytes()[0] as char)
+}
+
+fn is_punctuation(c: char) -> bool {
+    matches!(c, '{' | '}' | '(' | ')' | ';' | '=' | ':' | ',' | '>')
+}
+
+/// Glob match with `*` for any sequence and `?` for any single character
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    let (pattern, name) = (pattern.as_bytes(), name.as_bytes());
+    // Position to resume from after the last `*`, as (pattern, name)
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut n) = (0, 0);
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == b'?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == b'*' {
+            star = Some((p + 1, n));
+            p += 1;
+        } else if let Some((sp, sn)) = star {
+            // Let the `*` consume one more character
+            star = Some((sp, sn + 1));
+            p = sp;
+            n = sn + 1;
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+fn tokenize(text: &str) -> anyhow::Result<Vec<&str>> {
+    let mut tokens = Vec::new();
+    let mut rest = text;
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            return Ok(tokens);
+        }
+        if let Some(comment) = rest.strip_prefix("/*") {
+            let end = comment
+                .find("*/")
+                .ok_or_else(|| anyhow::anyhow!("Unterminated comment"))?;
+            rest = &comment[end + 2..];
+            continue;
+        }
+
+        let c = rest.chars().next().unwrap();
+        let len = if is_punctuation(c) {
+            1
+        } else {
+            rest.find(|c: char| c.is_whitespace() || is_punctuation(c))
+                .unwrap_or(rest.len())
+        };
+        tokens.push(&rest[..len]);
+        rest = &rest[len..];
+    }
+}
+
+struct Tokens<'a> {
+    tokens: Vec<&'a str>,
+    pos: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn next(&mut self) -> Option<&'a str> {
+        let token = self.tokens.get(self.pos).copied();
+        self.pos += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, expected: &str) -> anyhow::Result<()> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => anyhow::bail!("Expected {expected:?}, found {token:?}"),
+            None => anyhow::bail!("Expected {expected:?}, found end of script"),
+        }
+    }
+}
@@ -1,93 +1,94 @@
-#![feature(drain_filter)]
-#![deny(unused_must_use)]
-
-mod args;
-mod config;
-mod math;
-mod name_resolution;
-mod open_files;
-mod permissions;
-mod relocation;
-mod section;
-mod write_elf64;
-
-use args::Args;
-use config::Config;
-use goblin::elf::Elf;
-use goblin::elf64::header::ET_REL;
-use goblin::elf64::section_header::{SHT_NOBITS, SHT_PROGBITS};
-use open_files::{InputCache, InputId};
-use section::{LinkedProgram, Section};
-use std::collections::HashSet;
+use linker::{args, config::Config};
 use std::fs;
-use std::path::PathBuf;
-use std::str;
-
-const ENTRYPOINT: &str = "_start";
+use std::io::Write;
+use std::path::Path;
 
-/// Location of a global symbol
-#[derive(Debug, Clone)]
-pub struct GlobalLocation {
-    input: InputId,
-    symtab_index: u32,
+/// Make the output executable, so it doesn't need a `chmod +x`
+fn set_output_mode(path: &Path, mode: u32) -> anyhow::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+    #[cfg(not(unix))]
+    let _ = (path, mode);
+    Ok(())
 }
 
-fn verify_inputs(inputs: &InputCache) -> anyhow::Result<()> {
-    for elf in inputs.iter() {
-        assert!(elf.is_64, "Only 64bit is supported");
-        assert!(elf.little_endian, "Only little-endian is supported");
-        assert_eq!(
-            elf.header.e_type, ET_REL,
-            "Only relocatable input files are allowed"
-        );
+/// Write the output via a temporary file in the same directory and rename it
+/// over the target, so that a failed link never leaves a partial output behind.
+/// `-` writes to stdout instead.
+fn write_output(path: &Path, binary: &[u8], mode: u32) -> anyhow::Result<()> {
+    if path == Path::new(args::STDOUT) {
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(binary)?;
+        stdout.flush()?;
+        return Ok(());
     }
-    Ok(())
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("Output path {path:?} is not a file"))?;
+    let mut tmp_name = std::ffi::OsString::from(".");
+    tmp_name.push(file_name);
+    tmp_name.push(format!(".tmp{}", std::process::id()));
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let result = fs::write(&tmp_path, binary)
+        .map_err(anyhow::Error::from)
+        .and_then(|()| set_output_mode(&tmp_path, mode))
+        .and_then(|()| Ok(fs::rename(&tmp_path, path)?));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
 }
 
-fn extract_section_names(inputs: &InputCache) -> anyhow::Result<HashSet<String>> {
-    let mut sections = HashSet::new();
-    for elf in inputs.iter() {
-        for sh in &elf.section_headers {
-            if sh.sh_type == SHT_PROGBITS || sh.sh_type == SHT_NOBITS {
-                if let Some(name) = elf.shdr_strtab.get_at(sh.sh_name) {
-                    sections.insert(name.to_owned());
-                }
-            }
+/// Warnings of the library on stderr, which embedders get through `log` instead
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Warn
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            let level = match record.level() {
+                log::Level::Error => "error",
+                _ => "warning",
+            };
+            eprintln!("{level}: {}", record.args());
         }
     }
-    Ok(sections)
-}
 
-fn build_binary(
-    config: &Config,
-    inputs: &InputCache,
-    linked: &LinkedProgram,
-) -> anyhow::Result<Vec<u8>> {
-    let mut result = Vec::new();
-    write_elf64::write(config, inputs, &mut result, linked)?;
-    Ok(result)
+    fn flush(&self) {}
 }
 
 fn main() -> anyhow::Result<()> {
+    log::set_logger(&StderrLogger).expect("Logger already set");
+    log::set_max_level(log::LevelFilter::Warn);
     let args = args::read();
-    let config = Config {
-        base_addr: 0x40_0000,
-        segment_file_align: 0x1000,
-        page_size: 0x1000,
-    };
+    let config = Config::from_args(&args)?;
+
+    let link = linker::link_program(&config, &args.inputs)?;
+    if args.check_only {
+        return Ok(());
+    }
 
-    let mut inputs = InputCache::default();
-    inputs.read_all(&args.inputs)?;
+    let output = args.output.as_deref().expect("Output path missing");
+    write_output(output, &link.bytes()?, args.output_mode)?;
 
-    // let mut f = File::create("/tmp/linker.log").unwrap();
-    // f.write_all(&format!("lolwat {input_path:?}\n").as_bytes()).unwrap();
-    // f.sync_data().unwrap();
+    if args.print_map {
+        link.write_map(&mut std::io::stdout().lock())?;
+    }
+    if let Some(path) = args.map {
+        let mut f = fs::File::create(path)?;
+        link.write_map(&mut f)?;
+    }
 
-    verify_inputs(&inputs)?;
-    let section_names = extract_section_names(&inputs)?;
-    let globals = name_resolution::extract_globals(&inputs)?;
-    let linked = section::build(&config, &inputs, &section_names, &globals)?;
-    let binary = build_binary(&config, &inputs, &linked)?;
-    fs::write(args.output, binary)?;
+    if let Some(path) = args.annotate {
+        let mut f = fs::File::create(path)?;
+        link.write_annotation(&mut f)?;
+    }
     Ok(())
 }
@@ -0,0 +1,71 @@
+//! Link map (`-M` and `--Map=FILE`), showing where each input section was placed
+
+use std::collections::HashMap;
+use std::io::Write;
+
+use crate::{
+    config::Config, demangle, name_resolution, open_files::InputCache, section::LinkedProgram,
+    GlobalLocation,
+};
+
+/// Lists each segment, its sections and the input chunks in them,
+/// followed by the addresses of all resolved global symbols
+pub fn write<T: Write>(
+    config: &Config,
+    inputs: &InputCache,
+    linked: &LinkedProgram,
+    globals: &HashMap<String, GlobalLocation>,
+    target: &mut T,
+) -> anyhow::Result<()> {
+    for it in linked.iter_with_positions(config) {
+        if it.section_index == 0 && it.chunk_index == 0 {
+            let permissions = it.segment.permissions();
+            writeln!(
+                target,
+                "Segment {:#010x} size {:#x} {}{}{}",
+                config.base_addr + it.segment_start,
+                it.segment.size(),
+                if permissions.read { 'R' } else { '-' },
+                if permissions.write { 'W' } else { '-' },
+                if permissions.execute { 'X' } else { '-' },
+            )?;
+        }
+        if it.chunk_index == 0 {
+            writeln!(
+                target,
+                "  {} {:#010x} size {:#x} align {:#x}",
+                it.section.name,
+                config.base_addr + it.section_start,
+                it.section.size(),
+                it.section.alignment(),
+            )?;
+        }
+
+        let elf = inputs.get_elf(it.chunk.input);
+        // Generated chunks like the GOT don't have an input section
+        let input_section = elf
+            .section_headers
+            .get(it.chunk.section_index as usize)
+            .and_then(|header| elf.shdr_strtab.get_at(header.sh_name))
+            .unwrap_or("(generated)");
+        writeln!(
+            target,
+            "    {:#010x} size {:#x} +{:#x} {input_section} from {}",
+            config.base_addr + it.chunk_start,
+            it.chunk.size(),
+            it.chunk_start - it.section_start,
+            inputs.description(it.chunk.input),
+        )?;
+    }
+
+    writeln!(target, "Symbols")?;
+    for (name, address, size) in name_resolution::resolved_symbols(linked, config, inputs, globals)
+    {
+        writeln!(
+            target,
+            "  {address:#010x} size {size:#x} {}",
+            demangle::display(config, &name)
+        )?;
+    }
+    Ok(())
+}
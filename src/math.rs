@@ -1,4 +1,14 @@
+/// Round `n` up to a multiple of `alignment`.
+/// Alignments 0 and 1 both mean no alignment, as `sh_addralign` allows.
 pub fn align_up(n: u64, alignment: u64) -> u64 {
+    if alignment <= 1 {
+        return n;
+    }
+    debug_assert!(
+        alignment.is_power_of_two(),
+        "Alignment {alignment:#x} is not a power of two"
+    );
+
     let over = n % alignment;
     if over == 0 {
         n
@@ -6,3 +16,44 @@ pub fn align_up(n: u64, alignment: u64) -> u64 {
         n - over + alignment
     }
 }
+
+/// Decimal or `0x`-prefixed hex integer, as used in options, scripts and symbol maps
+pub fn parse_int(value: &str) -> Option<u64> {
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_int_accepts_decimal_and_hex() {
+        assert_eq!(parse_int("4096"), Some(4096));
+        assert_eq!(parse_int("0x1000"), Some(0x1000));
+        assert_eq!(parse_int("0X1000"), Some(0x1000));
+        assert_eq!(parse_int("0"), Some(0));
+    }
+
+    #[test]
+    fn parse_int_rejects_garbage() {
+        assert_eq!(parse_int(""), None);
+        assert_eq!(parse_int("0x"), None);
+        assert_eq!(parse_int("12k"), None);
+        assert_eq!(parse_int("-1"), None);
+        assert_eq!(parse_int("0x1_0000_0000_0000_0000"), None);
+    }
+
+    #[test]
+    fn align_up_rounds_to_multiples() {
+        assert_eq!(align_up(0x1001, 0x1000), 0x2000);
+        assert_eq!(align_up(0x1000, 0x1000), 0x1000);
+        assert_eq!(align_up(7, 0), 7);
+        assert_eq!(align_up(7, 1), 7);
+    }
+}
@@ -0,0 +1,216 @@
+//! Merging of string sections (`SHF_MERGE | SHF_STRINGS`), e.g. `.rodata.str1.1`
+//!
+//! The NUL-terminated strings of all inputs are deduplicated into one chunk,
+//! which replaces the input chunks. References into the original sections are
+//! redirected to the deduplicated copy through [`MergedStrings::resolve`].
+
+use std::collections::{HashMap, HashSet};
+
+use goblin::elf64::section_header::{SHF_MERGE, SHF_STRINGS};
+
+use crate::{
+    config::Config,
+    open_files::{InputCache, InputId},
+    section::{Section, SectionChunk},
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct MergedStrings {
+    /// Merged input sections by (input, section index)
+    inputs: HashMap<(InputId, u32), MergedInput>,
+}
+
+#[derive(Debug, Clone)]
+struct MergedInput {
+    /// Key of the chunk holding the merged strings, as (input, section index)
+    merged: (InputId, u32),
+    /// Start of each string in the input section and in the merged chunk,
+    /// in offset order
+    strings: Vec<(u64, u64)>,
+}
+
+impl MergedStrings {
+    /// Location of an input section offset in the output chunks, as
+    /// (input, section index, offset) of the chunk. Offsets in sections
+    /// that were not merged are returned as-is.
+    pub fn resolve(&self, input: InputId, section_index: u32, offset: u64) -> (InputId, u32, u64) {
+        let Some(merged) = self.inputs.get(&(input, section_index)) else {
+            return (input, section_index, offset);
+        };
+        // References into the middle of a string, e.g. a suffix, stay within it
+        let index = merged
+            .strings
+            .partition_point(|(start, _)| *start <= offset)
+            .saturating_sub(1);
+        let (start, merged_offset) = merged.strings.get(index).copied().unwrap_or_default();
+        let (merged_input, merged_index) = merged.merged;
+        (merged_input, merged_index, merged_offset + (offset - start))
+    }
+
+    pub fn contains(&self, input: InputId, section_index: u32) -> bool {
+        self.inputs.contains_key(&(input, section_index))
+    }
+}
+
+/// String entry size of a mergeable input section, or `None` if it's not one
+fn string_entry_size(inputs: &InputCache, chunk: &SectionChunk) -> Option<u64> {
+    // Relocations would have to be moved along with the strings
+    if chunk.is_nobits() || !chunk.relocations.is_empty() {
+        return None;
+    }
+    let header = &inputs.get_elf(chunk.input).section_headers[chunk.section_index as usize];
+    let flags = header.sh_flags as u32;
+    (flags & SHF_MERGE != 0 && flags & SHF_STRINGS != 0).then_some(header.sh_entsize.max(1))
+}
+
+/// Split into strings including their terminator, which is `entry_size` zero bytes
+fn split_strings(bytes: &[u8], entry_size: usize) -> Vec<&[u8]> {
+    let mut result = Vec::new();
+    let mut start = 0;
+    let mut pos = 0;
+    while pos + entry_size <= bytes.len() {
+        let entry = &bytes[pos..pos + entry_size];
+        pos += entry_size;
+        if entry.iter().all(|b| *b == 0) {
+            result.push(&bytes[start..pos]);
+            start = pos;
+        }
+    }
+    // Unterminated leftovers are kept verbatim
+    if start < bytes.len() {
+        result.push(&bytes[start..]);
+    }
+    result
+}
+
+/// Map each string to the offset of its merged copy, appending the copies to
+/// `contents` in first-seen order. With `tail`, strings that end another one
+/// at an entry boundary are merged into it, like `"lo"` into `"hello"`.
+fn lay_out_strings<'a>(
+    strings: &[&'a [u8]],
+    entry_size: usize,
+    tail: bool,
+    contents: &mut Vec<u8>,
+) -> HashMap<&'a [u8], u64> {
+    // Strings merged into a longer one, with their offset in it
+    let mut suffixes: HashMap<&[u8], (&[u8], u64)> = HashMap::new();
+    if tail {
+        // Sorted by their reversed bytes, each string comes after those it ends
+        let mut sorted = strings.to_vec();
+        sorted.sort_by(|a, b| b.iter().rev().cmp(a.iter().rev()));
+        let mut longer: Option<&[u8]> = None;
+        for string in sorted {
+            match longer {
+                Some(longer)
+                    if longer.ends_with(string)
+                        && (longer.len() - string.len()) % entry_size == 0
+                        && string.ends_with(&vec![0; entry_size]) =>
+                {
+                    suffixes.insert(string, (longer, (longer.len() - string.len()) as u64));
+                }
+                _ => longer = Some(string),
+            }
+        }
+    }
+
+    let mut offsets = HashMap::new();
+    for &string in strings {
+        if !suffixes.contains_key(string) {
+            offsets.insert(string, contents.len() as u64);
+            contents.extend_from_slice(string);
+        }
+    }
+    for (string, (longer, offset)) in suffixes {
+        offsets.insert(string, offsets[longer] + offset);
+    }
+    offsets
+}
+
+/// Replace the mergeable string chunks of each section with one deduplicated chunk
+/// per entry size, placed where the first of them was. Nothing is merged at `-O0`,
+/// and string suffixes only from `-O2` on.
+pub fn merge_strings(
+    config: &Config,
+    inputs: &InputCache,
+    sections: &mut [Section],
+) -> MergedStrings {
+    let mut result = MergedStrings::default();
+    if config.opt_level == 0 {
+        return result;
+    }
+    for section in sections.iter_mut() {
+        let mut entry_sizes: Vec<u64> = section
+            .chunks
+            .iter()
+            .filter_map(|chunk| string_entry_size(inputs, chunk))
+            .collect();
+        entry_sizes.sort();
+        entry_sizes.dedup();
+
+        for entry_size in entry_sizes {
+            // Indices of the merged chunks, and the strings of each
+            let mut chunk_strings: Vec<(usize, Vec<&[u8]>)> = Vec::new();
+            let mut unique: Vec<&[u8]> = Vec::new();
+            let mut seen = HashSet::new();
+            for (index, chunk) in section.chunks.iter().enumerate() {
+                if string_entry_size(inputs, chunk) != Some(entry_size) {
+                    continue;
+                }
+                let strings = split_strings(chunk.unpatched_bytes(inputs), entry_size as usize);
+                unique.extend(strings.iter().filter(|string| seen.insert(**string)));
+                chunk_strings.push((index, strings));
+            }
+            let Some(&(key, _)) = chunk_strings.first() else {
+                continue;
+            };
+
+            let mut contents: Vec<u8> = Vec::new();
+            let tail = config.opt_level >= 2;
+            let offsets = lay_out_strings(&unique, entry_size as usize, tail, &mut contents);
+            let merged = (section.chunks[key].input, section.chunks[key].section_index);
+            let mut alignment = 1;
+            for (index, strings) in chunk_strings {
+                let chunk = &section.chunks[index];
+                alignment = alignment.max(chunk.alignment);
+                let mut input_offset = 0;
+                let strings = strings
+                    .into_iter()
+                    .map(|string| {
+                        let start = input_offset;
+                        input_offset += string.len() as u64;
+                        (start, offsets[string])
+                    })
+                    .collect();
+                result.inputs.insert(
+                    (chunk.input, chunk.section_index),
+                    MergedInput { merged, strings },
+                );
+            }
+
+            let chunk = &section.chunks[key];
+            let merged = SectionChunk::generated(
+                chunk.input,
+                chunk.section_index,
+                alignment,
+                chunk.permissions,
+                contents,
+            );
+
+            let mut merged = Some(merged);
+            section.chunks = std::mem::take(&mut section.chunks)
+                .into_iter()
+                .enumerate()
+                .filter_map(|(index, chunk)| {
+                    if index == key {
+                        merged.take()
+                    } else if string_entry_size(inputs, &chunk) == Some(entry_size) {
+                        None
+                    } else {
+                        Some(chunk)
+                    }
+                })
+                .collect();
+        }
+    }
+    result
+}
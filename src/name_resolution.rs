@@ -1,25 +1,34 @@
 use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
 
 use goblin::{
     elf::Elf,
-    elf64::{section_header::SHT_NOBITS, sym::*},
+    elf64::{
+        section_header::{SHN_ABS, SHN_COMMON, SHT_NOBITS},
+        sym::*,
+    },
 };
 
 use crate::{
+    common,
+    config::{Config, DefsymValue, Entry},
+    demangle,
+    linker_script::Location,
+    math::{align_up, parse_int},
     open_files::{InputCache, InputId},
-    GlobalLocation, ENTRYPOINT,
+    section::{LinkedProgram, INIT_FINI_ARRAYS},
+    write_elf64, GlobalLocation, ENTRYPOINT,
 };
 
 fn extract_globals_from(
+    config: &Config,
+    inputs: &InputCache,
     input: InputId,
     elf: &Elf,
     global_symbols: &mut HashMap<String, GlobalLocation>,
+    errors: &mut Vec<String>,
 ) {
-    // TODO: binding preference support
-    // TODO: weak binds
-
-    let strtab = elf.strtab.to_vec().unwrap();
-    let shdr_strtab = elf.shdr_strtab.to_vec().unwrap();
     for (sym_idx, sym) in elf.syms.iter().enumerate() {
         let name = elf.strtab.get_at(sym.st_name).unwrap_or("");
         // println!("{name: <20}: {sym:?}");
@@ -28,16 +37,49 @@ fn extract_globals_from(
         } else if sym.is_import() {
             // println!("^ import ^");
         }
-        if sym.st_bind() == STB_GLOBAL && sym.st_visibility() != STV_HIDDEN && sym.st_shndx != 0 {
+        let binding = sym.st_bind();
+        let exported = binding == STB_GLOBAL || binding == STB_WEAK;
+        // Definitions in duplicate COMDAT groups resolve to the kept copy
+        let discarded = inputs.is_discarded(input, sym.st_shndx);
+        if exported && sym.st_visibility() != STV_HIDDEN && sym.st_shndx != 0 && !discarded {
             // println!("^ export ^");
-            let location = GlobalLocation {
+            let location = GlobalLocation::Symbol {
                 input,
                 symtab_index: sym_idx.try_into().expect("Symtab index overflow"),
+                binding,
             };
-            let old = global_symbols.insert(name.to_string(), location.clone());
-            if let Some(old) = old {
-                panic!("Duplicate definition of {name:?}: exists in both {old:?} and {location:?}");
+            if let Some(old) = global_symbols.get(name) {
+                // The largest common symbol is kept, until a strong definition overrides it
+                let common = sym.st_shndx == SHN_COMMON as usize;
+                if let Some(old_size) = common_size(inputs, old) {
+                    if (common && sym.st_size > old_size) || (!common && binding != STB_WEAK) {
+                        global_symbols.insert(name.to_string(), location);
+                    }
+                    continue;
+                }
+                if common {
+                    if is_weak(old) {
+                        global_symbols.insert(name.to_string(), location);
+                    }
+                    continue;
+                }
+                // A strong definition overrides weak ones, and the first weak one is kept
+                if binding == STB_WEAK {
+                    continue;
+                }
+                if is_weak(old) {
+                    global_symbols.insert(name.to_string(), location);
+                    continue;
+                }
+                errors.push(format!(
+                    "Duplicate definition of {:?}: exists in both {} and {}",
+                    demangle::display(config, name),
+                    describe(inputs, old),
+                    inputs.description(input),
+                ));
+                continue;
             }
+            global_symbols.insert(name.to_string(), location);
         }
         if sym.st_shndx != 0 {
             if let Some(section) = &elf.section_headers.get(sym.st_shndx) {
@@ -50,36 +92,387 @@ fn extract_globals_from(
     }
 }
 
-pub fn extract_globals(inputs: &InputCache) -> anyhow::Result<HashMap<String, GlobalLocation>> {
+pub fn extract_globals(
+    config: &Config,
+    inputs: &InputCache,
+) -> anyhow::Result<HashMap<String, GlobalLocation>> {
     let mut global_symbols: HashMap<String, GlobalLocation> = HashMap::new();
+    let mut errors = Vec::new();
     for input in inputs.iter_ids() {
         let elf = inputs.get_elf(input);
-        extract_globals_from(input, elf, &mut global_symbols);
+        extract_globals_from(config, inputs, input, elf, &mut global_symbols, &mut errors);
     }
     // dbg!(&global_symbols["_start"]);
+    if !errors.is_empty() {
+        anyhow::bail!("{}", errors.join("\n"));
+    }
     Ok(global_symbols)
 }
 
+fn is_weak(glob: &GlobalLocation) -> bool {
+    matches!(
+        glob,
+        GlobalLocation::Symbol {
+            binding: STB_WEAK,
+            ..
+        }
+    )
+}
+
+/// Size of a common symbol, or `None` for other definitions
+fn common_size(inputs: &InputCache, glob: &GlobalLocation) -> Option<u64> {
+    match *glob {
+        GlobalLocation::Symbol {
+            input,
+            symtab_index,
+            ..
+        } if common::is_common(inputs, input, symtab_index) => symbol_size(inputs, glob),
+        _ => None,
+    }
+}
+
+/// Where a global symbol comes from, for error messages
+pub fn describe(inputs: &InputCache, glob: &GlobalLocation) -> String {
+    match *glob {
+        GlobalLocation::Symbol { input, .. } => inputs.description(input),
+        GlobalLocation::Absolute(address) => format!("linker-defined address {address:#x}"),
+    }
+}
+
+/// Define absolute symbols from a file of `name address` lines, e.g. for calls
+/// into a fixed ROM. Addresses are hex with `0x` or decimal, `#` starts a comment.
+pub fn define_from_symbol_map(
+    config: &Config,
+    inputs: &InputCache,
+    path: &Path,
+    globals: &mut HashMap<String, GlobalLocation>,
+) -> anyhow::Result<()> {
+    let text = fs::read_to_string(path)?;
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let invalid = || anyhow::anyhow!("{}:{}: expected `name address`", path.display(), i + 1);
+        let mut fields = line.split_whitespace();
+        let (Some(name), Some(address), None) = (fields.next(), fields.next(), fields.next())
+        else {
+            return Err(invalid());
+        };
+        let address = parse_int(address).ok_or_else(invalid)?;
+
+        if let Some(old) = globals.get(name) {
+            anyhow::bail!(
+                "{}:{}: symbol {:?} is already defined in {}",
+                path.display(),
+                i + 1,
+                demangle::display(config, name),
+                describe(inputs, old),
+            );
+        }
+        globals.insert(name.to_owned(), GlobalLocation::Absolute(address));
+    }
+    Ok(())
+}
+
+/// Define the `--defsym` symbols, in order, so an alias may refer to an earlier one
+pub fn define_defsyms(
+    config: &Config,
+    inputs: &InputCache,
+    globals: &mut HashMap<String, GlobalLocation>,
+) -> anyhow::Result<()> {
+    for (name, value) in &config.defsyms {
+        let location = match value {
+            DefsymValue::Address(address) => GlobalLocation::Absolute(*address),
+            DefsymValue::Symbol(target) => globals.get(target).cloned().ok_or_else(|| {
+                anyhow::anyhow!(
+                    "--defsym {}: symbol {:?} is undefined",
+                    demangle::display(config, name),
+                    demangle::display(config, target),
+                )
+            })?,
+        };
+        if let Some(old) = globals.get(name) {
+            anyhow::bail!(
+                "--defsym symbol {:?} is already defined in {}",
+                demangle::display(config, name),
+                describe(inputs, old),
+            );
+        }
+        globals.insert(name.to_owned(), location);
+    }
+    Ok(())
+}
+
+/// Define the symbols assigned by the linker script, once the layout is final
+pub fn define_script_symbols(
+    config: &Config,
+    inputs: &InputCache,
+    linked: &LinkedProgram,
+    globals: &mut HashMap<String, GlobalLocation>,
+) -> anyhow::Result<()> {
+    let Some(script) = &config.script else {
+        return Ok(());
+    };
+
+    let section_range = |index: usize| {
+        let name = &script.sections[index].name;
+        linked
+            .iter_with_positions(config)
+            .find(|it| it.chunk_index == 0 && it.section.name == *name)
+            .map(|it| {
+                let start = config.base_addr + it.section_start;
+                start..start + it.section.size()
+            })
+    };
+
+    for (name, location) in &script.symbols {
+        let address = match *location {
+            Location::Absolute(address) => address,
+            Location::SectionStart(index) | Location::SectionEnd(index) => {
+                let Some(range) = section_range(index) else {
+                    anyhow::bail!(
+                        "Linker script symbol {:?} refers to the empty output section {}",
+                        demangle::display(config, name),
+                        script.sections[index].name,
+                    );
+                };
+                match location {
+                    Location::SectionStart(_) => range.start,
+                    _ => range.end,
+                }
+            }
+        };
+
+        if let Some(old) = globals.get(name) {
+            anyhow::bail!(
+                "Linker script symbol {:?} is already defined in {}",
+                demangle::display(config, name),
+                describe(inputs, old),
+            );
+        }
+        globals.insert(name.to_owned(), GlobalLocation::Absolute(address));
+    }
+    Ok(())
+}
+
+/// Final virtual address of a global (or local) symbol,
+/// or `None` if the section defining it is not included in the output
+pub fn global_address(
+    config: &Config,
+    inputs: &InputCache,
+    linked: &LinkedProgram,
+    glob: &GlobalLocation,
+) -> Option<u64> {
+    match *glob {
+        GlobalLocation::Symbol {
+            input,
+            symtab_index,
+            ..
+        } => {
+            let sym = inputs
+                .get_elf(input)
+                .syms
+                .get(symtab_index as usize)
+                .expect("Missing symbol");
+            if sym.st_shndx == SHN_ABS as usize {
+                return Some(sym.st_value);
+            }
+            if sym.st_shndx == SHN_COMMON as usize {
+                return linked.commons.address(config, linked, input, symtab_index);
+            }
+            let address = linked.input_offset_address(input, sym.st_shndx as u32, sym.st_value)?;
+            Some(config.base_addr + address)
+        }
+        GlobalLocation::Absolute(address) => Some(address),
+    }
+}
+
+/// Fail early if no input defines the entry symbol, before unrelated
+/// errors from laying out a program that can't start
+pub fn check_entry(
+    config: &Config,
+    globals: &HashMap<String, GlobalLocation>,
+) -> anyhow::Result<()> {
+    let Entry::Symbol(name) = &config.entry else {
+        return Ok(());
+    };
+    if !globals.contains_key(name) {
+        anyhow::bail!(
+            "Entry symbol {:?} is not defined by any input, use -e SYMBOL to choose another entry point",
+            demangle::display(config, name)
+        );
+    }
+    Ok(())
+}
+
+/// Final virtual address of the entry point symbol
+pub fn entry_point(
+    config: &Config,
+    inputs: &InputCache,
+    linked: &LinkedProgram,
+    globals: &HashMap<String, GlobalLocation>,
+) -> anyhow::Result<u64> {
+    let name = match &config.entry {
+        Entry::Symbol(name) => name,
+        Entry::Address(address) => return Ok(*address),
+    };
+    let glob = globals
+        .get(name)
+        .ok_or_else(|| anyhow::anyhow!("Entry symbol {name:?} is undefined"))?;
+    global_address(config, inputs, linked, glob).ok_or_else(|| {
+        anyhow::anyhow!(
+            "Entry symbol {name:?} in {} is not in any loadable section",
+            describe(inputs, glob)
+        )
+    })
+}
+
+/// Declared size of a global symbol, if it has one
+pub fn symbol_size(inputs: &InputCache, glob: &GlobalLocation) -> Option<u64> {
+    match *glob {
+        GlobalLocation::Symbol {
+            input,
+            symtab_index,
+            ..
+        } => {
+            let sym = inputs.get_elf(input).syms.get(symtab_index as usize)?;
+            Some(sym.st_size)
+        }
+        GlobalLocation::Absolute(_) => None,
+    }
+}
+
+/// All global symbols included in the output, as (name, virtual address, size),
+/// sorted by address and then name
+pub fn resolved_symbols(
+    linked: &LinkedProgram,
+    config: &Config,
+    inputs: &InputCache,
+    globals: &HashMap<String, GlobalLocation>,
+) -> Vec<(String, u64, u64)> {
+    let mut result: Vec<_> = globals
+        .iter()
+        .filter_map(|(name, glob)| {
+            let address = global_address(config, inputs, linked, glob)?;
+            let size = symbol_size(inputs, glob).unwrap_or(0);
+            Some((name.clone(), address, size))
+        })
+        .collect();
+    result.sort_by(|a, b| (a.1, &a.0).cmp(&(b.1, &b.0)));
+    result
+}
+
+/// Symbols traditionally provided by the linker, marking the end of initialized data,
+/// start of bss and the end of the whole memory image. Allocators in programs without
+/// libc use these to find the heap. Startup code uses the bounds of `.init_array`
+/// and `.fini_array`. They are only defined when referenced, and real definitions
+/// from the inputs take precedence.
+pub fn define_boundary_symbols(
+    config: &Config,
+    inputs: &InputCache,
+    linked: &LinkedProgram,
+    globals: &mut HashMap<String, GlobalLocation>,
+) {
+    let (end_of_data, end_of_memory) =
+        match (linked.segment_starts(config).last(), linked.segments.last()) {
+            (Some(start), Some(segment)) => (start + segment.file_size(), start + segment.size()),
+            _ => {
+                let headers = write_elf64::headers_size(config, linked);
+                (headers, headers)
+            }
+        };
+    // Zero-initialized sections are at the end of the last segment
+    let bss_start = end_of_data;
+    let end = align_up(end_of_memory, config.page_size);
+
+    // Without any fragments, the arrays are empty
+    for array_name in INIT_FINI_ARRAYS {
+        let range = linked
+            .iter_with_positions(config)
+            .find(|it| it.chunk_index == 0 && it.section.name == array_name)
+            .map_or(end_of_data..end_of_data, |it| {
+                it.section_start..it.section_start + it.section.size()
+            });
+        let name = array_name.trim_start_matches('.');
+        for (name, addr) in [
+            (format!("__{name}_start"), range.start),
+            (format!("__{name}_end"), range.end),
+        ] {
+            if inputs.is_referenced(&name) {
+                globals
+                    .entry(name)
+                    .or_insert(GlobalLocation::Absolute(config.base_addr + addr));
+            }
+        }
+    }
+
+    for (name, addr) in [
+        ("edata", end_of_data),
+        ("_edata", end_of_data),
+        ("__bss_start", bss_start),
+        ("end", end),
+        ("_end", end),
+    ] {
+        if inputs.is_referenced(name) {
+            globals
+                .entry(name.to_owned())
+                .or_insert(GlobalLocation::Absolute(config.base_addr + addr));
+        }
+    }
+}
+
+/// Symbol table entry defining a name, as seen from `input`, as (input, symbol table index).
+/// `None` if it's undefined or defined by the linker.
+pub fn defining_symbol(
+    inputs: &InputCache,
+    globals: &HashMap<String, GlobalLocation>,
+    input: InputId,
+    name: &str,
+) -> Option<(InputId, u32)> {
+    match resolve_name(inputs.get_elf(input), name)? {
+        NameResolved::Local(symtab_index) => Some((input, symtab_index)),
+        NameResolved::Import => match *globals.get(name)? {
+            GlobalLocation::Symbol {
+                input,
+                symtab_index,
+                ..
+            } => Some((input, symtab_index)),
+            GlobalLocation::Absolute(_) => None,
+        },
+    }
+}
+
 #[derive(Debug)]
 pub enum NameResolved {
-    Local(u8),
+    /// Defined with local binding in the same file, at this symbol table index
+    Local(u32),
+    /// Resolved through the global symbols
     Import,
 }
 
+/// The symbol is referenced with `STB_WEAK` binding, but not defined in the file
+pub fn is_weak_reference(elf: &Elf, name: &str) -> bool {
+    elf.syms.iter().any(|sym| {
+        sym.is_import() && sym.st_bind() == STB_WEAK && elf.strtab.get_at(sym.st_name) == Some(name)
+    })
+}
+
 pub fn resolve_name(elf: &Elf, name: &str) -> Option<NameResolved> {
     // println!("Resolving {name:?}");
 
-    let strtab = elf.strtab.to_vec().unwrap();
-    let shdr_strtab = elf.shdr_strtab.to_vec().unwrap();
     for (sym_idx, sym) in elf.syms.iter().enumerate() {
         let sym_name = elf.strtab.get_at(sym.st_name).unwrap_or("");
 
         if sym_name == name {
             // println!("Found {sym:?} import={}", sym.is_import());
-            if sym.is_import() {
-                return Some(NameResolved::Import);
+            if sym.st_bind() == STB_LOCAL && !sym.is_import() {
+                let index = sym_idx.try_into().expect("Symtab index overflow");
+                return Some(NameResolved::Local(index));
             } else {
-                todo!("local sym");
+                // Global definitions in the same file go through the global map as well
+                return Some(NameResolved::Import);
             }
         }
     }
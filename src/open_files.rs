@@ -1,92 +1,244 @@
+use anyhow::Context;
+use goblin::elf::section_header::SHT_GROUP;
+use goblin::elf::sym::{STB_GLOBAL, STB_WEAK};
 use goblin::elf::Elf;
 use memmap::MmapOptions;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
-use std::path::PathBuf;
+use std::io::Read;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
+/// `SHT_GROUP` flag of COMDAT groups, of which only one copy is kept
+const GRP_COMDAT: u32 = 1;
+
+/// Input path that reads the file from stdin instead
+pub const STDIN: &str = "-";
+
 /// Cookie
 /// TODO: include some kind input cache identifier
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct InputId {
     file: usize,
     member: Option<usize>,
 }
 
-/// TODO: drop
+/// Parsed inputs. Dropping the cache unmaps the files.
 #[derive(Default)]
 pub struct InputCache {
     files: Vec<Arc<InputCacheItem>>,
     file_paths: Vec<PathBuf>,
+    /// Archive members that don't define any needed symbol, left out of the link
+    unused_members: HashSet<InputId>,
+    /// Members of duplicate COMDAT groups, as (input, section index),
+    /// with the same-named member of the kept copy if it has one
+    discarded_sections: HashMap<(InputId, usize), Option<(InputId, usize)>>,
 }
 impl InputCache {
+    /// Map and parse the inputs, spread over a few threads.
+    /// The files keep the command line order.
     pub fn read_all(&mut self, inputs: &[PathBuf]) -> anyhow::Result<()> {
-        for input_path in inputs {
-            let Some(extension) = input_path.extension() else {
-                panic!("File without extension {input_path:?}");
-            };
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let per_thread = inputs.len().div_ceil(threads).max(1);
+        let items: Vec<anyhow::Result<InputCacheItem>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = inputs
+                .chunks(per_thread)
+                .map(|paths| {
+                    scope.spawn(|| {
+                        paths
+                            .iter()
+                            .map(|path| read_input(path))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+                })
+                .collect()
+        });
 
-            let file = File::open(input_path)?;
-            let mmap_boxed = Box::new(unsafe { MmapOptions::new().map(&file)? });
-            let mmap: &'static memmap::Mmap = Box::leak(mmap_boxed);
-
-            if extension.to_str() == Some("o") {
-                let elf = goblin::elf::Elf::parse(&mmap)?;
-                self.file_paths.push(input_path.to_owned());
-                self.files.push(Arc::new(InputCacheItem::Elf { mmap, elf }));
-            } else if extension.to_str() == Some("rlib") {
-                match goblin::archive::Archive::parse(&mmap) {
-                    Ok(archive) => {
-                        let mut members = Vec::new();
-                        let mut member_names = Vec::new();
-                        for member in archive.members() {
-                            if !member.ends_with(".o") {
-                                continue;
-                            }
-
-                            let bytes = archive.extract(&member, &mmap)?;
-                            let elf = goblin::elf::Elf::parse(&bytes)?;
-                            member_names.push(member.to_owned());
-                            members.push(elf);
-                        }
-                        self.file_paths.push(input_path.to_owned());
-                        self.files.push(Arc::new(InputCacheItem::Archive {
-                            mmap,
-                            members,
-                            member_names,
-                        }));
-                    }
-                    Err(err) => panic!("ar parse error: {err:?}"),
+        for (input_path, item) in inputs.iter().zip(items) {
+            self.file_paths.push(input_path.to_owned());
+            self.files.push(Arc::new(item?));
+        }
+        Ok(())
+    }
+
+    /// Bytes of the object file, which section offsets are relative to
+    pub fn get_backing_bytes(&self, id: InputId) -> &[u8] {
+        let file = self.files.get(id.file).unwrap();
+        match file.as_ref() {
+            InputCacheItem::Elf { backing, .. } => backing,
+            InputCacheItem::Archive { member_bytes, .. } => member_bytes[id.member.unwrap()],
+        }
+    }
+
+    /// Include archive members only if they define a symbol that is otherwise
+    /// undefined, starting from the plain object files and `roots`, e.g. the entry point.
+    /// Repeats until no further members are needed.
+    pub fn select_archive_members(&mut self, roots: &[&str]) {
+        let mut included: HashSet<InputId> = HashSet::new();
+        let mut defined: HashSet<&str> = HashSet::new();
+        let mut undefined: Vec<&str> = roots.to_vec();
+        let mut add = |id: InputId, defined: &mut HashSet<_>, undefined: &mut Vec<_>| {
+            included.insert(id);
+            let elf = self.get_elf(id);
+            defined.extend(defined_symbols(elf));
+            undefined.extend(undefined_symbols(elf));
+        };
+
+        for id in self.iter_ids().filter(|id| id.member.is_none()) {
+            add(id, &mut defined, &mut undefined);
+        }
+
+        while let Some(name) = undefined.pop() {
+            if defined.contains(name) {
+                continue;
+            }
+            // Archives are searched in command line order
+            let provider = self.files.iter().enumerate().find_map(|(file, item)| {
+                let InputCacheItem::Archive { symbol_index, .. } = item.as_ref() else {
+                    return None;
+                };
+                let member = *symbol_index.get(name)?;
+                Some(InputId {
+                    file,
+                    member: Some(member),
+                })
+            });
+            match provider {
+                Some(id) => add(id, &mut defined, &mut undefined),
+                // Reported by the relocation pass, if it's actually used
+                None => {
+                    defined.insert(name);
                 }
-            } else {
-                panic!("Unknown extension for input {input_path:?}");
             }
         }
 
+        self.unused_members = self
+            .iter_ids()
+            .filter(|id| !included.contains(id))
+            .collect();
+    }
+
+    /// Keep only the first COMDAT group (`SHT_GROUP` with `GRP_COMDAT`) with each
+    /// signature, in input order. Sections of the later copies are discarded,
+    /// along with the symbols they define.
+    pub fn discard_duplicate_groups(&mut self) -> anyhow::Result<()> {
+        // Members of the kept copy of each group, by name
+        let mut kept: HashMap<&str, HashMap<&str, (InputId, usize)>> = HashMap::new();
+        let mut discarded = HashMap::new();
+        for id in self.iter_ids() {
+            let elf = self.get_elf(id);
+            let bytes = self.get_backing_bytes(id);
+            for header in elf
+                .section_headers
+                .iter()
+                .filter(|h| h.sh_type == SHT_GROUP)
+            {
+                let contents = header
+                    .file_range()
+                    .and_then(|range| bytes.get(range))
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "Section group of {} extends past the end of the file",
+                            self.description(id)
+                        )
+                    })?;
+                let words: Vec<u32> = contents
+                    .chunks_exact(4)
+                    .map(|word| {
+                        let word = word.try_into().unwrap();
+                        if elf.little_endian {
+                            u32::from_le_bytes(word)
+                        } else {
+                            u32::from_be_bytes(word)
+                        }
+                    })
+                    .collect();
+                let Some((&flags, members)) = words.split_first() else {
+                    continue;
+                };
+                if flags & GRP_COMDAT == 0 {
+                    continue;
+                }
+
+                let signature = elf
+                    .syms
+                    .get(header.sh_info as usize)
+                    .and_then(|sym| elf.strtab.get_at(sym.st_name))
+                    .unwrap_or("");
+                let name = |index: u32| {
+                    elf.section_headers
+                        .get(index as usize)
+                        .and_then(|header| elf.shdr_strtab.get_at(header.sh_name))
+                        .unwrap_or("")
+                };
+                if let Some(copy) = kept.get(signature) {
+                    discarded.extend(
+                        members
+                            .iter()
+                            .map(|&index| ((id, index as usize), copy.get(name(index)).copied())),
+                    );
+                } else {
+                    let copy = members
+                        .iter()
+                        .map(|&index| (name(index), (id, index as usize)))
+                        .collect();
+                    kept.insert(signature, copy);
+                }
+            }
+        }
+        self.discarded_sections = discarded;
         Ok(())
     }
 
-    pub fn get_backing_bytes(&self, id: InputId) -> &memmap::Mmap {
-        let file = self.files.get(id.file).unwrap();
-        match file.as_ref() {
-            InputCacheItem::Elf { mmap, .. } => mmap,
-            InputCacheItem::Archive { mmap, .. } => mmap, // TODO: do we have to subslice here for the selected archive?
+    /// Section belongs to a duplicate COMDAT group, see [`Self::discard_duplicate_groups`]
+    pub fn is_discarded(&self, id: InputId, section_index: usize) -> bool {
+        self.discarded_sections.contains_key(&(id, section_index))
+    }
+
+    /// Section that takes the place of an input section, which is the same-named
+    /// section of the kept copy for members of discarded COMDAT groups.
+    /// `None` if the kept copy has no such section.
+    pub fn kept_section(&self, id: InputId, section_index: usize) -> Option<(InputId, usize)> {
+        match self.discarded_sections.get(&(id, section_index)) {
+            Some(kept) => *kept,
+            None => Some((id, section_index)),
         }
     }
 
+    /// Some included input has an undefined reference to the symbol
+    pub fn is_referenced(&self, name: &str) -> bool {
+        self.iter().any(|elf| {
+            elf.syms.iter().any(|sym| {
+                sym.st_shndx == 0
+                    && matches!(sym.st_bind(), STB_GLOBAL | STB_WEAK)
+                    && elf.strtab.get_at(sym.st_name) == Some(name)
+            })
+        })
+    }
+
     pub fn description(&self, id: InputId) -> String {
-        let path = self.file_paths.get(id.file).unwrap();
+        let path = path_description(self.file_paths.get(id.file).unwrap());
 
         let file = self.files.get(id.file).unwrap();
         match file.as_ref() {
-            InputCacheItem::Elf { .. } => format!("{path:?}"),
+            InputCacheItem::Elf { .. } => path,
             InputCacheItem::Archive { member_names, .. } => format!(
-                "{:?} in {path:?}",
+                "{:?} in {path}",
                 member_names.get(id.member.unwrap()).unwrap()
             ),
         }
     }
 
-    pub fn get_elf(&self, id: InputId) -> &Elf<'static> {
+    pub fn get_elf(&self, id: InputId) -> &Elf<'_> {
         let file = self.files.get(id.file).unwrap();
         match file.as_ref() {
             InputCacheItem::Elf { elf, .. } => elf,
@@ -94,28 +246,270 @@ impl InputCache {
         }
     }
 
+    /// Inputs included in the link
     pub fn iter_ids(&self) -> impl Iterator<Item = InputId> + '_ {
         self.files
             .iter()
             .enumerate()
             .flat_map(|(file, item)| item._iter_ids_helper(file))
+            .filter(|id| !self.unused_members.contains(id))
     }
 
-    pub fn iter(&self) -> impl Iterator<Item = &Elf<'static>> + '_ {
+    pub fn iter(&self) -> impl Iterator<Item = &Elf<'_>> + '_ {
         self.iter_ids().map(|id| self.get_elf(id))
     }
 }
 
-/// Field order matters: dropped in order
+/// Contents of an input file: mapped, or read into memory when it can't be mapped
+pub enum Backing {
+    Mapped(memmap::Mmap),
+    Owned(Vec<u8>),
+}
+
+impl std::ops::Deref for Backing {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        match self {
+            Backing::Mapped(mmap) => mmap,
+            Backing::Owned(bytes) => bytes,
+        }
+    }
+}
+
+/// Path of an input in diagnostics, or `<stdin>` for [`STDIN`]
+fn path_description(path: &Path) -> String {
+    if path == Path::new(STDIN) {
+        "<stdin>".to_owned()
+    } else {
+        format!("{path:?}")
+    }
+}
+
+/// Map a file, or read stdin for [`STDIN`], also returning its bytes with a
+/// lifetime detached from the backing
+fn map_file(path: &Path) -> anyhow::Result<(Backing, &'static [u8])> {
+    let backing = if path == Path::new(STDIN) {
+        let mut bytes = Vec::new();
+        std::io::stdin().lock().read_to_end(&mut bytes)?;
+        Backing::Owned(bytes)
+    } else {
+        let file = File::open(path)?;
+        Backing::Mapped(unsafe { MmapOptions::new().map(&file)? })
+    };
+    // SAFETY: Neither the mapping nor the heap buffer moves when the `Backing` does,
+    // and `InputCacheItem` drops the parsed files borrowing it before the backing.
+    // The `'static` lifetime is never exposed, see `InputCache::get_elf`.
+    let bytes: &'static [u8] =
+        unsafe { std::slice::from_raw_parts(backing.as_ptr(), backing.len()) };
+    Ok((backing, bytes))
+}
+
+/// Map and parse an object file or an archive
+fn read_input(input_path: &Path) -> anyhow::Result<InputCacheItem> {
+    let (backing, bytes) = map_file(input_path)?;
+    let name = path_description(input_path);
+
+    let kind = input_kind(input_path, bytes)?;
+    if kind == InputKind::Object {
+        let elf = goblin::elf::Elf::parse(bytes)
+            .with_context(|| format!("{name} is not a valid ELF object"))?;
+        let elf = Box::new(elf);
+        return Ok(InputCacheItem::Elf { backing, elf });
+    }
+
+    // (name, contents) of the object members
+    let mut contents: Vec<(String, &'static [u8])> = Vec::new();
+    let mut member_maps = Vec::new();
+    if kind == InputKind::ThinArchive {
+        // Member names are paths, relative to the archive
+        let dir = input_path.parent().unwrap_or(Path::new(""));
+        for member in thin_archive_members(bytes)
+            .map_err(|err| anyhow::anyhow!("{name} is not a valid thin archive: {err}"))?
+        {
+            if !member.ends_with(".o") {
+                continue;
+            }
+            let (member_map, member_bytes) = map_file(&dir.join(&member)).map_err(|err| {
+                anyhow::anyhow!("{member:?}, referenced by thin archive {name}: {err}")
+            })?;
+            member_maps.push(member_map);
+            contents.push((member, member_bytes));
+        }
+    } else {
+        let archive = goblin::archive::Archive::parse(bytes)
+            .map_err(|err| anyhow::anyhow!("{name} is not a valid archive: {err}"))?;
+        for member in archive.members() {
+            if !member.ends_with(".o") {
+                continue;
+            }
+            contents.push((member.to_owned(), archive.extract(member, bytes)?));
+        }
+    }
+
+    let mut members = Vec::new();
+    let mut member_names = Vec::new();
+    let mut member_bytes = Vec::new();
+    let mut symbol_index = HashMap::new();
+    for (member, bytes) in contents {
+        if let Some(format) = foreign_object_format(bytes) {
+            anyhow::bail!("{member:?} in {name} is a {format} object; only ELF is supported");
+        }
+        let elf = goblin::elf::Elf::parse(bytes)
+            .with_context(|| format!("{member:?} in {name} is not a valid ELF object"))?;
+        for name in defined_symbols(&elf) {
+            // The first member defining a symbol provides it
+            symbol_index.entry(name.to_owned()).or_insert(members.len());
+        }
+        member_names.push(member);
+        member_bytes.push(bytes);
+        members.push(elf);
+    }
+    Ok(InputCacheItem::Archive {
+        members,
+        member_names,
+        member_bytes,
+        symbol_index,
+        _member_maps: member_maps,
+        _backing: backing,
+    })
+}
+
+/// Member names of a GNU thin archive (`ar T`), which only stores the paths of
+/// the members. The symbol table and the long name table are the only contents.
+fn thin_archive_members(bytes: &[u8]) -> anyhow::Result<Vec<String>> {
+    const HEADER_SIZE: usize = 60;
+    let field = |header: &[u8], range: Range<usize>| -> anyhow::Result<String> {
+        Ok(std::str::from_utf8(&header[range])?.trim_end().to_owned())
+    };
+
+    let mut result = Vec::new();
+    let mut long_names: &[u8] = &[];
+    let mut offset = THIN_ARCHIVE_MAGIC.len();
+    while offset < bytes.len() {
+        let Some(header) = bytes.get(offset..offset + HEADER_SIZE) else {
+            anyhow::bail!("truncated member header at {offset:#x}");
+        };
+        if &header[58..60] != b"`\n" {
+            anyhow::bail!("bad member header at {offset:#x}");
+        }
+        let name = field(header, 0..16)?;
+        let size: usize = field(header, 48..58)?.parse()?;
+        offset += HEADER_SIZE;
+
+        let stored = |offset: usize| {
+            bytes
+                .get(offset..offset + size)
+                .ok_or_else(|| anyhow::anyhow!("truncated member at {offset:#x}"))
+        };
+        if name == "/" || name == "/SYM64/" {
+            stored(offset)?;
+        } else if name == "//" {
+            long_names = stored(offset)?;
+        } else {
+            // Only the special members above have contents here
+            let name = match name.strip_prefix('/') {
+                Some(index) => {
+                    let start: usize = index.parse()?;
+                    let rest = long_names
+                        .get(start..)
+                        .ok_or_else(|| anyhow::anyhow!("bad long name offset {start}"))?;
+                    let len = rest
+                        .windows(2)
+                        .position(|w| w == b"/\n")
+                        .unwrap_or(rest.len());
+                    std::str::from_utf8(&rest[..len])?.to_owned()
+                }
+                None => name.trim_end_matches('/').to_owned(),
+            };
+            result.push(name);
+            continue;
+        }
+        // Contents are padded to an even offset
+        offset += size + size % 2;
+    }
+    Ok(result)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InputKind {
+    Object,
+    Archive,
+    ThinArchive,
+}
+
+const THIN_ARCHIVE_MAGIC: &[u8] = b"!<thin>\n";
+
+/// Tell objects and archives apart by their magic, so that any file name works.
+/// Foreign objects are rejected by theirs, and the extension is only consulted
+/// for files with no known magic.
+fn input_kind(input_path: &Path, bytes: &[u8]) -> anyhow::Result<InputKind> {
+    let name = path_description(input_path);
+    if bytes.starts_with(b"\x7fELF") {
+        return Ok(InputKind::Object);
+    }
+    if bytes.starts_with(b"!<arch>\n") {
+        return Ok(InputKind::Archive);
+    }
+    if bytes.starts_with(THIN_ARCHIVE_MAGIC) {
+        return Ok(InputKind::ThinArchive);
+    }
+    if let Some(format) = foreign_object_format(bytes) {
+        anyhow::bail!("{name} is a {format} object; only ELF is supported");
+    }
+    match input_path.extension().and_then(|e| e.to_str()) {
+        Some("o") => Ok(InputKind::Object),
+        Some("rlib" | "a") => Ok(InputKind::Archive),
+        _ => anyhow::bail!("{name} is neither an ELF object nor an archive"),
+    }
+}
+
+/// Names of global and weak symbols defined in an object
+fn defined_symbols<'a>(elf: &'a Elf) -> impl Iterator<Item = &'a str> + 'a {
+    elf.syms
+        .iter()
+        .filter(|sym| sym.st_shndx != 0 && matches!(sym.st_bind(), STB_GLOBAL | STB_WEAK))
+        .filter_map(|sym| elf.strtab.get_at(sym.st_name))
+}
+
+/// Names of symbols an object needs from others. Weak references don't pull
+/// in archive members.
+fn undefined_symbols<'a>(elf: &'a Elf) -> impl Iterator<Item = &'a str> + 'a {
+    elf.syms
+        .iter()
+        .filter(|sym| sym.st_shndx == 0 && sym.st_bind() == STB_GLOBAL)
+        .filter_map(|sym| elf.strtab.get_at(sym.st_name))
+        .filter(|name| !name.is_empty())
+}
+
+/// Recognize common non-ELF object formats by their magic, for better error messages
+fn foreign_object_format(bytes: &[u8]) -> Option<&'static str> {
+    match bytes {
+        [0xfe, 0xed, 0xfa, 0xce | 0xcf, ..] | [0xce | 0xcf, 0xfa, 0xed, 0xfe, ..] => Some("Mach-O"),
+        [0xca, 0xfe, 0xba, 0xbe, ..] => Some("universal Mach-O"),
+        [b'M', b'Z', ..] => Some("PE"),
+        // COFF objects start with the machine type: x86-64, i386, ARM64, ARMv7
+        [0x64, 0x86, ..] | [0x4c, 0x01, ..] | [0x64, 0xaa, ..] | [0xc4, 0x01, ..] => Some("COFF"),
+        _ => None,
+    }
+}
+
+/// Field order matters: dropped in order, so the parsed files go before the mapping they borrow
 pub enum InputCacheItem {
     Elf {
-        elf: Elf<'static>,
-        mmap: &'static memmap::Mmap,
+        /// Boxed, as it's much larger than the other variant
+        elf: Box<Elf<'static>>,
+        backing: Backing,
     },
     Archive {
         members: Vec<Elf<'static>>,
         member_names: Vec<String>,
-        mmap: &'static memmap::Mmap,
+        /// Contents of each member, in `_backing` or, for thin archives, in `_member_maps`
+        member_bytes: Vec<&'static [u8]>,
+        /// Member defining each symbol
+        symbol_index: HashMap<String, usize>,
+        /// Files that the members of a thin archive refer to, only kept mapped
+        _member_maps: Vec<Backing>,
+        _backing: Backing,
     },
 }
 
@@ -0,0 +1,273 @@
+//! Static position-independent executables (`-pie`)
+//!
+//! The output is linked at `base_addr`, zero by default, and may be loaded anywhere.
+//! Its startup code then applies the `R_X86_64_RELATIVE` relocations in `.rela.dyn`,
+//! found through `_DYNAMIC` or `__rela_start` and `__rela_end`, adding the load bias
+//! to each absolute address stored in the image.
+
+use std::collections::{HashMap, HashSet};
+
+use goblin::elf64::{reloc::R_X86_64_RELATIVE, section_header::SHN_ABS};
+
+use crate::{
+    config::Config,
+    endian::ToBytes,
+    got::Got,
+    ifunc::{Ifuncs, RELA_SIZE},
+    name_resolution::{defining_symbol, is_weak_reference},
+    open_files::{InputCache, InputId},
+    permissions::Permissions,
+    relocation::RelativeTo,
+    section::{
+        ChunkSource, LinkedProgram, Section, SectionChunk, DYNAMIC_SECTION_INDEX,
+        RELA_DYN_SECTION_INDEX,
+    },
+    target::Target,
+    write_elf64, GlobalLocation,
+};
+
+pub const RELA_DYN: &str = ".rela.dyn";
+pub const DYNAMIC: &str = ".dynamic";
+
+const DT_NULL: u64 = 0;
+const DT_RELA: u64 = 7;
+const DT_RELASZ: u64 = 8;
+const DT_RELAENT: u64 = 9;
+/// Number of `R_X86_64_RELATIVE` entries at the start of the table, here all of them
+const DT_RELACOUNT: u64 = 0x6fff_fff9;
+
+/// Tags of the `.dynamic` entries, ending with the terminator
+const DYNAMIC_TAGS: [u64; 5] = [DT_RELA, DT_RELASZ, DT_RELAENT, DT_RELACOUNT, DT_NULL];
+/// Size of an `Elf64_Dyn`
+pub const DYNAMIC_ENTRY_SIZE: u64 = 16;
+
+#[derive(Debug, Clone, Default)]
+pub struct Pie {
+    /// Names defined as absolute addresses before layout, e.g. by `--defsym`.
+    /// Symbols the linker defines later are addresses in the image.
+    absolute: HashSet<String>,
+    /// Number of relative relocations, known before layout
+    count: usize,
+    /// Relative relocations as (place, link-time value), once relocated
+    pub relocations: Vec<(u64, u64)>,
+    /// Input the generated chunks are attributed to
+    input: Option<InputId>,
+}
+
+impl Pie {
+    /// Whether a relocation target stays put when the image is moved
+    pub fn is_absolute(
+        &self,
+        inputs: &InputCache,
+        globals: &HashMap<String, GlobalLocation>,
+        input: InputId,
+        target: &RelativeTo,
+    ) -> bool {
+        let RelativeTo::Symbol(name) = target else {
+            return false;
+        };
+        match defining_symbol(inputs, globals, input, name) {
+            Some((input, symtab_index)) => inputs
+                .get_elf(input)
+                .syms
+                .get(symtab_index as usize)
+                .is_some_and(|sym| sym.st_shndx == SHN_ABS as usize),
+            // Undefined weak references stay null wherever the executable is loaded
+            None => {
+                self.absolute.contains(name.as_str())
+                    || (!globals.contains_key(name)
+                        && is_weak_reference(inputs.get_elf(input), name))
+            }
+        }
+    }
+}
+
+/// Count the references that need a relative relocation, i.e. the 64-bit absolute
+/// ones and the GOT slots, and add the `.rela.dyn` and `.dynamic` sections
+pub fn build(
+    config: &Config,
+    inputs: &InputCache,
+    globals: &HashMap<String, GlobalLocation>,
+    got: &Got,
+    ifuncs: &Ifuncs,
+    sections: &mut Vec<Section>,
+) -> anyhow::Result<Pie> {
+    let mut pie = Pie::default();
+    if !config.pie {
+        return Ok(pie);
+    }
+    if config.target != Target::X86_64 {
+        anyhow::bail!("-pie is only supported on x86-64");
+    }
+    if !ifuncs.is_empty() {
+        anyhow::bail!("IFUNC symbols are not supported with -pie");
+    }
+
+    pie.absolute = globals
+        .iter()
+        .filter(|(_, glob)| matches!(glob, GlobalLocation::Absolute(_)))
+        .map(|(name, _)| name.clone())
+        .collect();
+    for chunk in sections.iter().flat_map(|s| s.chunks.iter()) {
+        pie.input.get_or_insert(chunk.input);
+        for reloc in &chunk.relocations {
+            if reloc.stores_address()
+                && !pie.is_absolute(inputs, globals, chunk.input, &reloc.relative_to)
+            {
+                pie.count += 1;
+            }
+        }
+    }
+    for (input, name) in got.slots() {
+        let target = RelativeTo::Symbol(name.to_owned());
+        if !pie.is_absolute(inputs, globals, input, &target) {
+            pie.count += 1;
+        }
+    }
+
+    let Some(input) = pie.input else {
+        return Ok(pie);
+    };
+    for (name, index, size, write) in [
+        (
+            RELA_DYN,
+            RELA_DYN_SECTION_INDEX,
+            pie.count as u64 * RELA_SIZE,
+            false,
+        ),
+        (
+            DYNAMIC,
+            DYNAMIC_SECTION_INDEX,
+            DYNAMIC_TAGS.len() as u64 * DYNAMIC_ENTRY_SIZE,
+            true,
+        ),
+    ] {
+        // An empty `.rela.dyn` would be a segment of its own without any memory
+        if size == 0 {
+            continue;
+        }
+        let permissions = Permissions {
+            read: true,
+            write,
+            execute: false,
+        };
+        // Like other linkers, `.dynamic` follows `.data.rel.ro`, so both are in `PT_GNU_RELRO`
+        let position = if index == DYNAMIC_SECTION_INDEX {
+            sections
+                .iter()
+                .rposition(|s| write_elf64::is_relro_section(&s.name))
+                .map_or(0, |i| i + 1)
+        } else {
+            sections.len()
+        };
+        sections.insert(
+            position,
+            Section {
+                name: name.to_owned(),
+                chunks: vec![SectionChunk::generated(
+                    input,
+                    index,
+                    8,
+                    permissions,
+                    vec![0; size as usize],
+                )],
+                address: None,
+            },
+        );
+    }
+    Ok(pie)
+}
+
+/// Define `__rela_start` and `__rela_end` around `.rela.dyn`, and `_DYNAMIC`
+/// at `.dynamic`, if referenced. Without `-pie`, the range is empty and
+/// `_DYNAMIC` is left undefined.
+pub fn define_symbols(
+    config: &Config,
+    inputs: &InputCache,
+    linked: &LinkedProgram,
+    globals: &mut HashMap<String, GlobalLocation>,
+) {
+    let start = |index| {
+        let input = linked.pie.input?;
+        linked.input_section_start(input, index)
+    };
+    let rela = start(RELA_DYN_SECTION_INDEX).unwrap_or(0);
+    for (name, addr) in [
+        ("__rela_start", Some(rela)),
+        (
+            "__rela_end",
+            Some(rela + linked.pie.count as u64 * RELA_SIZE),
+        ),
+        ("_DYNAMIC", start(DYNAMIC_SECTION_INDEX)),
+    ] {
+        let Some(addr) = addr else {
+            continue;
+        };
+        if inputs.is_referenced(name) {
+            globals
+                .entry(name.to_owned())
+                .or_insert(GlobalLocation::Absolute(config.base_addr + addr));
+        }
+    }
+}
+
+/// Fill `.rela.dyn` with the relative relocations, sorted by place,
+/// and `.dynamic` with its final address
+pub fn fill(config: &Config, linked: &mut LinkedProgram) {
+    let Some(input) = linked.pie.input else {
+        return;
+    };
+    assert_eq!(
+        linked.pie.relocations.len(),
+        linked.pie.count,
+        "Relative relocation count changed"
+    );
+    let endian = config.endian;
+    let rela_start = linked
+        .input_section_start(input, RELA_DYN_SECTION_INDEX)
+        .map_or(0, |start| config.base_addr + start);
+
+    let mut relocations = linked.pie.relocations.clone();
+    relocations.sort();
+    let mut rela = Vec::new();
+    for (place, value) in relocations {
+        rela.extend(place.to_bytes(endian));
+        rela.extend((R_X86_64_RELATIVE as u64).to_bytes(endian));
+        rela.extend(value.to_bytes(endian));
+    }
+
+    let mut dynamic = Vec::new();
+    for tag in DYNAMIC_TAGS {
+        let value = match tag {
+            DT_RELA => rela_start,
+            DT_RELASZ => rela.len() as u64,
+            DT_RELAENT => RELA_SIZE,
+            DT_RELACOUNT => linked.pie.count as u64,
+            _ => 0,
+        };
+        dynamic.extend(tag.to_bytes(endian));
+        dynamic.extend(value.to_bytes(endian));
+    }
+
+    for (index, contents) in [
+        (RELA_DYN_SECTION_INDEX, rela),
+        (DYNAMIC_SECTION_INDEX, dynamic),
+    ] {
+        if contents.is_empty() {
+            continue;
+        }
+        let chunk = linked
+            .segments
+            .iter_mut()
+            .flat_map(|segment| segment.sections.iter_mut())
+            .flat_map(|section| section.chunks.iter_mut())
+            .find(|chunk| chunk.section_index == index)
+            .expect("PIE section missing");
+        assert_eq!(
+            chunk.size(),
+            contents.len() as u64,
+            "PIE section size changed"
+        );
+        chunk.source = ChunkSource::Generated(contents);
+    }
+}
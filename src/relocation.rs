@@ -1,24 +1,34 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 
 use goblin::{
-    elf::{Elf, RelocSection},
+    elf::Elf,
     elf64::{
-        header::*,
         reloc::*,
-        sym::{STB_GLOBAL, STB_WEAK, STT_SECTION},
+        section_header::SHT_REL,
+        sym::{STB_LOCAL, STT_SECTION},
     },
 };
+use itertools::Itertools;
 
 use crate::{
+    aarch64,
     config::Config,
-    math::align_up,
-    name_resolution::resolve_name,
-    name_resolution::NameResolved,
+    demangle,
+    endian::{read_int, Endian, ToBytes},
+    got,
+    name_resolution::{
+        describe, global_address, is_weak_reference, resolve_name, symbol_size, NameResolved,
+    },
     open_files::{InputCache, InputId},
     section::{ItChunk, LinkedProgram},
+    target::Target,
     GlobalLocation,
 };
 
+/// `mov r64, r/m64`, and the `lea` that loads the address instead
+const MOV_OPCODE: u8 = 0x8b;
+pub const LEA_OPCODE: u8 = 0x8d;
+
 #[derive(Debug, Clone)]
 pub struct Relocate {
     /// Location to patch, in the original input file section
@@ -26,11 +36,86 @@ pub struct Relocate {
     // Size and relocation mode. For example [`R_X86_64_PC32`]. Use r_to_str to display.
     mode: u32,
     /// Relative to, "anchor"
-    relative_to: RelativeTo,
+    pub relative_to: RelativeTo,
     /// Constant applied to relative, i.e. "addend"
     relative_offset: i64,
 }
 
+impl Relocate {
+    /// Whether this refers to the GOT slot of the symbol instead of the symbol itself
+    pub fn uses_got(&self) -> bool {
+        matches!(
+            self.mode,
+            R_X86_64_GOTPCREL | R_X86_64_GOTPCRELX | R_X86_64_REX_GOTPCRELX
+        )
+    }
+
+    /// Offset of the opcode of `mov foo@GOTPCREL(%rip), %reg`, if this relocation
+    /// marks it as relaxable and `bytes` of the section have that instruction
+    pub fn relaxable_got_load(&self, bytes: &[u8]) -> Option<usize> {
+        if !matches!(self.mode, R_X86_64_GOTPCRELX | R_X86_64_REX_GOTPCRELX) {
+            return None;
+        }
+        // The ModRM byte precedes the displacement, and the opcode precedes that
+        let opcode = (self.patch_offset as usize).checked_sub(2)?;
+        (bytes.get(opcode) == Some(&MOV_OPCODE)).then_some(opcode)
+    }
+
+    /// Stores the full address of its target, which a position-independent
+    /// executable adjusts with a relative relocation
+    pub fn stores_address(&self) -> bool {
+        self.mode == R_X86_64_64
+    }
+
+    /// Offset of the patched field in the input section
+    pub fn offset(&self) -> u64 {
+        self.patch_offset
+    }
+
+    /// Offset, type and addend, which relocations of identical sections share
+    pub fn shape(&self) -> (u64, u32, i64) {
+        (self.patch_offset, self.mode, self.relative_offset)
+    }
+
+    /// Target of a call or jump instruction in code `bytes`, which doesn't
+    /// expose the address of the target to the program
+    pub fn is_branch(&self, target: Target, bytes: &[u8]) -> bool {
+        let offset = self.patch_offset as usize;
+        let before = |n: usize| offset.checked_sub(n).and_then(|i| bytes.get(i)).copied();
+        // `call` or `jmp rel32`, or a conditional `jcc rel32`
+        let x86_branch = matches!(before(1), Some(0xe8 | 0xe9))
+            || before(2) == Some(0x0f) && before(1).is_some_and(|op| op & 0xf0 == 0x80);
+        match target {
+            Target::X86_64 => matches!(self.mode, R_X86_64_PC32 | R_X86_64_PLT32) && x86_branch,
+            Target::I386 => matches!(self.mode, R_386_PC32 | R_386_PLT32) && x86_branch,
+            Target::Aarch64 => matches!(self.mode, R_AARCH64_CALL26 | R_AARCH64_JUMP26),
+        }
+    }
+
+    /// Distance from the patched field to the end of the instruction, which
+    /// x86 PC-relative addends subtract. Zero for other relocations.
+    fn pc_bias(&self, target: Target) -> i64 {
+        match target {
+            Target::X86_64 => match self.mode {
+                R_X86_64_PC32 | R_X86_64_PLT32 => 4,
+                R_X86_64_PC16 => 2,
+                R_X86_64_PC8 => 1,
+                _ => 0,
+            },
+            Target::I386 => match self.mode {
+                R_386_PC32 | R_386_PLT32 => 4,
+                _ => 0,
+            },
+            Target::Aarch64 => 0,
+        }
+    }
+
+    /// Refer to the symbol itself, once the instruction is relaxed to `lea`
+    pub fn relax_to_pc32(&mut self) {
+        self.mode = R_X86_64_PC32;
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum RelativeTo {
     /// Start of a section in the same compilation unit as the relocation
@@ -39,13 +124,48 @@ pub enum RelativeTo {
     Symbol(String),
 }
 
-/// Extract relocations for a single section
-pub fn extract(elf: &Elf, target_section_index: u32) -> Vec<Relocate> {
-    elf.shdr_relocs
+impl RelativeTo {
+    /// For error messages, e.g. `symbol "foo"` or `section .text`
+    fn describe(&self, config: &Config, inputs: &InputCache, input: InputId) -> String {
+        match self {
+            RelativeTo::Section { index } => {
+                let elf = inputs.get_elf(input);
+                match elf
+                    .section_headers
+                    .get(*index)
+                    .and_then(|header| elf.shdr_strtab.get_at(header.sh_name))
+                {
+                    Some(name) => format!("section {name}"),
+                    None => format!("section {index}"),
+                }
+            }
+            RelativeTo::Symbol(name) => format!("symbol {:?}", demangle::display(config, name)),
+        }
+    }
+}
+
+/// Extract relocations for a single section, sorted by patch offset.
+/// `bytes` is the whole input file, where `SHT_REL` sections keep the addend
+/// in the field being patched.
+pub fn extract(elf: &Elf, bytes: &[u8], target_section_index: u32) -> Vec<Relocate> {
+    let target = Target::from_machine(elf.header.e_machine);
+    let endian = if elf.little_endian {
+        Endian::Little
+    } else {
+        Endian::Big
+    };
+    let contents = elf
+        .section_headers
+        .get(target_section_index as usize)
+        .and_then(|header| bytes.get(header.file_range()?))
+        .unwrap_or_default();
+    let mut relocations: Vec<Relocate> = elf
+        .shdr_relocs
         .iter()
         .filter(|(ri, _)| elf.section_headers[*ri].sh_info == target_section_index)
-        .flat_map(|(_, reloc_section)| {
-            reloc_section.iter().map(|reloc| {
+        .flat_map(|(ri, reloc_section)| {
+            let rel = elf.section_headers[*ri].sh_type == SHT_REL;
+            reloc_section.iter().map(move |reloc| {
                 let sym = elf.syms.get(reloc.r_sym).unwrap();
                 let relative_to = if sym.st_info == STT_SECTION {
                     RelativeTo::Section {
@@ -59,15 +179,60 @@ pub fn extract(elf: &Elf, target_section_index: u32) -> Vec<Relocate> {
                     RelativeTo::Symbol(symname.to_owned())
                 };
 
+                let relative_offset = match (rel, target) {
+                    (true, Some(target)) => {
+                        let field = contents.get(reloc.r_offset as usize..).unwrap_or_default();
+                        implicit_addend(target, endian, reloc.r_type, field)
+                    }
+                    _ => reloc.r_addend.unwrap_or(0),
+                };
                 Relocate {
                     patch_offset: reloc.r_offset,
                     mode: reloc.r_type,
                     relative_to,
-                    relative_offset: reloc.r_addend.unwrap_or(0),
+                    relative_offset,
                 }
             })
         })
-        .collect()
+        .collect();
+    relocations.sort_by_key(|reloc| reloc.patch_offset);
+    relocations
+}
+
+/// Size of the field a relocation patches, and whether it's signed.
+/// `None` for instructions and unknown types.
+fn field_size(target: Target, mode: u32) -> Option<(usize, bool)> {
+    Some(match target {
+        Target::X86_64 => match mode {
+            R_X86_64_64 | R_X86_64_PC64 | R_X86_64_TPOFF64 => (8, true),
+            R_X86_64_PC32
+            | R_X86_64_PLT32
+            | R_X86_64_GOTPCREL
+            | R_X86_64_GOTPCRELX
+            | R_X86_64_REX_GOTPCRELX
+            | R_X86_64_32S
+            | R_X86_64_TPOFF32 => (4, true),
+            R_X86_64_32 => (4, false),
+            R_X86_64_PC16 => (2, true),
+            R_X86_64_16 => (2, false),
+            R_X86_64_PC8 => (1, true),
+            R_X86_64_8 => (1, false),
+            _ => return None,
+        },
+        Target::I386 => (4, true),
+        Target::Aarch64 => return None,
+    })
+}
+
+/// Addend of an `SHT_REL` relocation at the start of `bytes`, i.e. the value
+/// of the field being patched. Fields outside of the section are reported later.
+fn implicit_addend(target: Target, endian: Endian, mode: u32, bytes: &[u8]) -> i64 {
+    let Some((size, signed)) = field_size(target, mode) else {
+        return 0;
+    };
+    bytes
+        .get(..size)
+        .map_or(0, |field| read_int(field, signed, endian))
 }
 
 pub fn apply_relocations(
@@ -76,191 +241,419 @@ pub fn apply_relocations(
     linked: &mut LinkedProgram,
     globals: &HashMap<String, GlobalLocation>,
 ) -> anyhow::Result<()> {
-    let mut chunk_starts = linked
-        .iter_with_positions(config)
-        .map(|it| it.chunk_start)
-        .collect::<Vec<_>>()
-        .into_iter();
-
-    let mut anchors = resolve_relocation_symbols(config, inputs, linked, globals)?.into_iter();
-
-    for segment in linked.segments.iter_mut() {
-        for section in segment.sections.iter_mut() {
-            for chunk in section.chunks.iter_mut() {
-                let cs = chunk_starts.next().unwrap();
-
-                for reloc in chunk.clone().relocations.into_iter() {
-                    let RelocationComputed {
-                        relative_to: anchor,
-                        chunk_start: _,
-                        offset,
-                    } = anchors.next().unwrap();
-
-                    let resolved_address = config.base_addr.checked_add(anchor).unwrap();
-                    let patch_pos = reloc.patch_offset as usize;
-
-                    let backing_bytes =
-                        &inputs.get_backing_bytes(chunk.input)[chunk.range_in_input.clone()];
-
-                    // Patch
-                    // See: https://docs.rs/goblin/latest/goblin/elf/reloc/index.html
-                    match reloc.mode {
-                        R_X86_64_PC32 => {
-                            let final_value = anchor as i64 + reloc.relative_offset + offset as i64
-                                - cs as i64
-                                - patch_pos as i64;
-
-                            let final_value: i32 = final_value.try_into().expect("Overflow");
-
-                            assert_eq!(
-                                &backing_bytes[patch_pos..patch_pos + 4],
-                                &[0; 4],
-                                "Must only patch over zeroes"
-                            );
-                            chunk
-                                .patch(patch_pos, final_value.to_le_bytes().to_vec())
-                                .expect("Invalid patch");
-                        }
-                        R_X86_64_64 => {
-                            let final_value: u64 =
-                                (resolved_address as i64 + reloc.relative_offset) as u64;
-
-                            // println!(
-                            //     "APPLY RELOCATION {}: [{patch_pos:#08x}.._+8] = {final_value:#08x}",
-                            //     r_to_str(reloc.mode, EM_X86_64)
-                            // );
-
-                            assert_eq!(
-                                &backing_bytes[patch_pos..patch_pos + 8],
-                                &[0; 8],
-                                "Must only patch over zeroes"
-                            );
-                            chunk
-                                .patch(patch_pos, final_value.to_le_bytes().to_vec())
-                                .expect("Invalid patch");
-                        }
-                        _ => panic!(
-                            "Unknown relocation type: {}",
-                            r_to_str(reloc.mode, EM_X86_64)
-                        ),
-                    }
-                }
+    // Resolved relocation targets, one entry per chunk in layout order
+    let (resolved, got_addresses) = resolve_relocation_symbols(config, inputs, linked, globals)?;
+    got::fill(linked, config.endian, &got_addresses);
+    let tls_range = linked.tls_range(config);
+
+    // Relative relocations of a position-independent executable, as (place, value)
+    let mut relatives = Vec::new();
+    if config.pie {
+        for (address, (input, name)) in got_addresses.iter().zip(linked.got.slots()) {
+            let target = RelativeTo::Symbol(name.to_owned());
+            if !linked.pie.is_absolute(inputs, globals, input, &target) {
+                let slot = linked.got.slot_address(config, linked, name).unwrap();
+                relatives.push((slot, *address));
             }
         }
     }
 
-    debug_assert!(anchors.next().is_none());
+    let pie = &linked.pie;
+    let chunks = linked
+        .segments
+        .iter_mut()
+        .flat_map(|segment| segment.sections.iter_mut())
+        .flat_map(|section| section.chunks.iter_mut());
+
+    for (chunk, (cs, targets)) in chunks.zip(resolved) {
+        if chunk.relocations.is_empty() {
+            // E.g. position-independent code or plain data, nothing to patch
+            continue;
+        }
+        assert_eq!(
+            chunk.relocations.len(),
+            targets.len(),
+            "Every relocation must be resolved"
+        );
+
+        for (reloc, RelocationComputed { address }) in
+            chunk.relocations.clone().into_iter().zip(targets)
+        {
+            let patch_pos: usize = reloc.patch_offset.try_into()?;
+
+            // Final addresses of the symbol (S) and the patched location (P)
+            let symbol = address as i128;
+            let place = config.base_addr as i128 + cs as i128 + patch_pos as i128;
+            let machine = config.target.machine();
+            let endian = config.endian;
+            let addend = reloc.relative_offset as i128;
+            // Thread pointer relative offsets are negative, the block ends at the pointer
+            let thread_pointer = || {
+                let (_, range) = tls_range.clone().ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Relocation {} in {} refers to thread-local storage, but there are no TLS sections",
+                        r_to_str(reloc.mode, machine),
+                        inputs.description(chunk.input),
+                    )
+                })?;
+                anyhow::Ok(config.base_addr as i128 + range.end as i128)
+            };
+            let overflow = |value: i128| {
+                anyhow::anyhow!(
+                            "Relocation {} against {} at offset {patch_pos:#x} in {} overflows: {value:#x} doesn't fit the field",
+                            r_to_str(reloc.mode, machine),
+                            reloc.relative_to.describe(config, inputs, chunk.input),
+                            inputs.description(chunk.input),
+                        )
+            };
+
+            // Absolute references in a position-independent executable must move with it
+            if config.pie && !pie.is_absolute(inputs, globals, chunk.input, &reloc.relative_to) {
+                match reloc.mode {
+                    R_X86_64_64 => relatives.push((place as u64, (symbol + addend) as u64)),
+                    R_X86_64_32 | R_X86_64_32S | R_X86_64_16 | R_X86_64_8 => anyhow::bail!(
+                        "Relocation {} against {} in {} can't be used with -pie, recompile with -fPIE",
+                        r_to_str(reloc.mode, machine),
+                        reloc.relative_to.describe(config, inputs, chunk.input),
+                        inputs.description(chunk.input),
+                    ),
+                    _ => {}
+                }
+            }
 
+            // Patch
+            // See: https://docs.rs/goblin/latest/goblin/elf/reloc/index.html
+            let bytes = match config.target {
+                Target::X86_64 => match reloc.mode {
+                    R_X86_64_64 => ((symbol + addend) as u64).to_bytes(endian),
+                    // For GOT-relative relocations, the symbol is its GOT slot.
+                    // Without shared objects there is no PLT, so calls go directly.
+                    R_X86_64_PC32
+                    | R_X86_64_PLT32
+                    | R_X86_64_GOTPCREL
+                    | R_X86_64_GOTPCRELX
+                    | R_X86_64_REX_GOTPCRELX => {
+                        let value = symbol + addend - place;
+                        let value: i32 = value.try_into().map_err(|_| overflow(value))?;
+                        value.to_bytes(endian)
+                    }
+                    R_X86_64_PC64 => ((symbol + addend - place) as i64).to_bytes(endian),
+                    R_X86_64_32 => {
+                        let value = symbol + addend;
+                        let value: u32 = value.try_into().map_err(|_| overflow(value))?;
+                        value.to_bytes(endian)
+                    }
+                    R_X86_64_32S => {
+                        // Addresses are 64-bit, so the top half may be the sign extension
+                        let value = (symbol + addend) as i64 as i128;
+                        let value: i32 = value.try_into().map_err(|_| overflow(value))?;
+                        value.to_bytes(endian)
+                    }
+                    R_X86_64_16 => {
+                        let value = symbol + addend;
+                        let value: u16 = value.try_into().map_err(|_| overflow(value))?;
+                        value.to_bytes(endian)
+                    }
+                    R_X86_64_PC16 => {
+                        let value = symbol + addend - place;
+                        let value: i16 = value.try_into().map_err(|_| overflow(value))?;
+                        value.to_bytes(endian)
+                    }
+                    R_X86_64_8 => {
+                        let value = symbol + addend;
+                        let value: u8 = value.try_into().map_err(|_| overflow(value))?;
+                        value.to_bytes(endian)
+                    }
+                    R_X86_64_PC8 => {
+                        let value = symbol + addend - place;
+                        let value: i8 = value.try_into().map_err(|_| overflow(value))?;
+                        value.to_bytes(endian)
+                    }
+                    // Local-exec TLS, e.g. `mov %fs:sym@tpoff, %eax`
+                    R_X86_64_TPOFF32 => {
+                        let value = symbol + addend - thread_pointer()?;
+                        let value: i32 = value.try_into().map_err(|_| overflow(value))?;
+                        value.to_bytes(endian)
+                    }
+                    R_X86_64_TPOFF64 => {
+                        ((symbol + addend - thread_pointer()?) as i64).to_bytes(endian)
+                    }
+                    _ => panic!("Unknown relocation type: {}", r_to_str(reloc.mode, machine)),
+                },
+                Target::I386 => match reloc.mode {
+                    R_386_32 => {
+                        let value = symbol + addend;
+                        let value: u32 = value.try_into().map_err(|_| overflow(value))?;
+                        value.to_bytes(endian)
+                    }
+                    R_386_PC32 | R_386_PLT32 => {
+                        let value = symbol + addend - place;
+                        let value: i32 = value.try_into().map_err(|_| overflow(value))?;
+                        value.to_bytes(endian)
+                    }
+                    _ => panic!("Unknown relocation type: {}", r_to_str(reloc.mode, machine)),
+                },
+                Target::Aarch64 => {
+                    // Patches outside of the section are reported below.
+                    // Instructions are little-endian even on big-endian targets.
+                    let instruction = chunk
+                        .unpatched_bytes(inputs)
+                        .get(patch_pos..patch_pos + 4)
+                        .map_or(0, |b| u32::from_le_bytes(b.try_into().unwrap()));
+                    aarch64::patch(
+                        reloc.mode,
+                        endian,
+                        symbol,
+                        addend,
+                        place,
+                        instruction,
+                        overflow,
+                    )?
+                    .unwrap_or_else(|| {
+                        panic!("Unknown relocation type: {}", r_to_str(reloc.mode, machine))
+                    })
+                }
+            };
+
+            // With explicit addends, whatever the field holds is overwritten
+            patch_pos
+                .checked_add(bytes.len())
+                .filter(|end| *end as u64 <= chunk.size())
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Relocation {} at offset {patch_pos:#x} in {} is outside of its section",
+                        r_to_str(reloc.mode, machine),
+                        inputs.description(chunk.input),
+                    )
+                })?;
+            chunk.patch(patch_pos, bytes).expect("Invalid patch");
+        }
+    }
+
+    linked.pie.relocations = relatives;
     Ok(())
 }
 
 struct RelocationComputed {
-    relative_to: u64,
-    offset: u64,
-    chunk_start: u64,
+    /// Final virtual address of the symbol or section the relocation is relative to
+    address: u64,
 }
 
+/// Start of a chunk, relative to `base_addr`, and the targets of its relocations
+type ChunkTargets = (u64, Vec<RelocationComputed>);
+
 /// Resolve:
 /// * relative addresses used by the relocs
+/// * addresses stored in the GOT slots
+///
+/// All unresolvable symbols are collected and reported together.
 fn resolve_relocation_symbols(
     config: &Config,
     inputs: &InputCache,
     linked: &LinkedProgram,
     globals: &HashMap<String, GlobalLocation>,
-) -> anyhow::Result<Vec<RelocationComputed>> {
-    linked.iter_with_positions(config).map(|
-            ItChunk {
-                chunk,
-                chunk_start, ..
+) -> anyhow::Result<(Vec<ChunkTargets>, Vec<u64>)> {
+    let mut errors = Vec::new();
+    let mut result = Vec::new();
+    for ItChunk {
+        chunk, chunk_start, ..
+    } in linked.iter_with_positions(config)
+    {
+        let mut targets = Vec::with_capacity(chunk.relocations.len());
+        for reloc in &chunk.relocations {
+            match resolve_relocation(config, inputs, linked, globals, chunk.input, reloc) {
+                Ok(target) => targets.push(target),
+                Err(err) => errors.push(err),
             }
-        | chunk.relocations.iter().map(move |reloc| -> anyhow::Result<RelocationComputed> {
-        let (relative_to, offset) = match &reloc.relative_to {
-            RelativeTo::Section { index } => {
+        }
+        result.push((chunk_start, targets));
+    }
+
+    let mut got_addresses = Vec::new();
+    for (i, (input, name)) in linked.got.slots().enumerate() {
+        // Resolved like an absolute reference from the input using the slot
+        let reloc = Relocate {
+            patch_offset: i as u64 * 8,
+            mode: R_X86_64_64,
+            relative_to: RelativeTo::Symbol(name.to_owned()),
+            relative_offset: 0,
+        };
+        match resolve_relocation(config, inputs, linked, globals, input, &reloc) {
+            Ok(RelocationComputed { address }) => got_addresses.push(address),
+            Err(err) => errors.push(err),
+        }
+    }
+
+    match errors.len() {
+        0 => Ok((result, got_addresses)),
+        1 => Err(errors.remove(0)),
+        n => anyhow::bail!(
+            "{n} relocations could not be resolved:\n{}",
+            errors.iter().map(|err| format!("  {err}")).join("\n")
+        ),
+    }
+}
+
+fn resolve_relocation(
+    config: &Config,
+    inputs: &InputCache,
+    linked: &LinkedProgram,
+    globals: &HashMap<String, GlobalLocation>,
+    input: InputId,
+    reloc: &Relocate,
+) -> anyhow::Result<RelocationComputed> {
+    if reloc.uses_got() {
+        let RelativeTo::Symbol(name) = &reloc.relative_to else {
+            unreachable!("GOT relocations against sections are rejected when building the GOT");
+        };
+        let address = linked
+            .got
+            .slot_address(config, linked, name)
+            .expect("GOT slot missing");
+        return Ok(RelocationComputed { address });
+    }
+
+    let not_loaded = || {
+        anyhow::anyhow!(
+            "Relocation against {} in {} refers to a section that is not in any loadable segment",
+            reloc.relative_to.describe(config, inputs, input),
+            inputs.description(input)
+        )
+    };
+    let address = match &reloc.relative_to {
+        RelativeTo::Section { index } => {
+            // Sections of discarded COMDAT duplicates are replaced by the kept copy
+            let (input, index) = inputs.kept_section(input, *index).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Relocation against {} in {} refers to a discarded COMDAT group, and the kept copy has no such section",
+                    reloc.relative_to.describe(config, inputs, input),
+                    inputs.description(input)
+                )
+            })?;
+            if linked.merged.contains(input, index as u32) {
+                // The addend selects the string, which may have moved when merging.
+                // Resolve so that adding the addend back gives the merged copy.
+                // PC-relative addends point before the string by the bias.
+                let bias = reloc.pc_bias(config.target);
+                let offset: u64 = (reloc.relative_offset + bias).try_into().map_err(|_| {
+                    anyhow::anyhow!(
+                        "Relocation with negative addend {} into a merged string section in {}",
+                        reloc.relative_offset,
+                        inputs.description(input)
+                    )
+                })?;
+                let merged = linked
+                    .input_offset_address(input, index as u32, offset)
+                    .ok_or_else(not_loaded)?;
+                (config.base_addr + merged).wrapping_sub(offset)
+            } else {
                 // Get start of section at index of the current chunk file
-                let section_addr = lookup_input_section_addr(&linked, config, chunk.input, *index).expect("Couln't resolve section in index");
-                (section_addr, 0)
-            },
-            RelativeTo::Symbol(name) => {
-                let elf = inputs.get_elf(chunk.input);
-                let resolved = resolve_name(elf, &name)
-                    .ok_or_else(|| {
-                        anyhow::anyhow!( // TODO: file name lookup
-                            "Unable to resolve symbol {name:?} in {:?}",
-                            chunk.input
-                        )
-                    })?;
-
-                match resolved {
-                    NameResolved::Local(_) => {
-                        todo!("Get local symbol {name:?} of file {:?}", chunk.input) // TODO: file name lookup
-                    },
-                    NameResolved::Import => {
-                        let glob = globals.get(name.as_str()).ok_or_else(|| {
-                            anyhow::anyhow!(
-                                "Unable to resolve imported symbol {name:?} in {}",
-                                inputs.description(chunk.input)
-                            )
-                        })?;
-
-                        // Get position of symbol in glob.symtab_index of glob.file
-                        let import_elf = inputs.get_elf(glob.input);
-                        let sym = import_elf
-                            .syms
-                            .get(glob.symtab_index as usize)
-                            .expect("Missing symbol");
-
-                        // Binding rules are enforced when creating the global map, so no need to check here
-                        let section_index = sym.st_shndx;
-                        let offset = sym.st_value;
-
-                        if let Some(itc) = linked.iter_with_positions(config).find(|
-                            it
-                        | {
-                            it.chunk.input == glob.input && it.chunk.section_index == section_index as u32
-                        }) {
-                            let addr = itc.chunk_start;
-                            (addr, offset)
-                        } else {
-                            todo!( // TODO: convert .input to paths
-                                "Section with symtab index {} from {} was not included in segments, but it contains global {name:?} referenced by a relocation {:?} in {}",
-                                glob.symtab_index,
-                                inputs.description(glob.input),
+                let section_addr =
+                    lookup_input_section_addr(linked, input, index).ok_or_else(not_loaded)?;
+                config.base_addr + section_addr
+            }
+        }
+        RelativeTo::Symbol(name) => {
+            let elf = inputs.get_elf(input);
+            let resolved = resolve_name(elf, name).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Unable to resolve symbol {:?} in {}",
+                    demangle::display(config, name),
+                    inputs.description(input)
+                )
+            })?;
+
+            match resolved {
+                NameResolved::Local(symtab_index) => {
+                    let local = GlobalLocation::Symbol {
+                        input,
+                        symtab_index,
+                        binding: STB_LOCAL,
+                    };
+                    if let Some(stub) = linked.ifuncs.stub_address(config, linked, &local) {
+                        stub
+                    } else {
+                        global_address(config, inputs, linked, &local).ok_or_else(not_loaded)?
+                    }
+                }
+                NameResolved::Import => {
+                    let Some(glob) = globals.get(name.as_str()) else {
+                        // Undefined weak references are null when nothing defines them
+                        if is_weak_reference(elf, name) {
+                            return Ok(RelocationComputed { address: 0 });
+                        }
+                        anyhow::bail!(
+                            "Unable to resolve imported symbol {:?} in {}",
+                            demangle::display(config, name),
+                            inputs.description(input)
+                        );
+                    };
+
+                    // Binding rules are enforced when creating the global map, so no need to check here
+                    if config.check_symbol_bounds {
+                        if let Some(size) = symbol_size(inputs, glob) {
+                            check_symbol_bounds(
+                                config,
+                                inputs,
+                                input,
                                 reloc,
-                                inputs.description(chunk.input),
+                                &demangle::display(config, name),
+                                size,
                             );
                         }
-                    },
+                    }
+
+                    if let Some(stub) = linked.ifuncs.stub_address(config, linked, glob) {
+                        stub
+                    } else {
+                        global_address(config, inputs, linked, glob).ok_or_else(|| {
+                            anyhow::anyhow!(
+                                "Symbol {:?} defined in {} is not in any loadable segment, but it's referenced from {}",
+                                demangle::display(config, name),
+                                describe(inputs, glob),
+                                inputs.description(input)
+                            )
+                        })?
+                    }
                 }
-            },
-        };
+            }
+        }
+    };
+
+    Ok(RelocationComputed { address })
+}
 
+/// Warn if a relocation refers outside of the symbol it's relative to
+fn check_symbol_bounds(
+    config: &Config,
+    inputs: &InputCache,
+    input: InputId,
+    reloc: &Relocate,
+    name: &str,
+    size: u64,
+) {
+    if size == 0 {
+        // Size not known
+        return;
+    }
 
-        Ok(RelocationComputed { relative_to, chunk_start, offset })
-    })).flatten().collect::<anyhow::Result<Vec<_>>>()
+    // PC-relative references are biased by the field size, e.g. `call foo` has addend -4
+    let offset = reloc.relative_offset + reloc.pc_bias(config.target);
+    if offset < 0 || offset as u64 > size {
+        log::warn!(
+            "relocation {} at offset {:#x} in {} refers to offset {offset} of {name:?}, outside of its size {size}",
+            r_to_str(reloc.mode, config.target.machine()),
+            reloc.patch_offset,
+            inputs.description(input),
+        );
+    }
 }
 
 /// Resolve a final address for an input file section
-/// TODO: build a lookup table for these before
 fn lookup_input_section_addr(
     linked: &LinkedProgram,
-    config: &Config,
     input: InputId,
     section_index: usize,
 ) -> Option<u64> {
-    let mut addr = 0;
-    for segment in linked.segments.iter() {
-        for section in segment.sections.iter() {
-            addr = align_up(addr, section.alignment());
-            for chunk in section.chunks.iter() {
-                addr = align_up(addr, chunk.alignment);
-                if chunk.input == input && chunk.section_index == (section_index as u32) {
-                    return Some(addr);
-                }
-                addr += chunk.size();
-            }
-        }
-        addr = align_up(segment.size(), segment.alignment().max(config.page_size));
-    }
-
-    None
+    linked.input_section_start(input, section_index as u32)
 }
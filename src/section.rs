@@ -1,19 +1,26 @@
 use std::collections::{HashMap, HashSet};
-use std::io::Write;
+use std::io::{Read, Write};
 use std::ops::Range;
 
-use goblin::{
-    elf::Elf,
-    elf64::{header::*, reloc::*, section_header::*, sym::*},
-};
+use goblin::{elf::Elf, elf64::section_header::*};
 
 use crate::{
+    build_id,
+    common::{self, Commons},
     config::Config,
+    eh_frame, gc,
+    got::{self, Got},
+    icf::{self, Folded},
+    ifunc::{self, Ifuncs},
+    linker_script::{MemoryRegion, OutputSection},
     math::align_up,
+    merge::{self, MergedStrings},
+    name_resolution,
     open_files::{InputCache, InputId},
     permissions::Permissions,
+    pie::{self, Pie},
     relocation::{self, apply_relocations, Relocate},
-    GlobalLocation,
+    write_elf64, GlobalLocation, SectionTransform,
 };
 
 #[derive(Debug, Clone)]
@@ -29,15 +36,41 @@ pub enum InvalidPatch {
     NotInRange,
 }
 
+/// Where the bytes of a chunk come from
+#[derive(Debug, Clone)]
+pub enum ChunkSource {
+    /// Range of the input file
+    Input(Range<usize>),
+    /// Generated by the linker, e.g. the GOT
+    Generated(Vec<u8>),
+    /// Zero-initialized memory of this size without file contents (`SHT_NOBITS`)
+    Zeroed(u64),
+}
+
+/// Section indices of the chunks the linker generates, which are past any
+/// index an input file can use
+pub const GOT_SECTION_INDEX: u32 = u32::MAX;
+pub const EH_FRAME_HDR_SECTION_INDEX: u32 = u32::MAX - 1;
+pub const IPLT_SECTION_INDEX: u32 = u32::MAX - 2;
+pub const GOT_PLT_SECTION_INDEX: u32 = u32::MAX - 3;
+pub const RELA_PLT_SECTION_INDEX: u32 = u32::MAX - 4;
+pub const BUILD_ID_SECTION_INDEX: u32 = u32::MAX - 5;
+pub const RELA_DYN_SECTION_INDEX: u32 = u32::MAX - 6;
+pub const DYNAMIC_SECTION_INDEX: u32 = u32::MAX - 7;
+pub const COMMON_SECTION_INDEX: u32 = u32::MAX - 8;
+
 #[derive(Debug, Clone)]
 pub struct SectionChunk {
+    /// Origin file. For generated chunks, the first input they were generated for.
     pub input: InputId,
-    pub range_in_input: Range<usize>,
+    pub source: ChunkSource,
     /// Index of section in the origin file, used by relocations
     pub section_index: u32,
     /// Alignment, extracted from the section header
     pub alignment: u64,
     pub permissions: Permissions,
+    /// Fixed virtual address from the input section header (`sh_addr`)
+    pub address: Option<u64>,
     pub relocations: Vec<Relocate>,
     /// Patches generated from relocations
     /// Invariant: sorted
@@ -46,23 +79,63 @@ pub struct SectionChunk {
 
 impl SectionChunk {
     pub fn size(&self) -> u64 {
-        self.range_in_input.len() as u64
+        match &self.source {
+            ChunkSource::Input(range) => range.len() as u64,
+            ChunkSource::Generated(bytes) => bytes.len() as u64,
+            ChunkSource::Zeroed(size) => *size,
+        }
+    }
+
+    /// Occupies memory, but no space in the output file
+    pub fn is_nobits(&self) -> bool {
+        matches!(self.source, ChunkSource::Zeroed(_))
+    }
+
+    /// Chunk with contents generated by the linker
+    pub fn generated(
+        input: InputId,
+        section_index: u32,
+        alignment: u64,
+        permissions: Permissions,
+        bytes: Vec<u8>,
+    ) -> Self {
+        SectionChunk {
+            input,
+            source: ChunkSource::Generated(bytes),
+            section_index,
+            alignment,
+            permissions,
+            address: None,
+            relocations: Vec::new(),
+            patches: Vec::new(),
+        }
+    }
+
+    /// Contents before patching
+    pub fn unpatched_bytes<'a>(&'a self, inputs: &'a InputCache) -> &'a [u8] {
+        match &self.source {
+            ChunkSource::Input(range) => &inputs.get_backing_bytes(self.input)[range.clone()],
+            ChunkSource::Generated(bytes) => bytes,
+            ChunkSource::Zeroed(_) => panic!("Zero-initialized chunks have no file contents"),
+        }
     }
 
     pub fn patch(&mut self, at: usize, bytes: Vec<u8>) -> Result<(), InvalidPatch> {
-        if at + bytes.len() >= self.size() as usize {
+        let end = at.checked_add(bytes.len());
+        if end.is_none_or(|end| end > self.size() as usize) {
             return Err(InvalidPatch::NotInRange);
         }
 
-        if self.patches.is_empty() {
-            self.patches.push(Patch { offset: at, bytes });
-            return Ok(());
-        }
+        // Relocations are sorted by offset, so usually this is an append
+        let index = match self.patches.last() {
+            Some(last) if last.offset < at => self.patches.len(),
+            _ => self.patches.partition_point(|p| p.offset < at),
+        };
 
-        let index = self.patches.partition_point(|p| p.offset < at);
-        let prev = &self.patches[index - 1];
-        if prev.offset + prev.bytes.len() >= at {
-            return Err(InvalidPatch::Overlapping);
+        if let Some(prev) = index.checked_sub(1).map(|i| &self.patches[i]) {
+            if prev.offset + prev.bytes.len() > at {
+                return Err(InvalidPatch::Overlapping);
+            }
         }
 
         if let Some(next) = self.patches.get(index) {
@@ -71,8 +144,13 @@ impl SectionChunk {
             }
         }
 
-        self.patches.push(Patch { offset: at, bytes });
-        return Ok(());
+        self.patches.insert(index, Patch { offset: at, bytes });
+        Ok(())
+    }
+
+    /// Patches applied so far, as (offset in chunk, patched bytes), in offset order
+    pub fn patches(&self) -> impl Iterator<Item = (usize, &[u8])> + '_ {
+        self.patches.iter().map(|p| (p.offset, p.bytes.as_slice()))
     }
 
     /// Write all patched bytes into a writer
@@ -81,7 +159,16 @@ impl SectionChunk {
         inputs: &InputCache,
         target: &mut T,
     ) -> std::io::Result<()> {
-        let bytes = &inputs.get_backing_bytes(self.input)[self.range_in_input.clone()];
+        if let ChunkSource::Zeroed(size) = self.source {
+            // Shares a section with file-backed chunks, so it must be written out
+            assert!(
+                self.patches.is_empty(),
+                "Zero-initialized chunks can't be patched"
+            );
+            return std::io::copy(&mut std::io::repeat(0).take(size), target).map(|_| ());
+        }
+
+        let bytes = self.unpatched_bytes(inputs);
         let mut cursor = 0;
         for patch in &self.patches {
             target.write_all(&bytes[cursor..patch.offset])?;
@@ -92,13 +179,28 @@ impl SectionChunk {
     }
 }
 
-fn build_section_from(input: InputId, elf: &Elf, section_name: &str) -> Vec<SectionChunk> {
+fn build_section_from(
+    input: InputId,
+    elf: &Elf,
+    bytes: &[u8],
+    section_name: &str,
+) -> Vec<SectionChunk> {
     let mut result = Vec::new();
 
     for (i, section) in elf.section_headers.iter().enumerate() {
         let section_index: u32 = i.try_into().expect("Session header index overflow");
 
-        if section.sh_type != SHT_PROGBITS {
+        let source = match section.sh_type {
+            // `SHT_X86_64_UNWIND` is the type of `.eh_frame` on x86-64
+            SHT_PROGBITS | SHT_NOTE | SHT_INIT_ARRAY | SHT_FINI_ARRAY | SHT_X86_64_UNWIND => {
+                ChunkSource::Input(section.file_range().unwrap())
+            }
+            SHT_NOBITS => ChunkSource::Zeroed(section.sh_size),
+            _ => continue,
+        };
+
+        // Non-allocated sections, e.g. `.comment`, don't take space in memory
+        if section.sh_flags & (SHF_ALLOC as u64) == 0 {
             continue;
         }
 
@@ -107,14 +209,10 @@ fn build_section_from(input: InputId, elf: &Elf, section_name: &str) -> Vec<Sect
                 continue;
             }
 
-            assert_eq!(
-                section.sh_addr, 0,
-                "TODO: Fixed-addess blocks are not supported"
-            );
-
+            let relocations = relocation::extract(elf, bytes, section_index);
             result.push(SectionChunk {
                 input,
-                range_in_input: section.file_range().unwrap(),
+                source,
                 section_index,
                 alignment: section.sh_addralign,
                 permissions: Permissions {
@@ -122,8 +220,9 @@ fn build_section_from(input: InputId, elf: &Elf, section_name: &str) -> Vec<Sect
                     write: (section.sh_flags as u32) & SHF_WRITE != 0,
                     execute: (section.sh_flags as u32) & SHF_EXECINSTR != 0,
                 },
-                relocations: relocation::extract(elf, section_index),
-                patches: Vec::new(),
+                address: (section.sh_addr != 0).then_some(section.sh_addr),
+                patches: Vec::with_capacity(relocations.len()),
+                relocations,
             });
         }
     }
@@ -139,18 +238,24 @@ fn build_section_group(
 
     for input_id in inputs.iter_ids() {
         let elf = inputs.get_elf(input_id);
-        let addition = build_section_from(input_id, elf, section_name);
-        section.extend(addition);
+        let bytes = inputs.get_backing_bytes(input_id);
+        let addition = build_section_from(input_id, elf, bytes, section_name);
+        section.extend(
+            addition
+                .into_iter()
+                .filter(|chunk| !inputs.is_discarded(input_id, chunk.section_index as usize)),
+        );
     }
 
     Ok(section)
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Section {
     pub name: String,
     pub chunks: Vec<SectionChunk>,
-    pub permissions: Permissions,
+    /// Fixed virtual address from the linker script
+    pub address: Option<u64>,
 }
 impl Section {
     pub fn permissions(&self) -> Permissions {
@@ -165,6 +270,11 @@ impl Section {
         self.chunks.iter().map(|c| c.alignment).max().unwrap_or(0)
     }
 
+    /// Occupies memory, but no space in the output file, like `.bss`
+    pub fn is_nobits(&self) -> bool {
+        !self.chunks.is_empty() && self.chunks.iter().all(|c| c.is_nobits())
+    }
+
     pub fn size(&self) -> u64 {
         let mut result = 0;
         for chunk in &self.chunks {
@@ -176,7 +286,7 @@ impl Section {
 }
 
 /// Segments are the actual loadable regions, specified in the program header.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Segment {
     pub sections: Vec<Section>,
 }
@@ -199,52 +309,214 @@ impl Segment {
         result
     }
 
-    pub fn permissions(&self) -> Permissions {
-        // All sections in a segment share their permissions
+    /// Size of the part backed by file contents. Sections without file
+    /// contents are placed last, so the rest is a prefix of the segment.
+    pub fn file_size(&self) -> u64 {
+        let mut result = 0;
+        for section in self.file_sections() {
+            result = align_up(result, section.alignment());
+            result += section.size();
+        }
+        result
+    }
+
+    /// Sections that have contents in the output file
+    pub fn file_sections(&self) -> impl Iterator<Item = &Section> + '_ {
+        self.sections.iter().take_while(|s| !s.is_nobits())
+    }
+
+    /// Thread-local data template, see [`is_tls_section`]
+    pub fn is_tls(&self) -> bool {
         self.sections
             .first()
-            .map(|s| s.permissions())
-            .unwrap_or_default()
+            .is_some_and(|section| is_tls_section(&section.name))
+    }
+
+    /// Fixed virtual address of the first section, from the linker script or `sh_addr`
+    pub fn fixed_address(&self) -> Option<u64> {
+        self.sections.first()?.address
+    }
+
+    pub fn permissions(&self) -> Permissions {
+        // Sections in a segment usually share their permissions,
+        // but e.g. `--no-rosegment` merges read-only data into code.
+        // Empty sections may end up in any segment, so they don't count.
+        let mut perm = Permissions {
+            read: true,
+            write: false,
+            execute: false,
+        };
+        for section in self.sections.iter().filter(|s| s.size() != 0) {
+            perm.relax(section.permissions());
+        }
+        perm
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct LinkedProgram {
     pub segments: Vec<Segment>,
+    pub got: Got,
+    pub ifuncs: Ifuncs,
+    pub pie: Pie,
+    pub commons: Commons,
+    pub merged: MergedStrings,
+    pub folded: Folded,
+    /// Start of each chunk by (input, section index), relative to `config.base_addr`.
+    /// Filled by [`Self::index_chunks`] once the layout is final.
+    chunk_starts: HashMap<(InputId, u32), u64>,
 }
 impl LinkedProgram {
-    pub fn segment_sizes(&self, config: &Config) -> impl Iterator<Item = u64> + '_ {
-        let alignment = config.segment_file_align;
+    /// Record the chunk positions, so that resolving relocations and symbols
+    /// doesn't need to walk the whole layout each time
+    pub fn index_chunks(&mut self, config: &Config) {
+        let mut chunk_starts = HashMap::new();
+        for it in self.iter_with_positions(config) {
+            chunk_starts
+                .entry((it.chunk.input, it.chunk.section_index))
+                .or_insert(it.chunk_start);
+        }
+        self.chunk_starts = chunk_starts;
+    }
+
+    /// Start of the chunk of an input section, relative to `config.base_addr`,
+    /// or `None` if the section is not included in the output
+    pub fn input_section_start(&self, input: InputId, section_index: u32) -> Option<u64> {
+        let (input, section_index) = self.folded.resolve(input, section_index);
+        self.chunk_starts.get(&(input, section_index)).copied()
+    }
+
+    /// Final address of an offset in an input section, relative to `config.base_addr`,
+    /// or `None` if the section is not included in the output
+    pub fn input_offset_address(
+        &self,
+        input: InputId,
+        section_index: u32,
+        offset: u64,
+    ) -> Option<u64> {
+        let (input, section_index, offset) = self.merged.resolve(input, section_index, offset);
+        Some(self.input_section_start(input, section_index)? + offset)
+    }
+
+    /// File offset of each segment, followed by the end of the last one.
+    /// Each offset is congruent to the final segment address modulo the page size,
+    /// which neither fixed addresses nor the base address keep page-aligned.
+    pub fn segment_offsets(&self, config: &Config) -> Vec<u64> {
+        let starts = self.segment_starts(config);
+        let mut end = write_elf64::headers_size(config, self);
+        let mut result = Vec::with_capacity(self.segments.len() + 1);
+        for (i, (segment, start)) in self.segments.iter().zip(starts).enumerate() {
+            let address = config.base_addr + start;
+            let offset = if i == 0 && self.headers_mapped() {
+                // Mapped along with the headers at file offset 0
+                address - self.headers_address(config)
+            } else {
+                end + (address.wrapping_sub(end) % config.page_size)
+            };
+            result.push(offset);
+            end = offset + align_up(segment.file_size(), config.segment_file_align);
+        }
+        result.push(end);
+        result
+    }
+
+    /// The TLS segment, as its index and address range relative to `config.base_addr`.
+    /// The range end is aligned, as the thread pointer points there on x86-64.
+    pub fn tls_range(&self, config: &Config) -> Option<(usize, Range<u64>)> {
+        let index = self.segments.iter().position(|s| s.is_tls())?;
+        let segment = &self.segments[index];
+        let start = self.segment_starts(config)[index];
+        Some((
+            index,
+            start..start + align_up(segment.size(), segment.alignment()),
+        ))
+    }
+
+    /// The file and program headers are mapped by the first segment, unless the
+    /// linker script moves it to a fixed address
+    pub fn headers_mapped(&self) -> bool {
+        self.segments
+            .first()
+            .is_none_or(|segment| segment.fixed_address().is_none())
+    }
+
+    /// Address of the mapped headers. They are at file offset 0, so that's the
+    /// page boundary at or below the base address, for the address to be congruent.
+    pub fn headers_address(&self, config: &Config) -> u64 {
+        config.base_addr - config.base_addr % config.page_size
+    }
+
+    /// Virtual address range of the `PT_LOAD` of each segment, which for the
+    /// first one includes the headers when they are mapped
+    pub fn load_ranges(&self, config: &Config) -> Vec<Range<u64>> {
+        let starts = self.segment_starts(config);
         self.segments
             .iter()
-            .map(move |s| align_up(s.size(), alignment))
+            .zip(starts)
+            .enumerate()
+            .map(|(i, (segment, start))| {
+                let end = config.base_addr + start + segment.size();
+                if i == 0 && self.headers_mapped() {
+                    self.headers_address(config)..end
+                } else {
+                    config.base_addr + start..end
+                }
+            })
+            .collect()
+    }
+
+    /// Start address of each segment, relative to `config.base_addr`.
+    /// The file and program headers are mapped by the first segment,
+    /// so its contents start after them. The alignment applies to the final
+    /// address, as the base address is only page-aligned.
+    pub fn segment_starts(&self, config: &Config) -> Vec<u64> {
+        let base = config.base_addr;
+        self.segments
+            .iter()
+            .enumerate()
+            .scan(
+                write_elf64::headers_size(config, self),
+                |addr, (si, segment)| {
+                    if si > 0 {
+                        *addr += self.segments[si - 1].size();
+                        let alignment = segment.alignment().max(config.page_size);
+                        *addr = align_up(base + *addr, alignment) - base;
+                        if config.guard_pages {
+                            // Unmapped gap, so that overrunning a segment faults
+                            *addr += config.page_size;
+                        }
+                    } else {
+                        *addr = align_up(base + *addr, segment.alignment()) - base;
+                    }
+                    // Checked against the previous segment in `check_segment_overlaps`
+                    if let Some(fixed) = segment.fixed_address() {
+                        *addr = fixed - config.base_addr;
+                    }
+                    Some(*addr)
+                },
+            )
+            .collect()
     }
 
     pub fn iter_with_positions<'a>(
         &'a self,
         config: &'a Config,
     ) -> impl Iterator<Item = ItChunk<'a>> {
+        let base = config.base_addr;
         self.segments
             .iter()
+            .zip(self.segment_starts(config))
             .enumerate()
-            .scan(0u64, |addr, (si, segment)| {
-                if si > 0 {
-                    *addr += self.segments[si - 1].size();
-                    *addr = align_up(*addr, segment.alignment().max(config.page_size));
-                }
-                Some((*addr, si, segment))
-            })
-            .flat_map(|(segment_start, segment_index, segment)| {
+            .flat_map(move |(segment_index, (segment, segment_start))| {
                 segment
                     .sections
                     .iter()
                     .enumerate()
-                    .scan(segment_start, |addr, (si, section)| {
-                        *addr = align_up(*addr, section.alignment());
+                    .scan(segment_start, move |addr, (si, section)| {
                         if si > 0 {
                             *addr += segment.sections[si - 1].size();
-                            *addr = align_up(*addr, section.alignment());
                         }
+                        *addr = align_up(base + *addr, section.alignment()) - base;
                         Some((*addr, si, section))
                     })
                     .flat_map(move |(section_start, section_index, section)| {
@@ -252,12 +524,11 @@ impl LinkedProgram {
                             .chunks
                             .iter()
                             .enumerate()
-                            .scan(section_start, |addr, (si, chunk)| {
-                                *addr = align_up(*addr, chunk.alignment);
+                            .scan(section_start, move |addr, (si, chunk)| {
                                 if si > 0 {
                                     *addr += section.chunks[si - 1].size();
-                                    *addr = align_up(*addr, chunk.alignment);
                                 }
+                                *addr = align_up(base + *addr, chunk.alignment) - base;
                                 Some((*addr, si, chunk))
                             })
                             .map(move |(chunk_start, chunk_index, chunk)| ItChunk {
@@ -288,6 +559,77 @@ pub struct ItChunk<'a> {
     pub chunk_start: u64,
 }
 
+/// Map a final virtual address back to the input it came from.
+/// Returns the input, the section name and the offset within that input section.
+pub fn provenance<'a>(
+    linked: &'a LinkedProgram,
+    config: &'a Config,
+    vaddr: u64,
+) -> Option<(InputId, &'a str, u64)> {
+    let addr = vaddr.checked_sub(config.base_addr)?;
+    linked
+        .iter_with_positions(config)
+        .find(|it| it.chunk_start <= addr && addr < it.chunk_start + it.chunk.size())
+        .map(|it| {
+            (
+                it.chunk.input,
+                it.section.name.as_str(),
+                addr - it.chunk_start,
+            )
+        })
+}
+
+/// Thread-local data and zero-initialized data (`SHF_TLS`). The loaded sections are
+/// only a template, copied to a separate block for each thread.
+pub fn is_tls_section(name: &str) -> bool {
+    [".tdata", ".tbss"]
+        .iter()
+        .any(|tls| name == *tls || name.starts_with(&format!("{tls}.")))
+}
+
+/// Arrays of function pointers called before and after `main`. Each gets
+/// `__NAME_start` and `__NAME_end` symbols, and is a garbage collection root.
+pub const INIT_FINI_ARRAYS: [&str; 2] = [".init_array", ".fini_array"];
+
+/// Move each input section into the first output section with a matching pattern
+fn place_output_sections<'a>(
+    inputs: &InputCache,
+    names: &[&'a String],
+    outputs: &[OutputSection],
+    memory: &[MemoryRegion],
+    placed: &mut HashSet<&'a str>,
+    result: &mut Vec<Section>,
+) -> anyhow::Result<()> {
+    let mut regions_used = HashSet::new();
+    for (index, output) in outputs.iter().enumerate() {
+        let mut chunks = Vec::new();
+        for name in names {
+            if placed.contains(name.as_str()) {
+                continue;
+            }
+            if outputs.iter().position(|o| o.matches(name)) == Some(index) {
+                chunks.extend(build_section_group(inputs, name)?);
+                placed.insert(name.as_str());
+            }
+        }
+        // Empty output sections are dropped, like other linkers do
+        if !chunks.is_empty() {
+            let mut address = output.address;
+            if let Some(region) = output.region {
+                if regions_used.insert(region) && address.is_none() {
+                    address = Some(memory[region].origin);
+                }
+            }
+            result.push(Section {
+                name: output.name.clone(),
+                chunks,
+                address,
+            });
+        }
+    }
+    Ok(())
+}
+
 /// Combine sections from different codegen units
 pub fn combine_sections(
     config: &Config,
@@ -297,38 +639,104 @@ pub fn combine_sections(
     let build_section_by_name = |section_name: &str| -> anyhow::Result<Section> {
         Ok(Section {
             name: section_name.to_owned(),
-            chunks: build_section_group(&inputs, section_name)?,
-            permissions: Permissions::default(),
+            chunks: build_section_group(inputs, section_name)?,
+            address: None,
         })
     };
 
-    // TODO: what about unnamed sections?
-
     let mut result: Vec<Section> = Vec::new();
-    for group_name in [".entry", ".text", ".rodata"] {
-        // exact match first
-        if section_names.contains(group_name) {
-            result.push(build_section_by_name(group_name)?);
+    let mut placed: HashSet<&str> = HashSet::new();
+    let mut names: Vec<&String> = section_names.iter().collect();
+    names.sort();
+
+    // Linker script output sections come first, in script order
+    if let Some(script) = &config.script {
+        place_output_sections(
+            inputs,
+            &names,
+            &script.sections,
+            &script.memory,
+            &mut placed,
+            &mut result,
+        )?;
+    }
+    place_output_sections(
+        inputs,
+        &names,
+        &config.output_sections,
+        &[],
+        &mut placed,
+        &mut result,
+    )?;
+
+    // Constructor and destructor pointers, which must be contiguous. Fragments with
+    // a priority suffix, like `.init_array.00100`, come first in priority order.
+    for array_name in INIT_FINI_ARRAYS {
+        let prefix = &format!("{array_name}.");
+        let mut fragments: Vec<&String> = section_names
+            .iter()
+            .filter(|name| *name == array_name || name.starts_with(prefix))
+            .filter(|name| !placed.contains(name.as_str()))
+            .collect();
+        fragments.sort_by_key(|name| {
+            let priority: Option<u64> = name
+                .strip_prefix(prefix)
+                .map(|priority| priority.parse().unwrap_or(u64::MAX));
+            (priority.is_none(), priority, name.as_str())
+        });
+
+        let mut chunks = Vec::new();
+        for name in fragments {
+            chunks.extend(build_section_group(inputs, name)?);
+            placed.insert(name);
         }
+        if !chunks.is_empty() {
+            result.push(Section {
+                name: array_name.to_owned(),
+                chunks,
+                address: None,
+            });
+        }
+    }
 
-        // exact match first
-        let prefix = &format!("{group_name}.");
-        for section in section_names {
-            if section.starts_with(prefix) {
-                result.push(build_section_by_name(section)?);
-            }
+    // Orphans: sections with unfamiliar names, including unnamed ones. Their permissions
+    // come from the section flags, so they end up in the matching segment regardless
+    // of the name, after the familiar sections there.
+    let orphans: Vec<&String> = names
+        .into_iter()
+        .filter(|name| !placed.contains(name.as_str()))
+        .collect();
+    for name in orphans {
+        let section = build_section_by_name(name)?;
+        // Only non-allocated sections with this name, nothing to load
+        if !section.chunks.is_empty() {
+            result.push(section);
         }
     }
 
+    // Input sections with a fixed address become output sections of their own
+    let mut fixed = Vec::new();
+    for section in &mut result {
+        for chunk in section.chunks.drain_filter(|chunk| chunk.address.is_some()) {
+            fixed.push(Section {
+                name: section.name.clone(),
+                address: chunk.address,
+                chunks: vec![chunk],
+            });
+        }
+    }
+    result.retain(|section| !section.chunks.is_empty());
+    result.extend(fixed);
+
     Ok(result)
 }
 
 /// Combines sections to segments, so that those with same permissions stay together.
 /// Segments are returned in sorted order, and the resulting value is essentially
-/// the loadable portion of the ELF file, excluding the BSS segment.
+/// the loadable portion of the ELF file. Zero-initialized sections like `.bss`
+/// are placed at the end of their segment, so they don't need file space.
 pub fn sections_to_segments(
     config: &Config,
-    inputs: &InputCache,
     mut sections: Vec<Section>,
 ) -> anyhow::Result<LinkedProgram> {
     // TODO: configurable segment/section order and grouping
@@ -357,29 +765,350 @@ pub fn sections_to_segments(
         },
     ];
 
+    // Read-only data can share the executable segment
+    let segment_permissions = |section: &Section| {
+        let mut perms = section.permissions();
+        if config.no_rosegment && !perms.write {
+            perms.execute = true;
+        }
+        perms
+    };
+
+    // Thread-local sections get a segment of their own, for `PT_TLS`
+    let tls: Vec<Section> = sections.drain_filter(|s| is_tls_section(&s.name)).collect();
+
     let segments: Vec<_> = order
         .into_iter()
         .map(|perms| Segment {
             sections: sections
-                .drain_filter(|s| s.permissions() == perms)
+                .drain_filter(|s| segment_permissions(s) == perms)
                 .collect(),
         })
         .filter(|segment| !segment.sections.is_empty())
         .collect();
 
     assert!(sections.is_empty(), "Uncollected sections");
-    Ok(LinkedProgram { segments })
+    if segments.is_empty() && tls.is_empty() {
+        anyhow::bail!("Nothing to link, as no input has loadable sections");
+    }
+
+    // Sections at a fixed address start a segment, right after the one they came from
+    let segments: Vec<Segment> = segments
+        .into_iter()
+        .flat_map(|mut segment| {
+            let fixed: Vec<Section> = segment
+                .sections
+                .drain_filter(|s| s.address.is_some())
+                .collect();
+            let mut fixed: Vec<Segment> = fixed
+                .into_iter()
+                .map(|section| Segment {
+                    sections: vec![section],
+                })
+                .collect();
+            fixed.sort_by_key(|s| s.fixed_address());
+            std::iter::once(segment)
+                .filter(|s| !s.sections.is_empty())
+                .chain(fixed)
+        })
+        .collect();
+
+    // A segment of only empty sections would be a `PT_LOAD` without memory at the
+    // address of the next one, so they join a neighbouring segment instead.
+    // A fixed address stays on the first section of its segment.
+    let mut merged: Vec<Segment> = Vec::new();
+    for mut segment in segments {
+        match merged.last_mut() {
+            Some(last) if segment.size() == 0 && segment.fixed_address().is_none() => {
+                last.sections.append(&mut segment.sections);
+            }
+            Some(last) if last.size() == 0 && last.fixed_address().is_none() => {
+                segment.sections.append(&mut last.sections);
+                *last = segment;
+            }
+            _ => merged.push(segment),
+        }
+    }
+    for segment in &mut merged {
+        segment
+            .sections
+            .sort_by_key(|s| (s.address.is_none(), s.is_nobits()));
+    }
+    let mut segments = merged;
+
+    // Before the rest of the writable data, like other linkers place it
+    if !tls.is_empty() {
+        let mut segment = Segment { sections: tls };
+        segment.sections.sort_by_key(|s| s.is_nobits());
+        let index = segments
+            .iter()
+            .position(|s| s.permissions().write)
+            .unwrap_or(segments.len());
+        segments.insert(index, segment);
+    }
+
+    // `.entry` must be the first thing in the image, even if another
+    // segment or a more strictly aligned section would otherwise precede it
+    let entry_segment = segments
+        .iter()
+        .position(|segment| segment.sections.iter().any(|s| s.name == ".entry"));
+    if let Some(index) = entry_segment {
+        let mut segment = segments.remove(index);
+        let entry = segment
+            .sections
+            .iter()
+            .position(|s| s.name == ".entry")
+            .unwrap();
+        let section = segment.sections.remove(entry);
+        segment.sections.insert(0, section);
+        segments.insert(0, segment);
+    }
+
+    if config.sort_by_address {
+        segments = sort_by_address(config, segments);
+    }
+
+    let linked = LinkedProgram {
+        segments,
+        got: Got::default(),
+        ifuncs: Ifuncs::default(),
+        pie: Pie::default(),
+        commons: Commons::default(),
+        merged: MergedStrings::default(),
+        folded: Folded::default(),
+        chunk_starts: HashMap::new(),
+    };
+    check_fixed_addresses(config, &linked)?;
+    Ok(linked)
 }
 
+/// Reorder the segments by address (`--sort-by-address`), reporting each inversion.
+/// A segment at a fixed address moves along with the ones placed after it.
+fn sort_by_address(config: &Config, segments: Vec<Segment>) -> Vec<Segment> {
+    let mut runs: Vec<Vec<Segment>> = Vec::new();
+    for segment in segments {
+        match runs.last_mut() {
+            Some(run) if segment.fixed_address().is_none() => run.push(segment),
+            _ => runs.push(vec![segment]),
+        }
+    }
+    // Fixed addresses are at least the base address, where the first run starts otherwise
+    let address = |run: &Vec<Segment>| run[0].fixed_address().unwrap_or(config.base_addr);
+    for pair in runs.windows(2) {
+        let (a, b) = (address(&pair[0]), address(&pair[1]));
+        if b < a {
+            log::warn!(
+                "{} at {b:#x} is placed after {} at {a:#x}, moving it before",
+                pair[1][0].sections[0].name,
+                pair[0][0].sections[0].name,
+            );
+        }
+    }
+    runs.sort_by_key(address);
+    runs.into_iter().flatten().collect()
+}
+
+/// Sections placed at a fixed address, by the linker script or `sh_addr`, start
+/// a segment, and the segments after it are placed after it. Overlaps are
+/// reported by `check_segment_overlaps`.
+fn check_fixed_addresses(config: &Config, linked: &LinkedProgram) -> anyhow::Result<()> {
+    for segment in &linked.segments {
+        for section in &segment.sections {
+            let Some(address) = section.address else {
+                continue;
+            };
+            if address % section.alignment().max(1) != 0 {
+                anyhow::bail!(
+                    "Fixed address {address:#x} of {} is not aligned to its alignment {:#x}",
+                    section.name,
+                    section.alignment(),
+                );
+            }
+            if address < config.base_addr {
+                anyhow::bail!(
+                    "Fixed address {address:#x} of {} is below the base address {:#x}",
+                    section.name,
+                    config.base_addr,
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Output sections placed in a memory region with `> REGION` must fit in it
+fn check_memory_regions(config: &Config, linked: &LinkedProgram) -> anyhow::Result<()> {
+    let Some(script) = &config.script else {
+        return Ok(());
+    };
+    for output in &script.sections {
+        let Some(region) = output.region.map(|index| &script.memory[index]) else {
+            continue;
+        };
+        let Some(it) = linked
+            .iter_with_positions(config)
+            .find(|it| it.chunk_index == 0 && it.section.name == output.name)
+        else {
+            continue;
+        };
+        let start = config.base_addr + it.section_start;
+        let end = start + it.section.size();
+        let region_end = region.origin + region.length;
+        if start < region.origin || end > region_end {
+            anyhow::bail!(
+                "Section {} at {start:#x}..{end:#x} overflows memory region {} at {:#x}..{region_end:#x}",
+                output.name,
+                region.name,
+                region.origin,
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Address ranges of the `PT_LOAD`s must be disjoint and, as the ELF
+/// specification requires, in ascending order
+fn check_segment_overlaps(config: &Config, linked: &LinkedProgram) -> anyhow::Result<()> {
+    let ranges: Vec<(Range<u64>, &str)> = linked
+        .load_ranges(config)
+        .into_iter()
+        .zip(&linked.segments)
+        .filter(|(range, _)| !range.is_empty())
+        .map(|(range, segment)| {
+            let name = segment.sections.first().map_or("", |s| s.name.as_str());
+            (range, name)
+        })
+        .collect();
+    for pair in ranges.windows(2) {
+        let [(a, a_name), (b, b_name)] = pair else {
+            unreachable!()
+        };
+        if b.end <= a.start {
+            anyhow::bail!(
+                "Segment starting with {b_name} at {:#x}..{:#x} is below the one before it, starting with {a_name} at {:#x}..{:#x}; --sort-by-address orders segments by address",
+                b.start,
+                b.end,
+                a.start,
+                a.end,
+            );
+        }
+        if b.start < a.end {
+            anyhow::bail!(
+                "Segment starting with {b_name} at {:#x}..{:#x} overlaps the one before it, starting with {a_name} at {:#x}..{:#x}",
+                b.start,
+                b.end,
+                a.start,
+                a.end,
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Replace the contents of input sections with those given by the transforms
+fn transform_sections(
+    inputs: &InputCache,
+    transforms: &[Box<dyn SectionTransform>],
+    sections: &mut [Section],
+) -> anyhow::Result<()> {
+    for section in sections {
+        for chunk in &mut section.chunks {
+            if chunk.is_nobits() {
+                continue;
+            }
+            for transform in transforms {
+                let description = inputs.description(chunk.input);
+                let bytes = chunk.unpatched_bytes(inputs);
+                let Some(bytes) = transform.transform(&section.name, &description, bytes) else {
+                    continue;
+                };
+                if let Some(reloc) = chunk
+                    .relocations
+                    .iter()
+                    .find(|reloc| reloc.offset() >= bytes.len() as u64)
+                {
+                    anyhow::bail!(
+                        "Transformed {} from {description} is {:#x} bytes, but has a relocation at offset {:#x}",
+                        section.name,
+                        bytes.len(),
+                        reloc.offset(),
+                    );
+                }
+                chunk.source = ChunkSource::Generated(bytes);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Collect, lay out and relocate the input sections
 pub fn build(
     config: &Config,
     inputs: &InputCache,
     section_names: &HashSet<String>,
-    globals: &HashMap<String, GlobalLocation>,
+    transforms: &[Box<dyn SectionTransform>],
+    globals: &mut HashMap<String, GlobalLocation>,
 ) -> anyhow::Result<LinkedProgram> {
-    let sections = combine_sections(config, inputs, section_names)?;
-    let mut linked = sections_to_segments(config, inputs, sections)?;
-    // TODO: dead code elimination
-    apply_relocations(config, inputs, &mut linked, globals)?;
+    let mut linked = lay_out(config, inputs, section_names, transforms, globals)?;
+    relocate(config, inputs, &mut linked, globals)?;
     Ok(linked)
 }
+
+/// Collect the input sections and fix their addresses, defining
+/// the symbols derived from the layout
+pub fn lay_out(
+    config: &Config,
+    inputs: &InputCache,
+    section_names: &HashSet<String>,
+    transforms: &[Box<dyn SectionTransform>],
+    globals: &mut HashMap<String, GlobalLocation>,
+) -> anyhow::Result<LinkedProgram> {
+    let mut sections = combine_sections(config, inputs, section_names)?;
+    if config.gc_sections {
+        gc::collect_garbage(config, inputs, globals, &mut sections)?;
+    }
+    let commons = common::build(inputs, globals, &mut sections);
+    let merged = merge::merge_strings(config, inputs, &mut sections);
+    transform_sections(inputs, transforms, &mut sections)?;
+    let folded = icf::fold(config, inputs, globals, &mut sections);
+    let got = got::build(config, inputs, globals, &mut sections)?;
+    let ifuncs = ifunc::build(config, inputs, globals, &mut sections)?;
+    let pie = pie::build(config, inputs, globals, &got, &ifuncs, &mut sections)?;
+    if config.eh_frame_hdr {
+        eh_frame::build(config, inputs, &mut sections)?;
+    }
+    build_id::build(config, &mut sections);
+    let mut linked = sections_to_segments(config, sections)?;
+    linked.got = got;
+    linked.ifuncs = ifuncs;
+    linked.pie = pie;
+    linked.commons = commons;
+    linked.merged = merged;
+    linked.folded = folded;
+    linked.index_chunks(config);
+    check_memory_regions(config, &linked)?;
+    name_resolution::define_script_symbols(config, inputs, &linked, globals)?;
+    name_resolution::define_boundary_symbols(config, inputs, &linked, globals);
+    ifunc::define_symbols(config, inputs, &linked, globals);
+    pie::define_symbols(config, inputs, &linked, globals);
+    Ok(linked)
+}
+
+/// Patch the laid out sections and fill in the generated ones
+pub fn relocate(
+    config: &Config,
+    inputs: &InputCache,
+    linked: &mut LinkedProgram,
+    globals: &HashMap<String, GlobalLocation>,
+) -> anyhow::Result<()> {
+    apply_relocations(config, inputs, linked, globals)?;
+    ifunc::fill(config, inputs, linked)?;
+    pie::fill(config, linked);
+    if config.eh_frame_hdr {
+        eh_frame::fill(config, inputs, linked)?;
+    }
+    // Last, as it digests the final contents
+    build_id::fill(config, inputs, linked)?;
+    check_segment_overlaps(config, linked)?;
+    Ok(())
+}
@@ -0,0 +1,100 @@
+//! Target architecture, selected from the `e_machine` of the inputs
+
+use goblin::elf::header::{EM_386, EM_AARCH64, EM_X86_64};
+
+use crate::{
+    endian::Endian,
+    open_files::{InputCache, InputId},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    X86_64,
+    Aarch64,
+    I386,
+}
+
+impl Target {
+    /// Value of `e_machine` in the ELF header
+    pub fn machine(self) -> u16 {
+        match self {
+            Target::X86_64 => EM_X86_64,
+            Target::Aarch64 => EM_AARCH64,
+            Target::I386 => EM_386,
+        }
+    }
+
+    /// Uses ELFCLASS64 objects and output
+    pub fn is_64(self) -> bool {
+        self != Target::I386
+    }
+
+    /// Architecture of an `e_machine` value, if supported
+    pub fn from_machine(machine: u16) -> Option<Self> {
+        match machine {
+            EM_X86_64 => Some(Target::X86_64),
+            EM_AARCH64 => Some(Target::Aarch64),
+            EM_386 => Some(Target::I386),
+            _ => None,
+        }
+    }
+}
+
+/// Architecture shared by all inputs. Without inputs, x86-64 is assumed.
+pub fn detect(inputs: &InputCache) -> anyhow::Result<Target> {
+    let mut result: Option<(Target, InputId)> = None;
+    for input in inputs.iter_ids() {
+        let elf = inputs.get_elf(input);
+        let machine = elf.header.e_machine;
+        let Some(target) = Target::from_machine(machine) else {
+            anyhow::bail!(
+                "Unsupported architecture (e_machine {machine:#x}) in {}",
+                inputs.description(input)
+            );
+        };
+        if elf.is_64 != target.is_64() {
+            anyhow::bail!(
+                "{} has the wrong ELF class for {target:?}",
+                inputs.description(input)
+            );
+        }
+        match result {
+            Some((first, first_input)) if first != target => anyhow::bail!(
+                "Mixed architectures: {} is {first:?}, but {} is {target:?}",
+                inputs.description(first_input),
+                inputs.description(input),
+            ),
+            Some(_) => {}
+            None => result = Some((target, input)),
+        }
+    }
+    Ok(result.map_or(Target::X86_64, |(target, _)| target))
+}
+
+/// Byte order shared by all inputs. Without inputs, little-endian is assumed.
+pub fn detect_endian(inputs: &InputCache, target: Target) -> anyhow::Result<Endian> {
+    let mut result: Option<(Endian, InputId)> = None;
+    for input in inputs.iter_ids() {
+        let endian = if inputs.get_elf(input).little_endian {
+            Endian::Little
+        } else {
+            Endian::Big
+        };
+        if endian == Endian::Big && target != Target::Aarch64 {
+            anyhow::bail!(
+                "{} is big-endian, which {target:?} doesn't support",
+                inputs.description(input)
+            );
+        }
+        match result {
+            Some((first, first_input)) if first != endian => anyhow::bail!(
+                "Mixed byte orders: {} is {first:?}-endian, but {} is {endian:?}-endian",
+                inputs.description(first_input),
+                inputs.description(input),
+            ),
+            Some(_) => {}
+            None => result = Some((endian, input)),
+        }
+    }
+    Ok(result.map_or(Endian::Little, |(endian, _)| endian))
+}
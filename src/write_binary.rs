@@ -0,0 +1,35 @@
+//! Flat binary output (`--oformat binary`), like `objcopy -O binary`
+//!
+//! The file is the memory image starting at the lowest loaded address,
+//! without any headers. Gaps are filled with zeros, and zero-initialized
+//! sections at the end are left out.
+
+use std::io::{Read, Write};
+
+use crate::{config::Config, open_files::InputCache, section::LinkedProgram};
+
+pub fn write<T: Write>(
+    config: &Config,
+    inputs: &InputCache,
+    target: &mut T,
+    linked: &LinkedProgram,
+) -> anyhow::Result<()> {
+    let mut file_chunks = linked
+        .iter_with_positions(config)
+        // Empty chunks would only extend the padding at the end
+        .filter(|it| !it.section.is_nobits() && it.chunk.size() > 0)
+        .peekable();
+    let Some(first) = file_chunks.peek() else {
+        return Ok(());
+    };
+
+    // Headers are not part of the image, so it starts at the first chunk
+    let mut position = first.chunk_start;
+    for it in file_chunks {
+        let padding = it.chunk_start - position;
+        std::io::copy(&mut std::io::repeat(0).take(padding), target)?;
+        it.chunk.write_finalized(inputs, target)?;
+        position = it.chunk_start + it.chunk.size();
+    }
+    Ok(())
+}
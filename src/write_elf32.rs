@@ -0,0 +1,139 @@
+//! ELFCLASS32 variants of the file, program and section headers, and of the
+//! symbol table entries, for i386 output.
+//! The layout and the segment contents are shared with [`crate::write_elf64`].
+
+use std::io::Write;
+
+use goblin::elf::sym::STB_GLOBAL;
+
+use crate::{
+    endian::{Endian, ToBytes},
+    write_elf64::{ProgramHeader, SectionHeader, SectionHeaderTable},
+};
+
+/// Size of the file header
+pub const FILE_HEADER_SIZE: u64 = 0x34;
+/// Size of a program header table entry
+pub const PROGRAM_HEADER_SIZE: u64 = 0x20;
+/// Size of a section header table entry
+pub const SECTION_HEADER_SIZE: u64 = 0x28;
+/// Size of an `Elf32_Sym`
+pub const SYMBOL_SIZE: u64 = 0x10;
+
+pub fn write_file_header<T: Write>(
+    target: &mut T,
+    endian: Endian,
+    machine: u16,
+    entry_point: u64,
+    program_header_count: u16,
+    section_headers: Option<&SectionHeaderTable>,
+) -> anyhow::Result<()> {
+    // Magic number
+    target.write_all(&[0x7f, b'E', b'L', b'F'])?;
+    // 32 bit format, byte order
+    target.write_all(&[1, if endian.is_little() { 1 } else { 2 }])?;
+    // ELF version: 1
+    target.write_all(&[1])?;
+    // OS ABI: UNIX - SystemV
+    target.write_all(&[0, 0])?;
+    // Reserved padding
+    target.write_all(&[0; 7])?;
+    // File type: executable
+    target.write_all(&2u16.to_bytes(endian))?;
+    // Target architecture
+    target.write_all(&machine.to_bytes(endian))?;
+    // Another version number: 1
+    target.write_all(&0x1_u32.to_bytes(endian))?;
+
+    // Entry point
+    target.write_all(&narrow(entry_point, "Entry point").to_bytes(endian))?;
+    // Program header table offset: Immediately after this header
+    target.write_all(&(FILE_HEADER_SIZE as u32).to_bytes(endian))?;
+    // Section header table offset, if any
+    let section_header_offset = section_headers.map(|t| t.offset).unwrap_or(0);
+    target.write_all(&narrow(section_header_offset, "Section header offset").to_bytes(endian))?;
+    // Flags: none
+    target.write_all(&0_u32.to_bytes(endian))?;
+    // Size of this header
+    target.write_all(&(FILE_HEADER_SIZE as u16).to_bytes(endian))?;
+    // Program header entry size
+    target.write_all(&(PROGRAM_HEADER_SIZE as u16).to_bytes(endian))?;
+    // Program header entry count:
+    target.write_all(&program_header_count.to_bytes(endian))?;
+    if let Some(table) = section_headers {
+        // Section header entry size
+        target.write_all(&(SECTION_HEADER_SIZE as u16).to_bytes(endian))?;
+        // Section header entry count
+        let count: u16 = table.headers.len().try_into().expect("Too many sections");
+        target.write_all(&count.to_bytes(endian))?;
+        // Index into section header entry containing section names
+        target.write_all(&table.shstrndx.to_bytes(endian))?;
+    } else {
+        // Section headers are not emitted
+        target.write_all(&0_u16.to_bytes(endian))?;
+        target.write_all(&0_u16.to_bytes(endian))?;
+        target.write_all(&0_u16.to_bytes(endian))?;
+    }
+
+    Ok(())
+}
+
+/// Unlike in ELF64, the flags come after the sizes
+pub fn write_program_header<T: Write>(
+    target: &mut T,
+    endian: Endian,
+    ph: &ProgramHeader,
+) -> anyhow::Result<()> {
+    target.write_all(&ph.type_.to_bytes(endian))?;
+    target.write_all(&narrow(ph.offset, "Segment offset").to_bytes(endian))?;
+    target.write_all(&narrow(ph.vaddr, "Segment address").to_bytes(endian))?;
+    target.write_all(&narrow(ph.vaddr, "Segment address").to_bytes(endian))?;
+    target.write_all(&narrow(ph.filesz, "Segment size").to_bytes(endian))?;
+    target.write_all(&narrow(ph.memsz, "Segment size").to_bytes(endian))?;
+    target.write_all(&ph.flags.to_bytes(endian))?;
+    target.write_all(&narrow(ph.align, "Segment alignment").to_bytes(endian))?;
+    Ok(())
+}
+
+fn narrow(value: u64, what: &str) -> u32 {
+    value
+        .try_into()
+        .unwrap_or_else(|_| panic!("{what} {value:#x} doesn't fit a 32-bit ELF"))
+}
+
+/// Same fields as in ELF64, with the address-sized ones narrowed
+pub fn write_section_header<T: Write>(
+    target: &mut T,
+    endian: Endian,
+    header: &SectionHeader,
+) -> anyhow::Result<()> {
+    target.write_all(&header.name.to_bytes(endian))?;
+    target.write_all(&header.type_.to_bytes(endian))?;
+    target.write_all(&narrow(header.flags, "Section flags").to_bytes(endian))?;
+    target.write_all(&narrow(header.addr, "Section address").to_bytes(endian))?;
+    target.write_all(&narrow(header.offset, "Section offset").to_bytes(endian))?;
+    target.write_all(&narrow(header.size, "Section size").to_bytes(endian))?;
+    target.write_all(&header.link.to_bytes(endian))?;
+    target.write_all(&header.info.to_bytes(endian))?;
+    target.write_all(&narrow(header.align, "Section alignment").to_bytes(endian))?;
+    target.write_all(&narrow(header.entsize, "Section entry size").to_bytes(endian))?;
+    Ok(())
+}
+
+/// Append a global symbol. Unlike in ELF64, the value and size come before
+/// the binding and the section index.
+pub fn push_symbol(
+    symtab: &mut Vec<u8>,
+    endian: Endian,
+    name: u32,
+    value: u64,
+    size: u64,
+    shndx: u16,
+) {
+    symtab.extend_from_slice(&name.to_bytes(endian));
+    symtab.extend_from_slice(&narrow(value, "Symbol value").to_bytes(endian));
+    symtab.extend_from_slice(&narrow(size, "Symbol size").to_bytes(endian));
+    symtab.push(STB_GLOBAL << 4);
+    symtab.push(0);
+    symtab.extend_from_slice(&shndx.to_bytes(endian));
+}
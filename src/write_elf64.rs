@@ -1,55 +1,84 @@
 //! https://en.wikipedia.org/wiki/Executable_and_Linkable_Format
 
 use std::io::Write;
+use std::ops::Range;
+
+use goblin::elf64::{
+    header::{ET_DYN, ET_EXEC},
+    section_header::{
+        SHF_ALLOC, SHF_EXECINSTR, SHF_TLS, SHF_WRITE, SHN_ABS, SHT_DYNAMIC, SHT_NOBITS, SHT_NOTE,
+        SHT_PROGBITS, SHT_RELA, SHT_STRTAB, SHT_SYMTAB,
+    },
+    sym::STB_GLOBAL,
+};
 
 use crate::{
-    config::Config, math::align_up, open_files::InputCache, permissions::Permissions,
-    section::LinkedProgram, Section,
+    config::{Config, Strip},
+    eh_frame,
+    endian::{Endian, ToBytes},
+    ifunc,
+    math::align_up,
+    open_files::InputCache,
+    pie,
+    section::LinkedProgram,
+    write_elf32,
 };
 
 pub fn write_file_header<T: Write>(
     target: &mut T,
+    endian: Endian,
+    file_type: u16,
+    machine: u16,
     entry_point: u64,
     program_header_count: u16,
+    section_headers: Option<&SectionHeaderTable>,
 ) -> anyhow::Result<()> {
     // Magic number
     target.write_all(&[0x7f, b'E', b'L', b'F'])?;
-    // 64 bit format, little-endian
-    target.write_all(&[2, 1])?;
+    // 64 bit format, byte order
+    target.write_all(&[2, if endian.is_little() { 1 } else { 2 }])?;
     // ELF version: 1
     target.write_all(&[1])?;
     // OS ABI: UNIX - SystemV
     target.write_all(&[0, 0])?;
     // Reserved padding
     target.write_all(&[0; 7])?;
-    // File type: executable
-    target.write_all(&2u16.to_le_bytes())?;
-    // Target architecture: x86-64
-    target.write_all(&0x3e_u16.to_le_bytes())?;
+    // File type: executable, or shared object for position-independent ones
+    target.write_all(&file_type.to_bytes(endian))?;
+    // Target architecture
+    target.write_all(&machine.to_bytes(endian))?;
     // Another version number: 1
-    target.write_all(&0x1_u32.to_le_bytes())?;
+    target.write_all(&0x1_u32.to_bytes(endian))?;
 
     // Entry point
-    target.write_all(&entry_point.to_le_bytes())?;
+    target.write_all(&entry_point.to_bytes(endian))?;
     // Program header table offset: Immediately after this header
-    target.write_all(&0x40_u64.to_le_bytes())?;
-    // Section header table: Currently unused
-    target.write_all(&0_u64.to_le_bytes())?;
+    target.write_all(&0x40_u64.to_bytes(endian))?;
+    // Section header table offset, if any
+    let section_header_offset = section_headers.map(|t| t.offset).unwrap_or(0);
+    target.write_all(&section_header_offset.to_bytes(endian))?;
     // Flags: none
-    target.write_all(&0_u32.to_le_bytes())?;
+    target.write_all(&0_u32.to_bytes(endian))?;
     // Size of this header: 0x40 bytes
-    target.write_all(&0x40_u16.to_le_bytes())?;
+    target.write_all(&0x40_u16.to_bytes(endian))?;
     // Program header entry size: 0x38 bytes
-    target.write_all(&0x38_u16.to_le_bytes())?;
+    target.write_all(&0x38_u16.to_bytes(endian))?;
     // Program header entry count:
-    target.write_all(&program_header_count.to_le_bytes())?;
-    // Section header entry size: 0x40 bytes
-    // target.write_all(&0x40_u16.to_le_bytes())?;
-    target.write_all(&0_u16.to_le_bytes())?;
-    // Section header entry count: Currently unused
-    target.write_all(&0_u16.to_le_bytes())?;
-    // Index into section header entry containing section names: Currently unused
-    target.write_all(&0_u16.to_le_bytes())?;
+    target.write_all(&program_header_count.to_bytes(endian))?;
+    if let Some(table) = section_headers {
+        // Section header entry size: 0x40 bytes
+        target.write_all(&(SECTION_HEADER_SIZE as u16).to_bytes(endian))?;
+        // Section header entry count
+        let count: u16 = table.headers.len().try_into().expect("Too many sections");
+        target.write_all(&count.to_bytes(endian))?;
+        // Index into section header entry containing section names
+        target.write_all(&table.shstrndx.to_bytes(endian))?;
+    } else {
+        // Section headers are not emitted
+        target.write_all(&0_u16.to_bytes(endian))?;
+        target.write_all(&0_u16.to_bytes(endian))?;
+        target.write_all(&0_u16.to_bytes(endian))?;
+    }
 
     Ok(())
 }
@@ -76,6 +105,12 @@ mod program_header_type {
     pub const LOOS: u32 = 0x60000000;
     // Operating system specific. Inclusive range end.
     pub const HIOS: u32 = 0x6fffffff;
+    // Location of `.eh_frame_hdr`.
+    pub const GNU_EH_FRAME: u32 = 0x6474e550;
+    // Read-only after relocation.
+    pub const GNU_RELRO: u32 = 0x6474e552;
+    // Stack permissions and size.
+    pub const GNU_STACK: u32 = 0x6474e551;
     // Processor specific. Inclusive range start.
     pub const LOPROC: u32 = 0x70000000;
 
@@ -85,106 +120,646 @@ mod program_header_type {
 
 pub fn write_program_header<T: Write>(
     target: &mut T,
-    type_: u32,
-    flags: u32,
-    offset: u64,
-    v_addr: u64,
-    p_addr: u64,
-    filesz: u64,
-    memsz: u64,
-    align: u64,
+    endian: Endian,
+    ph: &ProgramHeader,
+) -> anyhow::Result<()> {
+    target.write_all(&ph.type_.to_bytes(endian))?;
+    target.write_all(&ph.flags.to_bytes(endian))?;
+    target.write_all(&ph.offset.to_bytes(endian))?;
+    target.write_all(&ph.vaddr.to_bytes(endian))?;
+    // Physical address, the same as the virtual one
+    target.write_all(&ph.vaddr.to_bytes(endian))?;
+    target.write_all(&ph.filesz.to_bytes(endian))?;
+    target.write_all(&ph.memsz.to_bytes(endian))?;
+    target.write_all(&ph.align.to_bytes(endian))?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ProgramHeader {
+    pub type_: u32,
+    pub flags: u32,
+    pub offset: u64,
+    /// Virtual address, also used as the physical address
+    pub vaddr: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+    pub align: u64,
+}
+
+/// Position of a program header type in the canonical order.
+/// `PT_PHDR` must precede all loadable segments, and `PT_INTERP` must also
+/// precede them. Headers not describing memory contents go last.
+fn program_header_rank(type_: u32) -> u8 {
+    use program_header_type::*;
+    match type_ {
+        PHDR => 0,
+        INTERP => 1,
+        LOAD => 2,
+        DYNAMIC => 3,
+        NOTE => 4,
+        TLS => 5,
+        GNU_RELRO => 6,
+        GNU_EH_FRAME => 7,
+        GNU_STACK => 8,
+        _ => 9,
+    }
+}
+
+/// Sort program headers into the canonical order, keeping the relative
+/// order of headers of the same type, and check the ELF ordering rules
+fn sort_program_headers(headers: &mut [ProgramHeader]) {
+    headers.sort_by_key(|ph| program_header_rank(ph.type_));
+
+    let loads: Vec<_> = headers
+        .iter()
+        .filter(|ph| ph.type_ == program_header_type::LOAD)
+        .collect();
+    assert!(
+        loads.windows(2).all(|w| w[0].vaddr < w[1].vaddr),
+        "PT_LOAD entries must be sorted by address"
+    );
+    for (i, ph) in headers.iter().enumerate() {
+        if ph.type_ == program_header_type::PHDR || ph.type_ == program_header_type::INTERP {
+            assert!(
+                headers[..i]
+                    .iter()
+                    .all(|other| other.type_ != program_header_type::LOAD),
+                "PT_PHDR and PT_INTERP must precede all PT_LOAD entries"
+            );
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SectionHeader {
+    pub name: u32,
+    pub type_: u32,
+    pub flags: u64,
+    pub addr: u64,
+    pub offset: u64,
+    pub size: u64,
+    pub link: u32,
+    pub info: u32,
+    pub align: u64,
+    pub entsize: u64,
+}
+
+pub fn write_section_header<T: Write>(
+    target: &mut T,
+    endian: Endian,
+    header: &SectionHeader,
 ) -> anyhow::Result<()> {
-    target.write_all(&type_.to_le_bytes())?;
-    target.write_all(&flags.to_le_bytes())?;
-    target.write_all(&offset.to_le_bytes())?;
-    target.write_all(&v_addr.to_le_bytes())?;
-    target.write_all(&p_addr.to_le_bytes())?;
-    target.write_all(&filesz.to_le_bytes())?;
-    target.write_all(&memsz.to_le_bytes())?;
-    target.write_all(&align.to_le_bytes())?;
+    target.write_all(&header.name.to_bytes(endian))?;
+    target.write_all(&header.type_.to_bytes(endian))?;
+    target.write_all(&header.flags.to_bytes(endian))?;
+    target.write_all(&header.addr.to_bytes(endian))?;
+    target.write_all(&header.offset.to_bytes(endian))?;
+    target.write_all(&header.size.to_bytes(endian))?;
+    target.write_all(&header.link.to_bytes(endian))?;
+    target.write_all(&header.info.to_bytes(endian))?;
+    target.write_all(&header.align.to_bytes(endian))?;
+    target.write_all(&header.entsize.to_bytes(endian))?;
     Ok(())
 }
 
+/// Section header table, and the sections not loaded into memory:
+/// the symbol table and the string tables. Placed after all segment contents.
+pub struct SectionHeaderTable {
+    /// Contents of `.symtab`, `.strtab` and `.shstrtab`, written right after the segment contents
+    pub contents: Vec<u8>,
+    /// File offset of the table itself, after the contents
+    pub offset: u64,
+    /// Entry 0 is always the mandatory `SHT_NULL`
+    pub headers: Vec<SectionHeader>,
+    pub shstrndx: u16,
+}
+
+/// Size of an `Elf64_Sym`
+const SYMBOL_SIZE: u64 = 24;
+/// Size of a section header table entry
+pub const SECTION_HEADER_SIZE: u64 = 0x40;
+
+impl SectionHeaderTable {
+    /// Describe the output sections and list `symbols` as (name, address, size).
+    /// `contents_end` is the file offset right after the last segment.
+    fn build(
+        config: &Config,
+        linked: &LinkedProgram,
+        symbols: &[(String, u64, u64)],
+        segment_offsets: &[u64],
+        contents_end: u64,
+    ) -> Self {
+        let mut shstrtab = vec![0u8];
+        let mut add_name = |name: &str| -> u32 {
+            let index: u32 = shstrtab
+                .len()
+                .try_into()
+                .expect("Section name table overflow");
+            shstrtab.extend_from_slice(name.as_bytes());
+            shstrtab.push(0);
+            index
+        };
+
+        let mut headers = vec![SectionHeader::default()];
+        for it in linked.iter_with_positions(config) {
+            if it.chunk_index != 0 {
+                continue;
+            }
+
+            let permissions = it.section.permissions();
+            // IRELATIVE and RELATIVE relocations, which startup code applies
+            let is_rela = it.section.name == ifunc::RELA_PLT || it.section.name == pie::RELA_DYN;
+            let is_dynamic = it.section.name == pie::DYNAMIC;
+            headers.push(SectionHeader {
+                name: add_name(&it.section.name),
+                type_: if it.section.is_nobits() {
+                    SHT_NOBITS
+                } else if is_rela {
+                    SHT_RELA
+                } else if is_dynamic {
+                    SHT_DYNAMIC
+                } else if is_note_section(&it.section.name) {
+                    SHT_NOTE
+                } else {
+                    SHT_PROGBITS
+                },
+                flags: (SHF_ALLOC
+                    | if permissions.write { SHF_WRITE } else { 0 }
+                    | if permissions.execute {
+                        SHF_EXECINSTR
+                    } else {
+                        0
+                    }
+                    | if it.segment.is_tls() { SHF_TLS } else { 0 }) as u64,
+                addr: config.base_addr + it.section_start,
+                offset: segment_offsets[it.segment_index] + (it.section_start - it.segment_start),
+                size: it.section.size(),
+                align: it.section.alignment(),
+                entsize: if is_rela {
+                    ifunc::RELA_SIZE
+                } else if is_dynamic {
+                    pie::DYNAMIC_ENTRY_SIZE
+                } else {
+                    0
+                },
+                ..Default::default()
+            });
+        }
+
+        // Symbol table, starting with the mandatory null symbol,
+        // left out entirely with `--strip-all`
+        let endian = config.endian;
+        let (symbol_size, word_align) = if config.target.is_64() {
+            (SYMBOL_SIZE, 8)
+        } else {
+            (write_elf32::SYMBOL_SIZE, 4)
+        };
+        let mut strtab = Vec::new();
+        let mut symtab = Vec::new();
+        if config.strip != Strip::All {
+            strtab.push(0);
+            symtab.resize(symbol_size as usize, 0);
+            for (name, address, size) in symbols {
+                let st_name: u32 = strtab.len().try_into().expect("String table overflow");
+                strtab.extend_from_slice(name.as_bytes());
+                strtab.push(0);
+
+                // Linker-defined symbols, e.g. `_end`, may be past the last section
+                let st_shndx = headers
+                    .iter()
+                    .position(|h| {
+                        h.flags & SHF_ALLOC as u64 != 0
+                            && (h.addr..h.addr + h.size).contains(address)
+                    })
+                    .map_or(SHN_ABS as u16, |i| i as u16);
+
+                if config.target.is_64() {
+                    symtab.extend_from_slice(&st_name.to_bytes(endian));
+                    symtab.push(STB_GLOBAL << 4);
+                    symtab.push(0);
+                    symtab.extend_from_slice(&st_shndx.to_bytes(endian));
+                    symtab.extend_from_slice(&address.to_bytes(endian));
+                    symtab.extend_from_slice(&size.to_bytes(endian));
+                } else {
+                    write_elf32::push_symbol(
+                        &mut symtab,
+                        endian,
+                        st_name,
+                        *address,
+                        *size,
+                        st_shndx,
+                    );
+                }
+            }
+
+            let symtab_index = headers.len() as u32;
+            let name = add_name(".symtab");
+            headers.push(SectionHeader {
+                name,
+                type_: SHT_SYMTAB,
+                offset: contents_end,
+                size: symtab.len() as u64,
+                // Index of the string table
+                link: symtab_index + 1,
+                // Index of the first non-local symbol
+                info: 1,
+                align: word_align,
+                entsize: symbol_size,
+                ..Default::default()
+            });
+            let name = add_name(".strtab");
+            headers.push(SectionHeader {
+                name,
+                type_: SHT_STRTAB,
+                offset: contents_end + symtab.len() as u64,
+                size: strtab.len() as u64,
+                align: 1,
+                ..Default::default()
+            });
+        }
+
+        let shstrndx: u16 = headers.len().try_into().expect("Too many sections");
+        // No dynamic entry refers to a string, but the link must be a string table
+        for header in headers.iter_mut().filter(|h| h.type_ == SHT_DYNAMIC) {
+            header.link = shstrndx.into();
+        }
+        let name = add_name(".shstrtab");
+        let shstrtab_offset = contents_end + (symtab.len() + strtab.len()) as u64;
+        headers.push(SectionHeader {
+            name,
+            type_: SHT_STRTAB,
+            offset: shstrtab_offset,
+            size: shstrtab.len() as u64,
+            align: 1,
+            ..Default::default()
+        });
+
+        let mut contents = symtab;
+        contents.extend(strtab);
+        contents.extend(shstrtab);
+        Self {
+            offset: align_up(contents_end + contents.len() as u64, word_align),
+            contents,
+            headers,
+            shstrndx,
+        }
+    }
+
+    fn write<T: Write>(
+        &self,
+        target: &mut T,
+        config: &Config,
+        position: u64,
+    ) -> anyhow::Result<()> {
+        target.write_all(&self.contents)?;
+        let position = position + self.contents.len() as u64;
+        for _ in position..self.offset {
+            target.write_all(&[0])?;
+        }
+        for header in &self.headers {
+            if config.target.is_64() {
+                write_section_header(target, config.endian, header)?;
+            } else {
+                write_elf32::write_section_header(target, config.endian, header)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn program_header_count(config: &Config, linked: &LinkedProgram) -> u16 {
+    // Not using `relro_range` here, as positions depend on the header size
+    let relro = config.relro
+        && linked
+            .segments
+            .iter()
+            .flat_map(|segment| segment.sections.iter())
+            .any(|section| is_relro_section(&section.name));
+    let eh_frame_hdr = linked
+        .segments
+        .iter()
+        .flat_map(|segment| segment.sections.iter())
+        .any(|section| section.name == eh_frame::EH_FRAME_HDR);
+    let notes = linked
+        .segments
+        .iter()
+        .flat_map(|segment| segment.sections.iter())
+        .filter(|section| is_note_section(&section.name))
+        .count();
+    let dynamic = linked
+        .segments
+        .iter()
+        .flat_map(|segment| segment.sections.iter())
+        .any(|section| section.name == pie::DYNAMIC);
+    let tls = linked.segments.iter().any(|segment| segment.is_tls());
+    // `PT_GNU_STACK` is always present, `PT_PHDR` whenever the headers are mapped
+    let count = linked.headers_mapped() as usize
+        + linked.segments.len()
+        + tls as usize
+        + relro as usize
+        + eh_frame_hdr as usize
+        + notes
+        + dynamic as usize
+        + 1;
+    count.try_into().expect("Too many program headers")
+}
+
+/// Notes from the inputs, like `.note.gnu.property`, and the build ID.
+/// Each gets a `PT_NOTE`, with the alignment of its notes.
+pub fn is_note_section(name: &str) -> bool {
+    name == ".note" || name.starts_with(".note.")
+}
+
+/// Sections that are writable only while relocating, see [`relro_range`]
+pub fn is_relro_section(name: &str) -> bool {
+    name == ".data.rel.ro" || name.starts_with(".data.rel.ro.") || name == pie::DYNAMIC
+}
+
+/// With `-z relro`, the range of `.data.rel.ro` sections and `.dynamic`, as segment
+/// index and address range relative to `base_addr`. They contain relocated pointers,
+/// which are final once relocated, so the runtime can map them read-only.
+fn relro_range(config: &Config, linked: &LinkedProgram) -> Option<(usize, Range<u64>)> {
+    if !config.relro {
+        return None;
+    }
+
+    let mut result: Option<(usize, Range<u64>)> = None;
+    for it in linked.iter_with_positions(config) {
+        if it.chunk_index != 0 || !is_relro_section(&it.section.name) {
+            continue;
+        }
+
+        let end = it.section_start + it.section.size();
+        match &mut result {
+            Some((segment_index, range)) => {
+                assert_eq!(
+                    *segment_index, it.segment_index,
+                    "RELRO sections must share a segment"
+                );
+                range.end = range.end.max(end);
+            }
+            None => result = Some((it.segment_index, it.section_start..end)),
+        }
+    }
+    result
+}
+
+/// Sizes of the file header and of a program header table entry,
+/// which are smaller for ELFCLASS32 targets
+fn header_entry_sizes(config: &Config) -> (u64, u64) {
+    if config.target.is_64() {
+        (0x40, 0x38)
+    } else {
+        (
+            write_elf32::FILE_HEADER_SIZE,
+            write_elf32::PROGRAM_HEADER_SIZE,
+        )
+    }
+}
+
+/// Size of the file header and the program header table, aligned so that
+/// the first segment contents can follow them.
+pub fn headers_size(config: &Config, linked: &LinkedProgram) -> u64 {
+    let (file_header_size, entry_size) = header_entry_sizes(config);
+    let unaligned = file_header_size + program_header_count(config, linked) as u64 * entry_size;
+    align_up(unaligned, config.segment_file_align)
+}
+
 pub fn write<T: Write>(
     config: &Config,
     inputs: &InputCache,
     target: &mut T,
     linked: &LinkedProgram,
+    entry_point: u64,
+    symbols: &[(String, u64, u64)],
 ) -> anyhow::Result<()> {
     // TODO: merge sections into program headers at some point
 
     // Calculate some offsets
-    let pos_after_headers = 0x40 + linked.segments.len() as u64 * 0x38;
-    let pos_first_content = align_up(pos_after_headers, config.segment_file_align);
+    let program_header_count = program_header_count(config, linked);
+    let (file_header_size, entry_size) = header_entry_sizes(config);
+    let pos_after_headers = file_header_size + program_header_count as u64 * entry_size;
+    let pos_first_content = headers_size(config, linked);
+
+    let segment_offsets = linked.segment_offsets(config);
+    let section_headers = config.section_headers.then(|| {
+        SectionHeaderTable::build(
+            config,
+            linked,
+            symbols,
+            &segment_offsets,
+            segment_offsets[linked.segments.len()],
+        )
+    });
 
-    let nth_segment_offset = |n: usize| -> u64 {
-        pos_first_content + linked.segment_sizes(&config).take(n).sum::<u64>()
-    };
+    let segment_starts = linked.segment_starts(config);
 
     // File header
-    write_file_header(target, config.base_addr, linked.segments.len() as u16)?;
+    if config.target.is_64() {
+        write_file_header(
+            target,
+            config.endian,
+            if config.pie { ET_DYN } else { ET_EXEC },
+            config.target.machine(),
+            entry_point,
+            program_header_count,
+            section_headers.as_ref(),
+        )?;
+    } else {
+        write_elf32::write_file_header(
+            target,
+            config.endian,
+            config.target.machine(),
+            entry_point,
+            program_header_count,
+            section_headers.as_ref(),
+        )?;
+    }
 
     // Program headers
-    let mut segment_vaddr = config.base_addr;
+    // The table describes itself, and is mapped by the first LOAD
+    let mut program_headers = Vec::new();
+    if linked.headers_mapped() {
+        program_headers.push(ProgramHeader {
+            type_: program_header_type::PHDR,
+            flags: 0b100,
+            offset: file_header_size,
+            vaddr: linked.headers_address(config) + file_header_size,
+            filesz: program_header_count as u64 * entry_size,
+            memsz: program_header_count as u64 * entry_size,
+            align: if config.target.is_64() { 8 } else { 4 },
+        });
+    }
+    let load_ranges = linked.load_ranges(config);
     for (i, segment) in linked.segments.iter().enumerate() {
-        segment_vaddr = align_up(segment_vaddr, config.page_size);
-
         // TODO: support other types than bare loadable program bits
 
-        write_program_header(
-            target,
-            program_header_type::LOAD,
-            (segment.permissions().read as u32) << 2
+        // The first segment also maps the file and program headers,
+        // so that they are visible in memory
+        let (offset, file_size) = if i == 0 && linked.headers_mapped() {
+            (0, segment_offsets[0] + segment.file_size())
+        } else {
+            (segment_offsets[i], segment.file_size())
+        };
+        let range = &load_ranges[i];
+        let (vaddr, size) = (range.start, range.end - range.start);
+        // Loaders map whole pages, so the ABI requires this of every `PT_LOAD`
+        assert_eq!(
+            offset % config.page_size,
+            vaddr % config.page_size,
+            "Segment offset and address must be congruent"
+        );
+
+        program_headers.push(ProgramHeader {
+            type_: program_header_type::LOAD,
+            flags: (segment.permissions().read as u32) << 2
                 | (segment.permissions().write as u32) << 1
                 | (segment.permissions().execute as u32),
-            nth_segment_offset(i) as u64,
-            segment_vaddr,
-            segment_vaddr,
-            // TODO: differing filesz, memsz values, e.g. .bss sections
-            align_up(segment.size(), config.segment_file_align),
-            align_up(segment.size(), config.page_size),
-            config.page_size,
-        )?;
+            offset,
+            vaddr,
+            // Zero-initialized sections at the end only take memory
+            filesz: file_size,
+            memsz: size,
+            align: config.page_size,
+        });
+    }
 
-        segment_vaddr += align_up(segment.size(), config.page_size);
+    // Template for the thread-local block of each thread
+    if let Some((segment_index, _)) = linked.tls_range(config) {
+        let segment = &linked.segments[segment_index];
+        program_headers.push(ProgramHeader {
+            type_: program_header_type::TLS,
+            flags: 0b100,
+            offset: segment_offsets[segment_index],
+            vaddr: config.base_addr + segment_starts[segment_index],
+            filesz: segment.file_size(),
+            memsz: segment.size(),
+            align: segment.alignment(),
+        });
     }
 
-    // Align to page size
-    for _ in 0..(pos_first_content - pos_after_headers) {
-        target.write_all(&[0])?;
+    if let Some((segment_index, range)) = relro_range(config, linked) {
+        let offset = segment_offsets[segment_index] + (range.start - segment_starts[segment_index]);
+        program_headers.push(ProgramHeader {
+            type_: program_header_type::GNU_RELRO,
+            flags: 0b100,
+            offset,
+            vaddr: config.base_addr + range.start,
+            filesz: range.end - range.start,
+            memsz: range.end - range.start,
+            align: 1,
+        });
     }
 
-    let mut position = pos_first_content;
-    for segment in &linked.segments {
-        for section in &segment.sections {
-            // Align to section alignment
-            let align_amount = align_up(position, section.alignment()) - position;
-            position += align_amount;
-            for _ in 0..align_amount {
-                target.write_all(&[0])?;
-            }
+    // Unwinders locate the FDE lookup table through this
+    let eh_frame_hdr = linked
+        .iter_with_positions(config)
+        .find(|it| it.chunk_index == 0 && it.section.name == eh_frame::EH_FRAME_HDR);
+    if let Some(it) = eh_frame_hdr {
+        let size = it.section.size();
+        program_headers.push(ProgramHeader {
+            type_: program_header_type::GNU_EH_FRAME,
+            flags: 0b100,
+            offset: segment_offsets[it.segment_index] + (it.section_start - it.segment_start),
+            vaddr: config.base_addr + it.section_start,
+            filesz: size,
+            memsz: size,
+            align: it.section.alignment(),
+        });
+    }
 
-            for chunk in &section.chunks {
-                chunk.write_finalized(inputs, target)?;
+    // Startup code of a position-independent executable finds `.rela.dyn` through this
+    let dynamic = linked
+        .iter_with_positions(config)
+        .find(|it| it.chunk_index == 0 && it.section.name == pie::DYNAMIC);
+    if let Some(it) = dynamic {
+        let size = it.section.size();
+        program_headers.push(ProgramHeader {
+            type_: program_header_type::DYNAMIC,
+            flags: 0b110,
+            offset: segment_offsets[it.segment_index] + (it.section_start - it.segment_start),
+            vaddr: config.base_addr + it.section_start,
+            filesz: size,
+            memsz: size,
+            align: it.section.alignment(),
+        });
+    }
 
-                // Align to chunk alignment
-                let align_amount = align_up(position, chunk.alignment) - position;
-                position += align_amount;
-                for _ in 0..align_amount {
-                    target.write_all(&[0])?;
-                }
+    let notes = linked
+        .iter_with_positions(config)
+        .filter(|it| it.chunk_index == 0 && is_note_section(&it.section.name));
+    for it in notes {
+        let size = it.section.size();
+        program_headers.push(ProgramHeader {
+            type_: program_header_type::NOTE,
+            flags: 0b100,
+            offset: segment_offsets[it.segment_index] + (it.section_start - it.segment_start),
+            vaddr: config.base_addr + it.section_start,
+            filesz: size,
+            memsz: size,
+            align: it.section.alignment(),
+        });
+    }
 
-                position += chunk.size();
-            }
+    // Without this, loaders may default to an executable stack.
+    // A requested stack size goes to `p_memsz`, for loaders that honor it.
+    program_headers.push(ProgramHeader {
+        type_: program_header_type::GNU_STACK,
+        flags: if config.exec_stack { 0b111 } else { 0b110 },
+        memsz: config.stack_size.unwrap_or(0),
+        align: 16,
+        ..Default::default()
+    });
+
+    sort_program_headers(&mut program_headers);
+    assert_eq!(
+        program_headers.len(),
+        program_header_count as usize,
+        "Program header count mismatch"
+    );
+    for ph in &program_headers {
+        if config.target.is_64() {
+            write_program_header(target, config.endian, ph)?;
+        } else {
+            write_elf32::write_program_header(target, config.endian, ph)?;
         }
+    }
 
-        // Align to max(section_alignment, page_size)
-        let alignment = segment.alignment().max(config.page_size);
-        let align_amount = align_up(position, alignment) - position;
-        position += align_amount;
-        for _ in 0..align_amount {
+    // Align to page size
+    for _ in 0..(pos_first_content - pos_after_headers) {
+        target.write_all(&[0])?;
+    }
+
+    // Each chunk goes at the same offset from its segment as in memory, with padding
+    // that also keeps the segment offsets congruent to their addresses
+    let file_sections: Vec<usize> = linked
+        .segments
+        .iter()
+        .map(|segment| segment.file_sections().count())
+        .collect();
+    let mut position = pos_first_content;
+    for it in linked.iter_with_positions(config) {
+        if it.section_index >= file_sections[it.segment_index] {
+            continue;
+        }
+        let offset = segment_offsets[it.segment_index] + (it.chunk_start - it.segment_start);
+        for _ in position..offset {
             target.write_all(&[0])?;
         }
+        it.chunk.write_finalized(inputs, target)?;
+        position = offset + it.chunk.size();
+    }
+
+    // The last segment is padded to the file alignment as well
+    let contents_end = segment_offsets[linked.segments.len()];
+    for _ in position..contents_end {
+        target.write_all(&[0])?;
+    }
+    position = contents_end;
+
+    if let Some(table) = section_headers {
+        table.write(target, config, position)?;
     }
 
     Ok(())
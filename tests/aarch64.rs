@@ -0,0 +1,87 @@
+mod common;
+
+use common::*;
+
+const BL: u32 = 0x9400_0000;
+/// `adrp x0, 0`
+const ADRP_X0: u32 = 0x9000_0000;
+/// `add x0, x0, #0`
+const ADD_X0: u32 = 0x9100_0000;
+const RET: u32 = 0xd65f_03c0;
+
+fn code(words: &[u32]) -> Code {
+    Code::new().raw(
+        &words
+            .iter()
+            .flat_map(|w| w.to_le_bytes())
+            .collect::<Vec<_>>(),
+    )
+}
+
+/// `_start` calling `helper` and taking the address of `value` in the other input,
+/// which also has a pointer to `helper`
+fn objects() -> [Object; 2] {
+    let mut main = Object::aarch64(true);
+    let text = main.text(".text", code(&[BL, ADRP_X0, ADD_X0, RET]));
+    main.reloc(text, 0, R_AARCH64_CALL26, sym("helper"), 0);
+    main.reloc(text, 4, R_AARCH64_ADR_PREL_PG_HI21, sym("value"), 0);
+    main.reloc(text, 8, R_AARCH64_ADD_ABS_LO12_NC, sym("value"), 0);
+    main.func("_start", text, 0);
+
+    let mut other = Object::aarch64(true);
+    let text = other.text(".text", code(&[RET]));
+    other.func("helper", text, 0);
+    let data = other.data(".data", 8, &[0; 16]);
+    other.object("value", data, 8, 8);
+    other.object("pointer", data, 0, 8);
+    other.reloc(data, 0, R_AARCH64_ABS64, sym("helper"), 0);
+    [main, other]
+}
+
+fn word(elf: &goblin::elf::Elf, bytes: &[u8], address: u64) -> u32 {
+    u32::from_le_bytes(read_at(elf, bytes, address, 4).try_into().unwrap())
+}
+
+#[test]
+fn calls_and_addresses_across_inputs_are_relocated() {
+    let [main, other] = objects();
+    let bytes = link(&[&main, &other], &["--section-headers"]).unwrap();
+    check_with_readelf(&bytes);
+    let elf = parse(&bytes);
+    assert_eq!(elf.header.e_machine, EM_AARCH64);
+    let start = symbol_address(&elf, "_start").unwrap();
+    let helper = symbol_address(&elf, "helper").unwrap();
+    let value = symbol_address(&elf, "value").unwrap();
+    assert_eq!(elf.header.e_entry, start);
+
+    // bl helper
+    let bl = word(&elf, &bytes, start);
+    assert_eq!(bl & 0xfc00_0000, BL);
+    let offset = (((bl & 0x03ff_ffff) << 6) as i32 >> 4) as i64;
+    assert_eq!(start as i64 + offset, helper as i64);
+
+    // adrp x0, value
+    let adrp = word(&elf, &bytes, start + 4);
+    let pages = ((adrp >> 29) & 0b11) | ((adrp >> 5) & 0x7_ffff) << 2;
+    let pages = ((pages << 11) as i32 >> 11) as i64;
+    assert_eq!(
+        (start as i64 & !0xfff) + (pages << 12),
+        value as i64 & !0xfff
+    );
+    assert_eq!(adrp & 0x1f, 0);
+
+    // add x0, x0, :lo12:value
+    let add = word(&elf, &bytes, start + 8);
+    assert_eq!(add & !(0xfff << 10), ADD_X0);
+    assert_eq!(((add >> 10) & 0xfff) as u64, value & 0xfff);
+
+    let pointer = symbol_address(&elf, "pointer").unwrap();
+    assert_eq!(read_at(&elf, &bytes, pointer, 8), helper.to_le_bytes());
+}
+
+#[test]
+fn mixed_architectures_are_rejected() {
+    let [main, _] = objects();
+    let error = link_error(&[&main, &Object::exiting(0)], &[]);
+    assert!(error.contains("Mixed architectures"), "{error}");
+}
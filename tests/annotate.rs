@@ -0,0 +1,68 @@
+mod common;
+
+use common::*;
+
+#[test]
+fn annotation_lists_chunks_and_patched_calls() {
+    let mut main = Object::x86_64();
+    let text = main.text(".text", Code::new().call(sym("foo")).exit(0));
+    main.func("_start", text, 0);
+    let mut callee = Object::x86_64();
+    let text = callee.text(".text", Code::new().ret());
+    callee.func("foo", text, 0);
+
+    let link = link_program(&[&main, &callee], &["--section-headers"]).unwrap();
+    let bytes = link.bytes().unwrap();
+    let elf = parse(&bytes);
+    let start = symbol_address(&elf, "_start").unwrap();
+    let foo = symbol_address(&elf, "foo").unwrap();
+
+    let mut annotation = Vec::new();
+    link.write_annotation(&mut annotation).unwrap();
+    let annotation = String::from_utf8(annotation).unwrap();
+
+    // `foo` is a single `ret`
+    let foo_line = format!("{foo:#010x}..{:#010x} .text from", foo + 1);
+    assert!(
+        annotation
+            .lines()
+            .any(|l| l.starts_with(&foo_line) && l.ends_with("1.o\"")),
+        "{annotation}"
+    );
+    // The call displacement is relative to the end of the instruction
+    let displacement = (foo as i64 - (start as i64 + 5)) as u32;
+    let patch = format!(
+        "{:#010x}: patched 4 bytes = {displacement:#010x}",
+        start + 1
+    );
+    assert!(annotation.contains(&patch), "{annotation}");
+}
+
+#[test]
+fn patches_are_shown_in_the_output_byte_order() {
+    // Big-endian AArch64, with a pointer in the code and a call
+    let mut object = Object::aarch64(false);
+    let bl = 0x9400_0000u32.to_le_bytes();
+    let text = object.text(".text", Code::new().raw(&bl).raw(&[0; 8]));
+    object.reloc(text, 0, R_AARCH64_CALL26, sym("_start"), 8);
+    object.reloc(text, 4, R_AARCH64_ABS64, sym("_start"), 0x1234);
+    object.func("_start", text, 0);
+
+    let link = link_program(&[&object], &["--section-headers"]).unwrap();
+    let bytes = link.bytes().unwrap();
+    let elf = parse(&bytes);
+    let start = symbol_address(&elf, "_start").unwrap();
+    let mut annotation = Vec::new();
+    link.write_annotation(&mut annotation).unwrap();
+    let annotation = String::from_utf8(annotation).unwrap();
+
+    // `bl .+8`, as an instruction word
+    let call = format!("{:#010x}: patched 4 bytes = 0x94000002", start);
+    assert!(annotation.contains(&call), "{annotation}");
+    let pointer = format!(
+        "{:#010x}: patched 8 bytes = {:#018x}",
+        start + 4,
+        start + 0x1234
+    );
+    assert!(annotation.contains(&pointer), "{annotation}");
+}
@@ -0,0 +1,169 @@
+mod common;
+
+use common::*;
+
+/// Link a command line of inputs and flags, with the output in `dir`
+fn link_args(dir: &TempDir, args: &[&str]) -> anyhow::Result<Vec<u8>> {
+    let args = parse_args(dir, args);
+    let config = linker::config::Config::from_args(&args)?;
+    linker::link(&config, &args.inputs)
+}
+
+/// `_start` calling `used`, and an archive of `used` (which calls `helper`),
+/// `helper`, and `unused`. Each member has a marker in `.rodata`.
+fn main_and_archive(dir: &TempDir, name: &str) -> String {
+    let mut main = Object::x86_64();
+    let text = main.text(".text", Code::new().call(sym("used")).exit(42));
+    main.func("_start", text, 0);
+
+    let member = |name: &str, callee: Option<&str>| {
+        let mut object = Object::x86_64();
+        let code = match callee {
+            Some(callee) => Code::new().call(sym(callee)).ret(),
+            None => Code::new().ret(),
+        };
+        let text = object.text(".text", code);
+        object.func(name, text, 0);
+        object.rodata(".rodata", 1, format!("marker of {name}").as_bytes());
+        object
+    };
+    let archive = archive(&[
+        ("used.o", &member("used", Some("helper"))),
+        ("unused.o", &member("unused", None)),
+        ("helper.o", &member("helper", None)),
+    ]);
+    dir.write(name, &archive);
+    dir.write("main.o", &main.bytes())
+        .to_str()
+        .unwrap()
+        .to_owned()
+}
+
+fn contains(bytes: &[u8], needle: &str) -> bool {
+    bytes.windows(needle.len()).any(|w| w == needle.as_bytes())
+}
+
+#[test]
+fn only_needed_archive_members_are_linked() {
+    let dir = TempDir::new();
+    let main = main_and_archive(&dir, "libfoo.rlib");
+    let archive = dir.path().join("libfoo.rlib");
+    let bytes = link_args(&dir, &[&main, archive.to_str().unwrap()]).unwrap();
+    assert!(contains(&bytes, "marker of used"));
+    // Needed by a member that was pulled in
+    assert!(contains(&bytes, "marker of helper"));
+    assert!(!contains(&bytes, "marker of unused"));
+    assert_exits(&bytes, 42);
+}
+
+#[test]
+fn libraries_are_found_in_library_paths() {
+    let dir = TempDir::new();
+    let main = main_and_archive(&dir, "libfoo.rlib");
+    let path = dir.path().to_str().unwrap();
+    for args in [
+        &[&main, "-L", path, "-lfoo"][..],
+        &["-L", path, &main, "-l", "foo"],
+    ] {
+        let bytes = link_args(&dir, args).unwrap();
+        assert!(!contains(&bytes, "marker of unused"));
+        assert_exits(&bytes, 42);
+    }
+}
+
+#[test]
+fn libraries_keep_their_place_among_inputs() {
+    let dir = TempDir::new();
+    let first = TempDir::new();
+    let main = main_and_archive(&dir, "libfoo.a");
+    first.write("libfoo.rlib", b"!<arch>\n");
+    let args = parse_args(
+        &dir,
+        &[
+            "-L",
+            first.path().to_str().unwrap(),
+            &format!("-L{}", dir.path().display()),
+            "-lfoo",
+            &main,
+        ],
+    );
+    // The first directory wins, even with the other file name
+    assert_eq!(args.inputs, [first.path().join("libfoo.rlib"), main.into()]);
+}
+
+#[test]
+#[should_panic(expected = "Library -lmissing not found, searched in")]
+fn missing_libraries_are_an_error() {
+    let dir = TempDir::new();
+    parse_args(&dir, &["-L", dir.path().to_str().unwrap(), "-lmissing"]);
+}
+
+#[test]
+fn plain_archives_link_like_rlibs() {
+    let dir = TempDir::new();
+    let main = main_and_archive(&dir, "libfoo.a");
+    let archive = dir.path().join("libfoo.a");
+    let bytes = link_args(&dir, &[&main, archive.to_str().unwrap()]).unwrap();
+    assert!(contains(&bytes, "marker of helper"));
+    assert_exits(&bytes, 42);
+
+    // A member header cut short
+    let broken = dir.write("libbroken.a", b"!<arch>\nbroken.o/  0  ");
+    let err = link_args(&dir, &[&main, broken.to_str().unwrap()]).unwrap_err();
+    let err = format!("{err:#}");
+    assert!(
+        err.contains("libbroken.a\" is not a valid archive"),
+        "{err}"
+    );
+}
+
+/// GNU thin archive referring to the members by their paths, relative to it
+fn thin_archive(members: &[(&str, usize)]) -> Vec<u8> {
+    let mut archive = b"!<thin>\n".to_vec();
+    for (name, size) in members {
+        let header = format!(
+            "{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+            format!("{name}/"),
+            0,
+            0,
+            0,
+            644,
+            size
+        );
+        archive.extend(header.bytes());
+    }
+    archive
+}
+
+#[test]
+fn thin_archives_link_the_files_they_refer_to() {
+    let dir = TempDir::new();
+    let mut main = Object::x86_64();
+    let text = main.text(".text", Code::new().call(sym("used")).exit(42));
+    main.func("_start", text, 0);
+    let main = dir.write("main.o", &main.bytes());
+    let mut used = Object::x86_64();
+    let text = used.text(".text", Code::new().call(sym("helper")).ret());
+    used.func("used", text, 0);
+    let mut helper = Object::x86_64();
+    let text = helper.text(".text", Code::new().ret());
+    helper.func("helper", text, 0);
+    std::fs::create_dir(dir.path().join("objs")).unwrap();
+    let used = used.bytes();
+    let helper = helper.bytes();
+    dir.write("objs/used.o", &used);
+    dir.write("objs/helper.o", &helper);
+
+    let members = [("objs/used.o", used.len()), ("objs/helper.o", helper.len())];
+    let archive = dir.write("libthin.a", &thin_archive(&members));
+    let args = [main.to_str().unwrap(), archive.to_str().unwrap()];
+    assert_exits(&link_args(&dir, &args).unwrap(), 42);
+
+    // The member files aren't copied into the archive
+    std::fs::remove_file(dir.path().join("objs/helper.o")).unwrap();
+    let err = format!("{:#}", link_args(&dir, &args).unwrap_err());
+    assert!(
+        err.contains("\"objs/helper.o\", referenced by thin archive") && err.contains("libthin.a"),
+        "{err}"
+    );
+}
@@ -0,0 +1,171 @@
+mod common;
+
+use common::*;
+
+#[test]
+fn rustc_style_command_line_parses() {
+    let dir = TempDir::new();
+    let paths = write_objects(&dir, &[&Object::exiting(0), &Object::exiting(0)]);
+    let lib_dir = dir.path().to_str().unwrap();
+    let out = dir.path().join("out");
+    let args = [
+        "-plugin",
+        "/usr/libexec/gcc/x86_64-linux-gnu/13/liblto_plugin.so",
+        "-plugin-opt=/usr/libexec/gcc/x86_64-linux-gnu/13/lto-wrapper",
+        "-plugin-opt=-fresolution=/tmp/ccXYZ.res",
+        "--build-id",
+        "--eh-frame-hdr",
+        "-m",
+        "elf_x86_64",
+        "--hash-style=gnu",
+        "-static",
+        "-z",
+        "noexecstack",
+        "-z",
+        "relro",
+        "-z",
+        "now",
+        "-o",
+        out.to_str().unwrap(),
+        &paths[0],
+        "-L",
+        lib_dir,
+        &format!("-L{lib_dir}"),
+        "--as-needed",
+        "-Bstatic",
+        &paths[1],
+        "--start-group",
+        "--end-group",
+        "-Bdynamic",
+        "--gc-sections",
+        "-nodefaultlibs",
+        "--no-undefined",
+        "--color-diagnostics",
+        "-O1",
+        "--strip-debug",
+    ];
+    let args = linker::args::parse(args.iter().map(|a| a.to_string()).collect());
+    assert_eq!(args.output, Some(out));
+    assert_eq!(args.inputs.len(), 2);
+    assert!(args.gc_sections && args.relro && args.eh_frame_hdr);
+    assert_eq!(args.build_id.as_deref(), Some("sha1"));
+    for ignored in ["-plugin /usr/libexec", "-m elf_x86_64", "-znow", "-static"] {
+        assert!(
+            args.ignored.iter().any(|flag| flag.starts_with(ignored)),
+            "{ignored} is not ignored: {:?}",
+            args.ignored
+        );
+    }
+}
+
+#[test]
+#[should_panic(expected = "Unknown option \"--frobnicate\"")]
+fn unknown_options_are_rejected() {
+    linker::args::parse(["--frobnicate".to_owned()].to_vec());
+}
+
+#[test]
+#[should_panic(expected = "Relocatable output (-r) is not supported")]
+fn relocatable_output_is_rejected() {
+    linker::args::parse(vec![
+        "-r".to_owned(),
+        "a.o".to_owned(),
+        "-o".to_owned(),
+        "b.o".to_owned(),
+    ]);
+}
+
+#[test]
+fn response_files_are_spliced_in() {
+    let dir = TempDir::new();
+    let paths = write_objects(&dir, &[&Object::exiting(0)]);
+    let spaced = dir.write("with space.o", &Object::x86_64().bytes());
+    let spaced = spaced.to_str().unwrap();
+    let out = dir.path().join("out");
+    let out = out.to_str().unwrap();
+
+    // Quoted and escaped paths, and a nested response file
+    let nested = dir.write("nested.rsp", format!("\"{spaced}\"\n").as_bytes());
+    let contents = format!(
+        "-o {}\n{} @{}\n",
+        out.replace(' ', "\\ "),
+        paths[0],
+        nested.display()
+    );
+    let response = dir.write("args.rsp", contents.as_bytes());
+    let expanded = linker::args::parse(vec![
+        "--gc-sections".to_owned(),
+        format!("@{}", response.display()),
+    ]);
+    let direct = linker::args::parse(
+        ["--gc-sections", "-o", out, &paths[0], spaced]
+            .map(str::to_owned)
+            .to_vec(),
+    );
+    assert_eq!(format!("{expanded:?}"), format!("{direct:?}"));
+    assert_eq!(expanded.inputs.len(), 2);
+}
+
+#[test]
+#[should_panic(expected = "Response files nested too deeply")]
+fn recursive_response_files_are_rejected() {
+    let dir = TempDir::new();
+    let path = dir.path().join("self.rsp");
+    std::fs::write(&path, format!("@{}", path.display())).unwrap();
+    linker::args::parse(vec![format!("@{}", path.display())]);
+}
+
+#[test]
+fn config_comes_from_the_arguments() {
+    let dir = TempDir::new();
+    let config = |flags: &[&str]| {
+        let args = parse_args(&dir, flags);
+        linker::config::Config::from_args(&args).unwrap()
+    };
+    let defaults = config(&[]);
+    assert_eq!(defaults.base_addr, 0x40_0000);
+    assert_eq!(defaults.segment_file_align, 0x1000);
+    assert_eq!(defaults.page_size, 0x1000);
+
+    let flags = [
+        "--image-base",
+        "0x200000",
+        "--file-align",
+        "0x200",
+        "-z",
+        "max-page-size=0x10000",
+    ];
+    let overrides = config(&flags);
+    assert_eq!(overrides.base_addr, 0x20_0000);
+    assert_eq!(overrides.segment_file_align, 0x200);
+    assert_eq!(overrides.page_size, 0x1_0000);
+    assert_eq!(config(&["-pie"]).base_addr, 0);
+
+    // Arguments built by other means are checked too
+    let mut args = parse_args(&dir, &[]);
+    args.file_align = Some(0x300);
+    let err = linker::config::Config::from_args(&args).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "The segment file alignment 0x300 is not a power of two"
+    );
+}
+
+#[test]
+fn invalid_option_values_are_errors() {
+    let dir = TempDir::new();
+    for (flags, expected) in [
+        (&["-e", "0xzz"][..], "Invalid entry address \"0xzz\""),
+        (&["--defsym", "a=1q"], "Invalid --defsym value \"1q\""),
+        (&["--build-id=uuid"], "Unsupported build ID style \"uuid\""),
+        (
+            &["--oformat", "pe-x86-64"],
+            "Unsupported output format \"pe-x86-64\"",
+        ),
+        (&["--icf=some"], "Unsupported --icf mode \"some\""),
+    ] {
+        let args = parse_args(&dir, flags);
+        let err = linker::config::Config::from_args(&args).unwrap_err();
+        assert_eq!(err.to_string(), expected);
+    }
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use common::*;
+
+/// Object with a copy of the COMDAT function `inline`, returning `value`
+fn with_inline(value: u32) -> (Object, usize) {
+    let mut object = Object::x86_64();
+    let text = object.text(".text.inline", Code::new().set_eax(value).ret());
+    object.weak("inline", text, 0);
+    object.comdat("inline", &[text]);
+    (object, text)
+}
+
+#[test]
+fn section_references_into_discarded_copies_use_the_kept_one() {
+    let (kept, _) = with_inline(7);
+    // The duplicate calls its copy through the section symbol
+    let (mut duplicate, inline) = with_inline(9);
+    let text = duplicate.text(".text", Code::new().call(Ref::Section(inline)).ret());
+    duplicate.func("helper", text, 0);
+    let mut main = Object::x86_64();
+    let text = main.text(
+        ".text",
+        Code::new().call(sym("helper")).eax_to_edi().exit_edi(),
+    );
+    main.func("_start", text, 0);
+
+    for flags in [&[][..], &["--gc-sections"]] {
+        let bytes = link(&[&main, &kept, &duplicate], flags).unwrap();
+        check_with_readelf(&bytes);
+        assert_exits(&bytes, 7);
+    }
+}
+
+#[test]
+fn symbol_references_use_the_kept_copy() {
+    let (kept, _) = with_inline(7);
+    let (duplicate, _) = with_inline(9);
+    let mut main = Object::x86_64();
+    let text = main.text(
+        ".text",
+        Code::new().call(sym("inline")).eax_to_edi().exit_edi(),
+    );
+    main.func("_start", text, 0);
+
+    let bytes = link(&[&main, &kept, &duplicate], &[]).unwrap();
+    assert_exits(&bytes, 7);
+    // Only one copy is linked
+    let elf = parse(&bytes);
+    let text = &program_headers(&elf, goblin::elf::program_header::PT_LOAD)[0];
+    let image = &bytes[text.p_offset as usize..][..text.p_filesz as usize];
+    let copies = image.windows(6).filter(|w| w[0] == 0xb8 && w[5] == 0xc3);
+    assert_eq!(copies.count(), 1);
+}
+
+#[test]
+fn groups_past_the_end_of_the_file_are_an_error() {
+    let (object, _) = with_inline(7);
+    let mut bytes = object.bytes();
+    let elf = parse(&bytes);
+    let group = elf
+        .section_headers
+        .iter()
+        .position(|header| header.sh_type == goblin::elf::section_header::SHT_GROUP)
+        .unwrap();
+    // `sh_size` of the group's section header
+    let size = elf.header.e_shoff as usize + group * 0x40 + 0x20;
+    bytes[size..size + 8].copy_from_slice(&0x10000u64.to_le_bytes());
+
+    let dir = TempDir::new();
+    let path = dir.write("group.o", &bytes);
+    let args = parse_args(&dir, &[path.to_str().unwrap()]);
+    let config = linker::config::Config::from_args(&args).unwrap();
+    let err = format!("{:#}", linker::link(&config, &args.inputs).unwrap_err());
+    assert!(err.contains("group.o"), "{err}");
+    assert!(err.contains("past the end of the file"), "{err}");
+}
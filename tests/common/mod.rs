@@ -0,0 +1,931 @@
+//! Shared test helpers: an emitter for small relocatable ELF objects and
+//! wrappers that link them through the library, as the command line would.
+
+#![allow(dead_code, unused_imports)]
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub use goblin::elf::header::{EM_386, EM_AARCH64, EM_X86_64};
+pub use goblin::elf::reloc::*;
+pub use goblin::elf::section_header::{
+    SHF_ALLOC, SHF_EXECINSTR, SHF_GROUP, SHF_MERGE, SHF_STRINGS, SHF_TLS, SHF_WRITE, SHT_NOBITS,
+    SHT_NOTE, SHT_PROGBITS,
+};
+pub use goblin::elf::sym::{STB_GLOBAL, STB_LOCAL, STB_WEAK, STT_FUNC, STT_NOTYPE, STT_OBJECT};
+
+use goblin::elf::section_header::{SHT_GROUP, SHT_REL, SHT_RELA, SHT_STRTAB, SHT_SYMTAB};
+use goblin::elf::sym::STT_SECTION;
+
+/// `STT_GNU_IFUNC`, which goblin doesn't name
+pub const STT_GNU_IFUNC: u8 = 10;
+const SHN_ABS: u16 = 0xfff1;
+const SHN_COMMON: u16 = 0xfff2;
+const GRP_COMDAT: u32 = 1;
+
+/// What a relocation or symbol refers to
+#[derive(Debug, Clone)]
+pub enum Ref {
+    Symbol(String),
+    /// Index returned when adding the section
+    Section(usize),
+}
+
+pub fn sym(name: &str) -> Ref {
+    Ref::Symbol(name.to_owned())
+}
+
+#[derive(Debug, Clone)]
+pub struct Reloc {
+    pub offset: u64,
+    pub r_type: u32,
+    pub target: Ref,
+    pub addend: i64,
+}
+
+#[derive(Debug, Clone)]
+pub enum Shndx {
+    Section(usize),
+    Undefined,
+    Absolute,
+    /// Alignment goes to the value of common symbols
+    Common,
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub value: u64,
+    pub size: u64,
+    pub bind: u8,
+    pub typ: u8,
+    pub shndx: Shndx,
+}
+
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub name: String,
+    pub sh_type: u32,
+    pub flags: u64,
+    pub addr: u64,
+    pub align: u64,
+    pub entsize: u64,
+    pub data: Vec<u8>,
+    /// Size of `SHT_NOBITS` sections, which have no data
+    pub nobits_size: u64,
+    pub relocs: Vec<Reloc>,
+}
+
+/// Relocatable ELF object under construction. Sections get indices from 1
+/// in the order they are added, followed by the relocation sections, the
+/// groups and the symbol and string tables.
+#[derive(Debug, Clone)]
+pub struct Object {
+    pub is_64: bool,
+    pub little_endian: bool,
+    pub machine: u16,
+    /// `SHT_REL` with the addends stored in the patched fields, instead of `SHT_RELA`
+    pub use_rel: bool,
+    pub e_type: u16,
+    pub sections: Vec<Section>,
+    pub symbols: Vec<Symbol>,
+    /// COMDAT groups, as (signature symbol, member sections)
+    pub groups: Vec<(String, Vec<usize>)>,
+}
+
+impl Default for Object {
+    fn default() -> Self {
+        Self::x86_64()
+    }
+}
+
+impl Object {
+    pub fn x86_64() -> Self {
+        Object {
+            is_64: true,
+            little_endian: true,
+            machine: EM_X86_64,
+            use_rel: false,
+            e_type: 1,
+            sections: Vec::new(),
+            symbols: Vec::new(),
+            groups: Vec::new(),
+        }
+    }
+
+    pub fn i386() -> Self {
+        Object {
+            is_64: false,
+            machine: EM_386,
+            use_rel: true,
+            ..Self::x86_64()
+        }
+    }
+
+    pub fn aarch64(little_endian: bool) -> Self {
+        Object {
+            little_endian,
+            machine: EM_AARCH64,
+            ..Self::x86_64()
+        }
+    }
+
+    /// Add a section, returning its index
+    pub fn section(
+        &mut self,
+        name: &str,
+        sh_type: u32,
+        flags: u32,
+        align: u64,
+        data: &[u8],
+    ) -> usize {
+        self.sections.push(Section {
+            name: name.to_owned(),
+            sh_type,
+            flags: flags as u64,
+            addr: 0,
+            align,
+            entsize: 0,
+            data: data.to_vec(),
+            nobits_size: 0,
+            relocs: Vec::new(),
+        });
+        self.sections.len()
+    }
+
+    /// Executable code, with the relocations of `code`
+    pub fn text(&mut self, name: &str, code: Code) -> usize {
+        let index = self.section(
+            name,
+            SHT_PROGBITS,
+            SHF_ALLOC | SHF_EXECINSTR,
+            16,
+            &code.bytes,
+        );
+        self.sections[index - 1].relocs = code.relocs;
+        index
+    }
+
+    /// Read-only data
+    pub fn rodata(&mut self, name: &str, align: u64, data: &[u8]) -> usize {
+        self.section(name, SHT_PROGBITS, SHF_ALLOC, align, data)
+    }
+
+    /// Writable data
+    pub fn data(&mut self, name: &str, align: u64, data: &[u8]) -> usize {
+        self.section(name, SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, align, data)
+    }
+
+    /// Zero-initialized data
+    pub fn bss(&mut self, name: &str, align: u64, size: u64) -> usize {
+        let index = self.section(name, SHT_NOBITS, SHF_ALLOC | SHF_WRITE, align, &[]);
+        self.sections[index - 1].nobits_size = size;
+        index
+    }
+
+    /// Null-terminated strings that may be merged with identical ones
+    pub fn strings(&mut self, name: &str, data: &[u8]) -> usize {
+        let index = self.section(
+            name,
+            SHT_PROGBITS,
+            SHF_ALLOC | SHF_MERGE | SHF_STRINGS,
+            1,
+            data,
+        );
+        self.sections[index - 1].entsize = 1;
+        index
+    }
+
+    pub fn reloc(&mut self, section: usize, offset: u64, r_type: u32, target: Ref, addend: i64) {
+        self.sections[section - 1].relocs.push(Reloc {
+            offset,
+            r_type,
+            target,
+            addend,
+        });
+    }
+
+    pub fn symbol(&mut self, name: &str, bind: u8, typ: u8, shndx: Shndx, value: u64, size: u64) {
+        self.symbols.push(Symbol {
+            name: name.to_owned(),
+            value,
+            size,
+            bind,
+            typ,
+            shndx,
+        });
+    }
+
+    /// Global function at an offset of a section
+    pub fn func(&mut self, name: &str, section: usize, value: u64) {
+        self.symbol(
+            name,
+            STB_GLOBAL,
+            STT_FUNC,
+            Shndx::Section(section),
+            value,
+            0,
+        );
+    }
+
+    /// Global object with a size
+    pub fn object(&mut self, name: &str, section: usize, value: u64, size: u64) {
+        self.symbol(
+            name,
+            STB_GLOBAL,
+            STT_OBJECT,
+            Shndx::Section(section),
+            value,
+            size,
+        );
+    }
+
+    pub fn local(&mut self, name: &str, section: usize, value: u64) {
+        self.symbol(
+            name,
+            STB_LOCAL,
+            STT_NOTYPE,
+            Shndx::Section(section),
+            value,
+            0,
+        );
+    }
+
+    pub fn weak(&mut self, name: &str, section: usize, value: u64) {
+        self.symbol(
+            name,
+            STB_WEAK,
+            STT_NOTYPE,
+            Shndx::Section(section),
+            value,
+            0,
+        );
+    }
+
+    pub fn weak_undefined(&mut self, name: &str) {
+        self.symbol(name, STB_WEAK, STT_NOTYPE, Shndx::Undefined, 0, 0);
+    }
+
+    pub fn absolute(&mut self, name: &str, value: u64) {
+        self.symbol(name, STB_GLOBAL, STT_NOTYPE, Shndx::Absolute, value, 0);
+    }
+
+    /// Tentative definition, like `int x;` with `-fcommon`
+    pub fn common(&mut self, name: &str, size: u64, align: u64) {
+        self.symbol(name, STB_GLOBAL, STT_OBJECT, Shndx::Common, align, size);
+    }
+
+    /// COMDAT group of sections, identified by a symbol defined in one of them
+    pub fn comdat(&mut self, signature: &str, sections: &[usize]) {
+        for &index in sections {
+            self.sections[index - 1].flags |= SHF_GROUP as u64;
+        }
+        self.groups.push((signature.to_owned(), sections.to_vec()));
+    }
+
+    /// Code at `_start` that exits with `status`
+    pub fn exiting(status: u8) -> Self {
+        let mut object = Self::x86_64();
+        let text = object.text(".text", Code::new().exit(status));
+        object.func("_start", text, 0);
+        object
+    }
+
+    fn put_u16(&self, out: &mut Vec<u8>, value: u16) {
+        out.extend(if self.little_endian {
+            value.to_le_bytes()
+        } else {
+            value.to_be_bytes()
+        });
+    }
+
+    fn put_u32(&self, out: &mut Vec<u8>, value: u32) {
+        out.extend(if self.little_endian {
+            value.to_le_bytes()
+        } else {
+            value.to_be_bytes()
+        });
+    }
+
+    fn put_u64(&self, out: &mut Vec<u8>, value: u64) {
+        out.extend(if self.little_endian {
+            value.to_le_bytes()
+        } else {
+            value.to_be_bytes()
+        });
+    }
+
+    /// Address-sized field
+    fn put_word(&self, out: &mut Vec<u8>, value: u64) {
+        if self.is_64 {
+            self.put_u64(out, value);
+        } else {
+            self.put_u32(out, value as u32);
+        }
+    }
+
+    /// Serialize as an ELF relocatable object
+    pub fn bytes(&self) -> Vec<u8> {
+        let section_count = self.sections.len();
+
+        // Symbols: null, one per section, locals, then the rest. Names that are
+        // referenced but not declared become undefined globals.
+        let mut symbols: Vec<Symbol> = (1..=section_count)
+            .map(|index| Symbol {
+                name: String::new(),
+                value: 0,
+                size: 0,
+                bind: STB_LOCAL,
+                typ: STT_SECTION,
+                shndx: Shndx::Section(index),
+            })
+            .collect();
+        symbols.extend(self.symbols.iter().filter(|s| s.bind == STB_LOCAL).cloned());
+        let first_global = symbols.len() + 1;
+        symbols.extend(self.symbols.iter().filter(|s| s.bind != STB_LOCAL).cloned());
+        let references = self
+            .sections
+            .iter()
+            .flat_map(|s| &s.relocs)
+            .filter_map(|r| match &r.target {
+                Ref::Symbol(name) => Some(name.clone()),
+                Ref::Section(_) => None,
+            })
+            .chain(self.groups.iter().map(|(signature, _)| signature.clone()));
+        for name in references {
+            if !symbols.iter().any(|s| s.name == name) {
+                symbols.push(Symbol {
+                    name,
+                    value: 0,
+                    size: 0,
+                    bind: STB_GLOBAL,
+                    typ: STT_NOTYPE,
+                    shndx: Shndx::Undefined,
+                });
+            }
+        }
+        let symbol_index = |target: &Ref| -> u32 {
+            match target {
+                Ref::Section(index) => *index as u32,
+                Ref::Symbol(name) => {
+                    symbols.iter().position(|s| &s.name == name).unwrap() as u32 + 1
+                }
+            }
+        };
+
+        let mut strtab = vec![0u8];
+        let mut symtab = Vec::new();
+        let sym_size = if self.is_64 { 24 } else { 16 };
+        symtab.resize(sym_size, 0);
+        for symbol in &symbols {
+            let name = if symbol.name.is_empty() {
+                0
+            } else {
+                let offset = strtab.len() as u32;
+                strtab.extend(symbol.name.as_bytes());
+                strtab.push(0);
+                offset
+            };
+            let shndx = match symbol.shndx {
+                Shndx::Section(index) => index as u16,
+                Shndx::Undefined => 0,
+                Shndx::Absolute => SHN_ABS,
+                Shndx::Common => SHN_COMMON,
+            };
+            let info = symbol.bind << 4 | symbol.typ;
+            self.put_u32(&mut symtab, name);
+            if self.is_64 {
+                symtab.extend([info, 0]);
+                self.put_u16(&mut symtab, shndx);
+                self.put_u64(&mut symtab, symbol.value);
+                self.put_u64(&mut symtab, symbol.size);
+            } else {
+                self.put_u32(&mut symtab, symbol.value as u32);
+                self.put_u32(&mut symtab, symbol.size as u32);
+                symtab.extend([info, 0]);
+                self.put_u16(&mut symtab, shndx);
+            }
+        }
+
+        // Contents of the relocation sections, for sections that have relocations
+        let mut relocations: Vec<(usize, Vec<u8>)> = Vec::new();
+        let mut section_data: Vec<Vec<u8>> = self.sections.iter().map(|s| s.data.clone()).collect();
+        for (i, section) in self.sections.iter().enumerate() {
+            if section.relocs.is_empty() {
+                continue;
+            }
+            let mut contents = Vec::new();
+            for reloc in &section.relocs {
+                let sym = symbol_index(&reloc.target);
+                self.put_word(&mut contents, reloc.offset);
+                if self.is_64 {
+                    self.put_u64(&mut contents, (sym as u64) << 32 | reloc.r_type as u64);
+                } else {
+                    self.put_u32(&mut contents, sym << 8 | reloc.r_type);
+                }
+                if self.use_rel {
+                    // The addend goes to the field, assumed to be 4 bytes
+                    let field = &mut section_data[i][reloc.offset as usize..][..4];
+                    let addend = reloc.addend as i32;
+                    field.copy_from_slice(&if self.little_endian {
+                        addend.to_le_bytes()
+                    } else {
+                        addend.to_be_bytes()
+                    });
+                } else {
+                    self.put_word(&mut contents, reloc.addend as u64);
+                }
+            }
+            relocations.push((i + 1, contents));
+        }
+
+        let symtab_index = (section_count + relocations.len() + self.groups.len() + 1) as u32;
+        let mut groups = Vec::new();
+        for (signature, members) in &self.groups {
+            let mut contents = Vec::new();
+            self.put_u32(&mut contents, GRP_COMDAT);
+            for &member in members {
+                self.put_u32(&mut contents, member as u32);
+            }
+            groups.push((symbol_index(&Ref::Symbol(signature.clone())), contents));
+        }
+
+        // (name, type, flags, addr, contents or size, link, info, align, entsize)
+        struct Header {
+            name: String,
+            sh_type: u32,
+            flags: u64,
+            addr: u64,
+            contents: Vec<u8>,
+            size: u64,
+            link: u32,
+            info: u32,
+            align: u64,
+            entsize: u64,
+        }
+        let mut headers = Vec::new();
+        for (section, data) in self.sections.iter().zip(section_data) {
+            headers.push(Header {
+                name: section.name.clone(),
+                sh_type: section.sh_type,
+                flags: section.flags,
+                addr: section.addr,
+                size: if section.sh_type == SHT_NOBITS {
+                    section.nobits_size
+                } else {
+                    data.len() as u64
+                },
+                contents: data,
+                link: 0,
+                info: 0,
+                align: section.align,
+                entsize: section.entsize,
+            });
+        }
+        let (word, reloc_size) = match (self.is_64, self.use_rel) {
+            (true, false) => (8, 24),
+            (true, true) => (8, 16),
+            (false, false) => (4, 12),
+            (false, true) => (4, 8),
+        };
+        for (target, contents) in relocations {
+            let prefix = if self.use_rel { ".rel" } else { ".rela" };
+            headers.push(Header {
+                name: format!("{prefix}{}", self.sections[target - 1].name),
+                sh_type: if self.use_rel { SHT_REL } else { SHT_RELA },
+                flags: 0,
+                addr: 0,
+                size: contents.len() as u64,
+                contents,
+                link: symtab_index,
+                info: target as u32,
+                align: word,
+                entsize: reloc_size,
+            });
+        }
+        for (signature, contents) in groups {
+            headers.push(Header {
+                name: ".group".to_owned(),
+                sh_type: SHT_GROUP,
+                flags: 0,
+                addr: 0,
+                size: contents.len() as u64,
+                contents,
+                link: symtab_index,
+                info: signature,
+                align: 4,
+                entsize: 4,
+            });
+        }
+        headers.push(Header {
+            name: ".symtab".to_owned(),
+            sh_type: SHT_SYMTAB,
+            flags: 0,
+            addr: 0,
+            size: symtab.len() as u64,
+            contents: symtab,
+            link: symtab_index + 1,
+            info: first_global as u32,
+            align: word,
+            entsize: sym_size as u64,
+        });
+        headers.push(Header {
+            name: ".strtab".to_owned(),
+            sh_type: SHT_STRTAB,
+            flags: 0,
+            addr: 0,
+            size: strtab.len() as u64,
+            contents: strtab,
+            link: 0,
+            info: 0,
+            align: 1,
+            entsize: 0,
+        });
+        let mut shstrtab = vec![0u8];
+        let mut names = Vec::new();
+        for header in headers.iter().map(|h| h.name.as_str()).chain([".shstrtab"]) {
+            names.push(shstrtab.len() as u32);
+            shstrtab.extend(header.as_bytes());
+            shstrtab.push(0);
+        }
+        headers.push(Header {
+            name: ".shstrtab".to_owned(),
+            sh_type: SHT_STRTAB,
+            flags: 0,
+            addr: 0,
+            size: shstrtab.len() as u64,
+            contents: shstrtab,
+            link: 0,
+            info: 0,
+            align: 1,
+            entsize: 0,
+        });
+
+        // File header, section contents, then the section header table
+        let header_size = if self.is_64 { 64 } else { 52 };
+        let mut contents = Vec::new();
+        let mut offsets = Vec::new();
+        for header in &headers {
+            let offset = header_size + contents.len();
+            let padding = (header.align.max(1) as usize - offset % header.align.max(1) as usize)
+                % header.align.max(1) as usize;
+            contents.resize(contents.len() + padding, 0);
+            offsets.push((header_size + contents.len()) as u64);
+            contents.extend(&header.contents);
+        }
+        contents.resize(contents.len().next_multiple_of(8), 0);
+        let shoff = (header_size + contents.len()) as u64;
+
+        let mut out = vec![0x7f, b'E', b'L', b'F'];
+        out.push(if self.is_64 { 2 } else { 1 });
+        out.push(if self.little_endian { 1 } else { 2 });
+        out.push(1);
+        out.resize(16, 0);
+        self.put_u16(&mut out, self.e_type);
+        self.put_u16(&mut out, self.machine);
+        self.put_u32(&mut out, 1);
+        self.put_word(&mut out, 0);
+        self.put_word(&mut out, 0);
+        self.put_word(&mut out, shoff);
+        self.put_u32(&mut out, 0);
+        self.put_u16(&mut out, header_size as u16);
+        self.put_u16(&mut out, 0);
+        self.put_u16(&mut out, 0);
+        self.put_u16(&mut out, if self.is_64 { 64 } else { 40 });
+        self.put_u16(&mut out, headers.len() as u16 + 1);
+        self.put_u16(&mut out, headers.len() as u16);
+        out.extend(contents);
+
+        // Null section header first
+        out.resize(out.len() + if self.is_64 { 64 } else { 40 }, 0);
+        for ((header, offset), name) in headers.iter().zip(offsets).zip(names) {
+            self.put_u32(&mut out, name);
+            self.put_u32(&mut out, header.sh_type);
+            self.put_word(&mut out, header.flags);
+            self.put_word(&mut out, header.addr);
+            self.put_word(&mut out, offset);
+            self.put_word(&mut out, header.size);
+            self.put_u32(&mut out, header.link);
+            self.put_u32(&mut out, header.info);
+            self.put_word(&mut out, header.align);
+            self.put_word(&mut out, header.entsize);
+        }
+        out
+    }
+}
+
+/// x86-64 machine code with relocations, built one instruction at a time
+#[derive(Debug, Clone, Default)]
+pub struct Code {
+    pub bytes: Vec<u8>,
+    pub relocs: Vec<Reloc>,
+}
+
+impl Code {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn raw(mut self, bytes: &[u8]) -> Self {
+        self.bytes.extend(bytes);
+        self
+    }
+
+    /// Relocated field at the current end of the code
+    fn field(mut self, r_type: u32, target: Ref, addend: i64, size: usize) -> Self {
+        self.relocs.push(Reloc {
+            offset: self.bytes.len() as u64,
+            r_type,
+            target,
+            addend,
+        });
+        self.bytes.resize(self.bytes.len() + size, 0);
+        self
+    }
+
+    /// `exit(status)` through the raw system call
+    pub fn exit(self, status: u8) -> Self {
+        self.raw(&[0xbf, status, 0, 0, 0]).exit_edi()
+    }
+
+    /// `exit(%edi)` through the raw system call
+    pub fn exit_edi(self) -> Self {
+        self.raw(&[0xb8, 60, 0, 0, 0, 0x0f, 0x05])
+    }
+
+    /// `call target`
+    pub fn call(self, target: Ref) -> Self {
+        self.raw(&[0xe8]).field(R_X86_64_PC32, target, -4, 4)
+    }
+
+    /// `call target@PLT`, as gcc emits calls
+    pub fn call_plt(self, target: Ref) -> Self {
+        self.raw(&[0xe8]).field(R_X86_64_PLT32, target, -4, 4)
+    }
+
+    /// `mov target+addend(%rip), %edi`
+    pub fn load_edi(self, target: Ref, addend: i64) -> Self {
+        self.raw(&[0x8b, 0x3d])
+            .field(R_X86_64_PC32, target, addend - 4, 4)
+    }
+
+    /// `movzbl target+addend(%rip), %edi`
+    pub fn load_byte_edi(self, target: Ref, addend: i64) -> Self {
+        self.raw(&[0x0f, 0xb6, 0x3d])
+            .field(R_X86_64_PC32, target, addend - 4, 4)
+    }
+
+    /// `mov target@GOTPCREL(%rip), %rax`, relaxable to `lea`
+    pub fn load_got_rax(self, target: Ref) -> Self {
+        self.raw(&[0x48, 0x8b, 0x05])
+            .field(R_X86_64_REX_GOTPCRELX, target, -4, 4)
+    }
+
+    /// `lea target+addend(%rip), %rdi`
+    pub fn lea_rdi(self, target: Ref, addend: i64) -> Self {
+        self.raw(&[0x48, 0x8d, 0x3d])
+            .field(R_X86_64_PC32, target, addend - 4, 4)
+    }
+
+    /// `movzbl (%rdi), %edi`
+    pub fn deref_byte_rdi(self) -> Self {
+        self.raw(&[0x0f, 0xb6, 0x3f])
+    }
+
+    /// `movzbl (%rax), %edi`
+    pub fn deref_byte_rax(self) -> Self {
+        self.raw(&[0x0f, 0xb6, 0x38])
+    }
+
+    /// `mov %eax, %edi`
+    pub fn eax_to_edi(self) -> Self {
+        self.raw(&[0x89, 0xc7])
+    }
+
+    /// `mov $value, %eax`
+    pub fn set_eax(self, value: u32) -> Self {
+        let mut code = self.raw(&[0xb8]);
+        code.bytes.extend(value.to_le_bytes());
+        code
+    }
+
+    /// Relocated data field, e.g. an absolute pointer with `R_X86_64_64`
+    pub fn reloc(self, r_type: u32, target: Ref, addend: i64, size: usize) -> Self {
+        self.field(r_type, target, addend, size)
+    }
+
+    pub fn ret(self) -> Self {
+        self.raw(&[0xc3])
+    }
+}
+
+/// GNU `ar` archive of the objects, as (member name, object)
+pub fn archive(members: &[(&str, &Object)]) -> Vec<u8> {
+    let mut out = b"!<arch>\n".to_vec();
+    for (name, object) in members {
+        let contents = object.bytes();
+        let header = format!(
+            "{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+            format!("{name}/"),
+            0,
+            0,
+            0,
+            644,
+            contents.len()
+        );
+        assert_eq!(header.len(), 60, "member name {name:?} is too long");
+        out.extend(header.as_bytes());
+        out.extend(&contents);
+        if contents.len() % 2 == 1 {
+            out.push(b'\n');
+        }
+    }
+    out
+}
+
+/// Directory removed when dropped
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "linker-test-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Write a file into the directory, returning its path
+    pub fn write(&self, name: &str, contents: &[u8]) -> PathBuf {
+        let path = self.0.join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Write the objects as `0.o`, `1.o`, ... and return their paths
+pub fn write_objects(dir: &TempDir, objects: &[&Object]) -> Vec<String> {
+    objects
+        .iter()
+        .enumerate()
+        .map(|(i, object)| {
+            let path = dir.write(&format!("{i}.o"), &object.bytes());
+            path.to_str().unwrap().to_owned()
+        })
+        .collect()
+}
+
+/// Parse a command line, with `-o` defaulting to a file in `dir`
+pub fn parse_args(dir: &TempDir, args: &[&str]) -> linker::args::Args {
+    let mut args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+    if !args.iter().any(|a| a == "-o") {
+        args.push("-o".to_owned());
+        args.push(dir.path().join("out").to_str().unwrap().to_owned());
+    }
+    linker::args::parse(args)
+}
+
+/// Config and inputs for linking the objects with the flags
+pub fn prepare(
+    dir: &TempDir,
+    objects: &[&Object],
+    flags: &[&str],
+) -> anyhow::Result<(linker::config::Config, Vec<PathBuf>)> {
+    let paths = write_objects(dir, objects);
+    let mut args: Vec<&str> = flags.to_vec();
+    args.extend(paths.iter().map(String::as_str));
+    let args = parse_args(dir, &args);
+    let config = linker::config::Config::from_args(&args)?;
+    Ok((config, args.inputs))
+}
+
+/// Link the objects like the command line would, returning the output file
+pub fn link(objects: &[&Object], flags: &[&str]) -> anyhow::Result<Vec<u8>> {
+    let dir = TempDir::new();
+    let (config, inputs) = prepare(&dir, objects, flags)?;
+    linker::link(&config, &inputs)
+}
+
+/// Link the objects, keeping the result for inspection
+pub fn link_program(objects: &[&Object], flags: &[&str]) -> anyhow::Result<linker::Link> {
+    let dir = TempDir::new();
+    let (config, inputs) = prepare(&dir, objects, flags)?;
+    linker::link_program(&config, &inputs)
+}
+
+/// Error message of a link expected to fail, with its causes
+pub fn link_error(objects: &[&Object], flags: &[&str]) -> String {
+    match link(objects, flags) {
+        Ok(_) => panic!("link succeeded"),
+        Err(err) => format!("{err:#}"),
+    }
+}
+
+pub fn parse(bytes: &[u8]) -> goblin::elf::Elf<'_> {
+    goblin::elf::Elf::parse(bytes).expect("output is not valid ELF")
+}
+
+/// Program headers of one type
+pub fn program_headers(elf: &goblin::elf::Elf, p_type: u32) -> Vec<goblin::elf::ProgramHeader> {
+    elf.program_headers
+        .iter()
+        .filter(|ph| ph.p_type == p_type)
+        .cloned()
+        .collect()
+}
+
+/// Address of a symbol in the output, which needs `--section-headers`
+pub fn symbol_address(elf: &goblin::elf::Elf, name: &str) -> Option<u64> {
+    elf.syms
+        .iter()
+        .find(|s| elf.strtab.get_at(s.st_name) == Some(name))
+        .map(|s| s.st_value)
+}
+
+/// Bytes of the output file at a virtual address
+pub fn read_at<'a>(elf: &goblin::elf::Elf, bytes: &'a [u8], vaddr: u64, len: usize) -> &'a [u8] {
+    let ph = elf
+        .program_headers
+        .iter()
+        .find(|ph| {
+            ph.p_type == goblin::elf::program_header::PT_LOAD
+                && (ph.p_vaddr..ph.p_vaddr + ph.p_filesz).contains(&vaddr)
+        })
+        .unwrap_or_else(|| panic!("{vaddr:#x} is not in the file image"));
+    let offset = (ph.p_offset + vaddr - ph.p_vaddr) as usize;
+    &bytes[offset..offset + len]
+}
+
+/// The program and section headers make sense to `readelf`, if it's installed.
+/// Panics with its complaints otherwise.
+pub fn check_with_readelf(bytes: &[u8]) {
+    let dir = TempDir::new();
+    let path = dir.write("out", bytes);
+    let Ok(output) = std::process::Command::new("readelf")
+        .arg("-lSW")
+        .arg(&path)
+        .output()
+    else {
+        return;
+    };
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        output.status.success() && stderr.trim().is_empty(),
+        "readelf complains: {stderr}"
+    );
+}
+
+/// Run an x86-64 executable and return its exit status, or `None`
+/// where it can't run, on other hosts
+pub fn run(bytes: &[u8]) -> Option<i32> {
+    if !cfg!(all(target_os = "linux", target_arch = "x86_64")) {
+        return None;
+    }
+    let dir = TempDir::new();
+    let path = dir.write("exe", bytes);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    let status = std::process::Command::new(&path)
+        .status()
+        .expect("output can't be executed");
+    Some(status.code().expect("output was killed by a signal"))
+}
+
+/// The output runs and exits with `status`, on hosts that can run it
+pub fn assert_exits(bytes: &[u8], status: i32) {
+    if let Some(code) = run(bytes) {
+        assert_eq!(code, status, "unexpected exit status");
+    }
+}
+
+/// Run the linker executable on the objects, for its exit status and diagnostics.
+/// The output goes to `out` in `dir`, unless the flags say otherwise.
+pub fn run_linker(dir: &TempDir, objects: &[&Object], flags: &[&str]) -> std::process::Output {
+    let paths = write_objects(dir, objects);
+    let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_linker"));
+    command.args(flags).args(&paths);
+    if !flags.contains(&"-o") && !flags.contains(&"--check-only") {
+        command.arg("-o").arg(dir.path().join("out"));
+    }
+    command.output().expect("linker can't be run")
+}
+
+pub fn stderr(output: &std::process::Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
@@ -0,0 +1,34 @@
+mod common;
+
+use common::*;
+
+/// Error for a call to the undefined `name`
+fn undefined_error(name: &str, flags: &[&str]) -> String {
+    let mut main = Object::x86_64();
+    let text = main.text(".text", Code::new().call(sym(name)).exit(0));
+    main.func("_start", text, 0);
+    link_error(&[&main], flags)
+}
+
+#[test]
+fn diagnostics_show_demangled_names() {
+    for (mangled, demangled) in [
+        ("_ZN7mycrate3foo17h0123456789abcdefE", "mycrate::foo"),
+        ("_RNvNtCs1234_7mycrate3sub3foo", "mycrate::sub::foo"),
+        ("_Z3fooi", "foo(int)"),
+        ("_ZN2ns3barEv", "ns::bar()"),
+    ] {
+        let err = undefined_error(mangled, &["--demangle"]);
+        assert!(err.contains(&format!("{demangled:?}")), "{err}");
+        let err = undefined_error(mangled, &[]);
+        assert!(err.contains(&format!("{mangled:?}")), "{err}");
+    }
+}
+
+#[test]
+fn unmangled_names_are_kept() {
+    for name in ["plain_c_name", "_ZN3foo"] {
+        let err = undefined_error(name, &["--demangle"]);
+        assert!(err.contains(&format!("{name:?}")), "{err}");
+    }
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use common::*;
+
+/// CIE with `zR` augmentation and PC-relative FDE pointers, padded to 4 bytes
+const CIE: [u8; 20] = [
+    16, 0, 0, 0, // length
+    0, 0, 0, 0, // CIE id
+    1, b'z', b'R', 0, // version, augmentation
+    1, 0x78, 16, // code alignment, data alignment (-8), return address register
+    1, 0x1b, // augmentation data: FDE pointers are `pcrel | sdata4`
+    0, 0, 0, // DW_CFA_nop
+];
+
+/// FDE at `offset` of `.eh_frame`, for the first `len` bytes of a function
+fn fde(offset: u32, len: u32) -> Vec<u8> {
+    let mut fde = 16u32.to_le_bytes().to_vec();
+    fde.extend((offset + 4).to_le_bytes());
+    fde.extend(0u32.to_le_bytes());
+    fde.extend(len.to_le_bytes());
+    fde.extend([0; 4]);
+    fde
+}
+
+/// Functions `a` and `b`, whose FDEs are in the opposite order
+fn unwound_functions() -> Object {
+    let mut object = Object::exiting(0);
+    let a = object.text(".text.a", Code::new().ret());
+    object.func("a", a, 0);
+    let b = object.text(".text.b", Code::new().set_eax(1).ret());
+    object.func("b", b, 0);
+    let mut eh_frame = CIE.to_vec();
+    eh_frame.extend(fde(20, 6));
+    eh_frame.extend(fde(40, 1));
+    let eh_frame = object.section(".eh_frame", SHT_PROGBITS, SHF_ALLOC, 4, &eh_frame);
+    object.reloc(eh_frame, 28, R_X86_64_PC32, sym("b"), 0);
+    object.reloc(eh_frame, 48, R_X86_64_PC32, sym("a"), 0);
+    object
+}
+
+#[test]
+fn eh_frame_hdr_table_is_sorted_by_initial_location() {
+    let flags = ["--eh-frame-hdr", "--section-headers"];
+    let bytes = link(&[&unwound_functions()], &flags).unwrap();
+    check_with_readelf(&bytes);
+    let elf = parse(&bytes);
+    let [header] = &program_headers(&elf, goblin::elf::program_header::PT_GNU_EH_FRAME)[..] else {
+        panic!("expected one PT_GNU_EH_FRAME");
+    };
+    let hdr = header.p_vaddr;
+    let contents = read_at(&elf, &bytes, hdr, header.p_memsz as usize);
+    let word = |offset: usize| i32::from_le_bytes(contents[offset..offset + 4].try_into().unwrap());
+    assert_eq!(contents[0], 1, "version");
+    assert_eq!(word(8), 2, "FDE count");
+    assert_eq!(header.p_memsz, 12 + 2 * 8);
+
+    let locations: Vec<u64> = (0..2)
+        .map(|i| hdr.wrapping_add_signed(word(12 + 8 * i) as i64))
+        .collect();
+    let functions = ["a", "b"].map(|name| symbol_address(&elf, name).unwrap());
+    assert!(functions[0] < functions[1]);
+    assert_eq!(locations, functions);
+}
@@ -0,0 +1,89 @@
+mod common;
+
+use common::*;
+
+#[test]
+fn entry_is_the_address_of_start() {
+    // `_start` follows the function of the first input in `.text`
+    let mut first = Object::x86_64();
+    let text = first.text(".text", Code::new().exit(1));
+    first.func("other", text, 0);
+    let bytes = link(&[&first, &Object::exiting(42)], &["--section-headers"]).unwrap();
+    let elf = parse(&bytes);
+    let start = symbol_address(&elf, "_start").unwrap();
+    assert!(start > symbol_address(&elf, "other").unwrap());
+    assert_eq!(elf.header.e_entry, start);
+    assert_exits(&bytes, 42);
+}
+
+#[test]
+fn missing_start_is_an_error() {
+    let mut object = Object::x86_64();
+    let text = object.text(".text", Code::new().exit(0));
+    object.func("main", text, 0);
+    let error = link_error(&[&object], &[]);
+    assert!(
+        error.contains("Entry symbol \"_start\" is not defined"),
+        "{error}"
+    );
+    assert!(error.contains("use -e SYMBOL"), "{error}");
+
+    // Reported before the references that can't be resolved either
+    let mut object = Object::x86_64();
+    let text = object.text(".text", Code::new().call(sym("missing")).exit(0));
+    object.func("main", text, 0);
+    let error = link_error(&[&object], &[]);
+    assert!(
+        error.contains("Entry symbol \"_start\" is not defined"),
+        "{error}"
+    );
+    assert!(!error.contains("missing"), "{error}");
+}
+
+/// `_start` exiting with 1, and `kernel_start` exiting with 42
+fn two_entries() -> Object {
+    let mut object = Object::exiting(1);
+    let text = object.text(".text.kernel", Code::new().exit(42));
+    object.func("kernel_start", text, 0);
+    object
+}
+
+#[test]
+fn entry_symbol_can_be_chosen() {
+    for flags in [&["-e", "kernel_start"][..], &["--entry=kernel_start"]] {
+        let flags = [flags, &["--section-headers"]].concat();
+        let bytes = link(&[&two_entries()], &flags).unwrap();
+        let elf = parse(&bytes);
+        assert_eq!(
+            elf.header.e_entry,
+            symbol_address(&elf, "kernel_start").unwrap()
+        );
+        assert_exits(&bytes, 42);
+    }
+
+    let error = link_error(&[&two_entries()], &["-e", "missing"]);
+    assert!(
+        error.contains("Entry symbol \"missing\" is not defined"),
+        "{error}"
+    );
+}
+
+#[test]
+fn entry_address_bypasses_symbols() {
+    let bytes = link(&[&two_entries()], &["--section-headers"]).unwrap();
+    let kernel_start = symbol_address(&parse(&bytes), "kernel_start").unwrap();
+
+    // The layout doesn't depend on the entry, so the address is that of `kernel_start`
+    for address in [format!("{kernel_start:#x}"), kernel_start.to_string()] {
+        let bytes = link(&[&two_entries()], &["-e", &address]).unwrap();
+        assert_eq!(parse(&bytes).header.e_entry, kernel_start);
+        assert_exits(&bytes, 42);
+    }
+
+    // No symbol is needed for it
+    let mut object = Object::x86_64();
+    let text = object.text(".text", Code::new().exit(0));
+    object.func("main", text, 0);
+    let bytes = link(&[&object], &["-e", "0x401000"]).unwrap();
+    assert_eq!(parse(&bytes).header.e_entry, 0x40_1000);
+}
@@ -0,0 +1,39 @@
+mod common;
+
+use common::*;
+
+/// `_start` in a section of its own, and an unreferenced function
+fn entry_and_unused() -> Object {
+    let mut object = Object::x86_64();
+    let start = object.text(".text._start", Code::new().exit(42));
+    object.func("_start", start, 0);
+    let unused = object.text(".text.unused", Code::new().raw(&[0xcc; 32]));
+    object.func("unused", unused, 0);
+    object
+}
+
+fn text_size(bytes: &[u8]) -> u64 {
+    let elf = parse(bytes);
+    elf.section_headers
+        .iter()
+        .find(|sh| elf.shdr_strtab.get_at(sh.sh_name) == Some(".text"))
+        .expect(".text missing")
+        .sh_size
+}
+
+#[test]
+fn gc_keeps_unreferenced_entry_section() {
+    let object = entry_and_unused();
+    let kept = link(&[&object], &["--section-headers"]).unwrap();
+    let collected = link(&[&object], &["--section-headers", "--gc-sections"]).unwrap();
+    assert_eq!(text_size(&kept), 16 + 32);
+    // Only `_start` is left, though nothing refers to it
+    assert_eq!(text_size(&collected), 12);
+    assert_exits(&collected, 42);
+}
+
+#[test]
+fn gc_errors_on_undefined_entry() {
+    let error = link_error(&[&entry_and_unused()], &["--gc-sections", "-e", "missing"]);
+    assert!(error.contains("Entry symbol \"missing\""), "{error}");
+}
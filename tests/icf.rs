@@ -0,0 +1,68 @@
+mod common;
+
+use common::*;
+
+/// `f` and `g` return the same value, and `f` is stored in a function pointer.
+/// `call_f` and `call_g` only differ in which of them they call.
+fn object() -> Object {
+    let mut object = Object::x86_64();
+    for name in ["f", "g"] {
+        let text = object.text(&format!(".text.{name}"), Code::new().set_eax(7).ret());
+        object.func(name, text, 0);
+    }
+    for name in ["f", "g"] {
+        let code = Code::new().call(sym(name)).ret();
+        let text = object.text(&format!(".text.call_{name}"), code);
+        object.func(&format!("call_{name}"), text, 0);
+    }
+    let data = object.data(".data", 8, &[0; 8]);
+    object.reloc(data, 0, R_X86_64_64, sym("f"), 0);
+    object.object("pointer", data, 0, 8);
+    let start = Code::new()
+        .call(sym("call_f"))
+        .call(sym("call_g"))
+        .eax_to_edi()
+        .exit_edi();
+    let text = object.text(".text", start);
+    object.func("_start", text, 0);
+    object
+}
+
+/// Addresses of `f`, `g`, `call_f` and `call_g`
+fn addresses(flags: &[&str]) -> [u64; 4] {
+    let mut flags = flags.to_vec();
+    flags.push("--section-headers");
+    let bytes = link(&[&object()], &flags).unwrap();
+    check_with_readelf(&bytes);
+    assert_exits(&bytes, 7);
+    let elf = parse(&bytes);
+    ["f", "g", "call_f", "call_g"].map(|name| symbol_address(&elf, name).unwrap())
+}
+
+#[test]
+fn all_folds_identical_functions() {
+    let [f, g, call_f, call_g] = addresses(&["--icf=all"]);
+    assert_eq!(f, g);
+    // Equal once what they call is folded
+    assert_eq!(call_f, call_g);
+    // The pointer refers to the kept copy
+    let bytes = link(&[&object()], &["--icf=all", "--section-headers"]).unwrap();
+    let elf = parse(&bytes);
+    let pointer = symbol_address(&elf, "pointer").unwrap();
+    assert_eq!(read_at(&elf, &bytes, pointer, 8), f.to_le_bytes());
+}
+
+#[test]
+fn safe_keeps_address_taken_functions_unique() {
+    let [f, g, call_f, call_g] = addresses(&["--icf=safe"]);
+    assert_ne!(f, g);
+    assert_ne!(call_f, call_g);
+}
+
+#[test]
+fn keep_unique_is_never_folded() {
+    let [f, g, ..] = addresses(&["--icf=all", "--keep-unique=g"]);
+    assert_ne!(f, g);
+    let [f, g, ..] = addresses(&["--icf=none"]);
+    assert_ne!(f, g);
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use common::*;
+
+/// `lea target(%rip), %reg` for the register encoded in the ModRM byte
+fn lea(code: Code, modrm: u8, target: &str) -> Code {
+    code.raw(&[0x48, 0x8d, modrm])
+        .reloc(R_X86_64_PC32, sym(target), -4, 4)
+}
+
+/// `_start` runs the `IRELATIVE` relocations between `__rela_iplt_start` and
+/// `__rela_iplt_end` like startup code would, then exits with what `pick` returns.
+/// `pick` is an IFUNC whose resolver chooses `seven`.
+fn ifunc_program() -> Object {
+    let mut object = Object::x86_64();
+    let code = lea(Code::new(), 0x1d, "__rela_iplt_start");
+    let code = lea(code, 0x05, "__rela_iplt_end")
+        .raw(&[0x48, 0x39, 0xc3]) // cmp %rax, %rbx
+        .raw(&[0x73, 0x12]) // jae done
+        .raw(&[0x48, 0x8b, 0x43, 0x10]) // mov 16(%rbx), %rax: the resolver
+        .raw(&[0xff, 0xd0]) // call *%rax
+        .raw(&[0x48, 0x8b, 0x0b]) // mov (%rbx), %rcx: the slot
+        .raw(&[0x48, 0x89, 0x01]) // mov %rax, (%rcx)
+        .raw(&[0x48, 0x83, 0xc3, 0x18]) // add $24, %rbx
+        .raw(&[0xeb, 0xe2]) // jmp to the second lea
+        .call(sym("pick")) // done:
+        .eax_to_edi()
+        .exit_edi();
+    let text = object.text(".text", code);
+    object.func("_start", text, 0);
+
+    let text = object.text(".text.seven", Code::new().set_eax(7).ret());
+    object.func("seven", text, 0);
+    let resolver = object.text(".text.pick", lea(Code::new(), 0x05, "seven").ret());
+    object.symbol(
+        "pick",
+        STB_GLOBAL,
+        STT_GNU_IFUNC,
+        Shndx::Section(resolver),
+        0,
+        8,
+    );
+    object
+}
+
+#[test]
+fn ifuncs_are_resolved_through_irelative_relocations() {
+    let bytes = link(&[&ifunc_program()], &["--section-headers"]).unwrap();
+    check_with_readelf(&bytes);
+    assert_exits(&bytes, 7);
+
+    let elf = parse(&bytes);
+    let section = |name: &str| {
+        elf.section_headers
+            .iter()
+            .find(|sh| elf.shdr_strtab.get_at(sh.sh_name) == Some(name))
+            .unwrap()
+            .clone()
+    };
+    let rela_plt = section(".rela.plt");
+    assert_eq!(rela_plt.sh_size, 24);
+    let rela = read_at(&elf, &bytes, rela_plt.sh_addr, 24);
+    let word = |i: usize| u64::from_le_bytes(rela[8 * i..8 * i + 8].try_into().unwrap());
+    let got_plt = section(".got.plt");
+    assert_eq!(word(0), got_plt.sh_addr);
+    assert_eq!(word(1), R_X86_64_IRELATIVE as u64);
+    assert_eq!(Some(word(2)), symbol_address(&elf, "pick"));
+    assert_eq!(
+        symbol_address(&elf, "__rela_iplt_start"),
+        Some(rela_plt.sh_addr)
+    );
+    assert_eq!(
+        symbol_address(&elf, "__rela_iplt_end"),
+        Some(rela_plt.sh_addr + 24)
+    );
+}
@@ -0,0 +1,239 @@
+mod common;
+
+use common::*;
+
+/// Error of linking a file `name` with the given contents
+fn input_error(name: &str, contents: &[u8]) -> String {
+    let dir = TempDir::new();
+    let path = dir.write(name, contents);
+    let args = parse_args(&dir, &[path.to_str().unwrap()]);
+    let config = linker::config::Config::from_args(&args).unwrap();
+    match linker::link(&config, &args.inputs) {
+        Ok(_) => panic!("link succeeded"),
+        Err(err) => format!("{err:#}"),
+    }
+}
+
+#[test]
+fn mach_o_objects_are_named() {
+    // 64-bit little-endian `MH_MAGIC_64`, then x86-64 and `MH_OBJECT`
+    let mut macho = vec![
+        0xcf, 0xfa, 0xed, 0xfe, 0x07, 0, 0, 0x01, 0x03, 0, 0, 0, 0x01,
+    ];
+    macho.resize(32, 0);
+    let err = input_error("foreign.o", &macho);
+    assert!(err.contains("foreign.o"), "{err}");
+    assert!(
+        err.contains("is a Mach-O object; only ELF is supported"),
+        "{err}"
+    );
+    let err = input_error("foreign", &macho);
+    assert!(err.contains("foreign\" is a Mach-O object"), "{err}");
+}
+
+#[test]
+fn coff_objects_are_named() {
+    // x86-64 machine type, no sections
+    let mut coff = vec![0x64, 0x86];
+    coff.resize(20, 0);
+    let err = input_error("foreign.o", &coff);
+    assert!(err.contains("foreign.o"), "{err}");
+    assert!(
+        err.contains("is a COFF object; only ELF is supported"),
+        "{err}"
+    );
+
+    // Whatever the file is called
+    for name in ["foreign.obj", "foreign"] {
+        let err = input_error(name, &coff);
+        assert!(err.contains(&format!("{name}\" is a COFF object")), "{err}");
+    }
+}
+
+/// Error of linking an object with an archive `name` of one member `member`
+fn archive_member_error(name: &str, member: &str, contents: &[u8]) -> String {
+    let dir = TempDir::new();
+    let mut archive = b"!<arch>\n".to_vec();
+    archive.extend(
+        format!(
+            "{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+            format!("{member}/"),
+            0,
+            0,
+            0,
+            644,
+            contents.len()
+        )
+        .bytes(),
+    );
+    archive.extend(contents);
+    if contents.len() % 2 == 1 {
+        archive.push(b'\n');
+    }
+    let archive = dir.write(name, &archive);
+    let main = dir.write("main.o", &Object::exiting(0).bytes());
+    let args = parse_args(&dir, &[main.to_str().unwrap(), archive.to_str().unwrap()]);
+    let config = linker::config::Config::from_args(&args).unwrap();
+    format!("{:#}", linker::link(&config, &args.inputs).err().unwrap())
+}
+
+#[test]
+fn foreign_archive_members_are_named() {
+    let mut coff = vec![0x64, 0x86];
+    coff.resize(20, 0);
+    let err = archive_member_error("libwin.a", "win.o", &coff);
+    assert!(
+        err.contains("\"win.o\" in") && err.contains("libwin.a"),
+        "{err}"
+    );
+    assert!(err.contains("is a COFF object"), "{err}");
+}
+
+#[test]
+fn malformed_objects_are_named() {
+    // The section headers are past the end of the file
+    let truncated = &Object::exiting(0).bytes()[..0x40];
+    let err = input_error("foreign.o", truncated);
+    assert!(
+        err.contains("foreign.o\" is not a valid ELF object"),
+        "{err}"
+    );
+
+    let err = archive_member_error("libbroken.a", "broken.o", truncated);
+    assert!(
+        err.contains("\"broken.o\" in") && err.contains("libbroken.a"),
+        "{err}"
+    );
+    assert!(err.contains("is not a valid ELF object"), "{err}");
+}
+
+#[test]
+fn arm_objects_are_rejected() {
+    // 32-bit ARM, whose `.ARM.exidx` unwind tables aren't supported
+    let arm = Object {
+        machine: goblin::elf::header::EM_ARM,
+        ..Object::i386()
+    };
+    let err = link_error(&[&arm], &[]);
+    assert!(
+        err.contains("Unsupported architecture (e_machine 0x28)"),
+        "{err}"
+    );
+}
+
+#[test]
+fn many_inputs_keep_the_command_line_order() {
+    const INPUTS: usize = 48;
+    let mut objects = vec![Object::exiting(0)];
+    for i in 1..INPUTS {
+        let mut object = Object::x86_64();
+        object.rodata(".rodata", 1, format!("<input {i:02}>").as_bytes());
+        objects.push(object);
+    }
+    let objects: Vec<&Object> = objects.iter().collect();
+    let bytes = link(&objects, &[]).unwrap();
+    // Parsed on several threads, yet laid out in order, and the same every time
+    let markers: Vec<usize> = (1..INPUTS)
+        .map(|i| {
+            let marker = format!("<input {i:02}>");
+            bytes
+                .windows(marker.len())
+                .position(|w| w == marker.as_bytes())
+                .unwrap()
+        })
+        .collect();
+    assert!(markers.windows(2).all(|w| w[1] == w[0] + 10), "{markers:?}");
+    for _ in 0..4 {
+        assert_eq!(link(&objects, &[]).unwrap(), bytes);
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn inputs_are_unmapped_after_linking() {
+    let dir = TempDir::new();
+    let prefix = format!("{}/", dir.path().display());
+    let mappings = || {
+        std::fs::read_to_string("/proc/self/maps")
+            .unwrap()
+            .lines()
+            .filter(|line| line.contains(&prefix))
+            .count()
+    };
+    let (config, inputs) = prepare(&dir, &[&Object::exiting(0), &Object::x86_64()], &[]).unwrap();
+    for _ in 0..32 {
+        linker::link(&config, &inputs).unwrap();
+    }
+    // Each link maps both inputs, and nothing else has them open
+    assert_eq!(mappings(), 0);
+}
+
+#[test]
+fn inputs_are_told_apart_by_their_magic() {
+    let dir = TempDir::new();
+    let mut main = Object::x86_64();
+    let text = main.text(".text", Code::new().call(sym("helper")).exit(42));
+    main.func("_start", text, 0);
+    let mut helper = Object::x86_64();
+    let text = helper.text(".text", Code::new().ret());
+    helper.func("helper", text, 0);
+
+    let main = dir.write("foo.tmp", &main.bytes());
+    let archive = dir.write("bar", &archive(&[("helper.o", &helper)]));
+    let args = parse_args(&dir, &[main.to_str().unwrap(), archive.to_str().unwrap()]);
+    let config = linker::config::Config::from_args(&args).unwrap();
+    assert_exits(&linker::link(&config, &args.inputs).unwrap(), 42);
+
+    // Without a known magic, only the extension remains
+    let junk = dir.write("junk", b"neither");
+    let args = parse_args(&dir, &[junk.to_str().unwrap()]);
+    let config = linker::config::Config::from_args(&args).unwrap();
+    let err = format!("{:#}", linker::link(&config, &args.inputs).unwrap_err());
+    assert!(
+        err.contains("is neither an ELF object nor an archive"),
+        "{err}"
+    );
+}
+
+/// Run the linker with `contents` on stdin, given as `-` before `inputs`
+fn link_stdin(dir: &TempDir, contents: &[u8], inputs: &[&str]) -> std::process::Output {
+    let stdin = std::fs::File::open(dir.write("stdin", contents)).unwrap();
+    std::process::Command::new(env!("CARGO_BIN_EXE_linker"))
+        .arg("-")
+        .args(inputs)
+        .arg("-o")
+        .arg(dir.path().join("out"))
+        .stdin(stdin)
+        .output()
+        .expect("linker can't be run")
+}
+
+#[test]
+fn inputs_are_read_from_stdin() {
+    let dir = TempDir::new();
+    let mut main = Object::x86_64();
+    let text = main.text(".text", Code::new().call(sym("helper")).exit(42));
+    main.func("_start", text, 0);
+    let mut helper = Object::x86_64();
+    let text = helper.text(".text", Code::new().ret());
+    helper.func("helper", text, 0);
+    let helper = dir.write("helper.o", &helper.bytes());
+
+    let output = link_stdin(&dir, &main.bytes(), &[helper.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_exits(&std::fs::read(dir.path().join("out")).unwrap(), 42);
+
+    // Archives are told apart by their magic
+    let archive = archive(&[("main.o", &Object::exiting(3))]);
+    let output = link_stdin(&dir, &archive, &[]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_exits(&std::fs::read(dir.path().join("out")).unwrap(), 3);
+
+    let output = link_stdin(&dir, b"\x7fELF", &[]);
+    assert!(!output.status.success());
+    let error = stderr(&output);
+    assert!(
+        error.contains("<stdin> is not a valid ELF object"),
+        "{error}"
+    );
+}
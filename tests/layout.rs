@@ -0,0 +1,471 @@
+mod common;
+
+use common::*;
+use goblin::elf::program_header::{PF_R, PF_W, PF_X, PT_LOAD};
+
+/// (flags, vaddr, memsz) of each `PT_LOAD`
+fn loads(bytes: &[u8]) -> Vec<(u32, u64, u64)> {
+    program_headers(&parse(bytes), PT_LOAD)
+        .iter()
+        .map(|ph| (ph.p_flags, ph.p_vaddr, ph.p_memsz))
+        .collect()
+}
+
+/// `_start` exiting with the byte at `value`, in `.rodata`
+fn code_and_rodata() -> Object {
+    let mut object = Object::x86_64();
+    let text = object.text(
+        ".text",
+        Code::new().load_byte_edi(sym("value"), 0).exit_edi(),
+    );
+    object.func("_start", text, 0);
+    let rodata = object.rodata(".rodata", 8, &[42]);
+    object.object("value", rodata, 0, 1);
+    object
+}
+
+#[test]
+fn rodata_has_its_own_segment() {
+    let bytes = link(&[&code_and_rodata()], &[]).unwrap();
+    let flags: Vec<u32> = loads(&bytes).iter().map(|l| l.0).collect();
+    assert_eq!(flags, [PF_R | PF_X, PF_R]);
+    assert_exits(&bytes, 42);
+}
+
+#[test]
+fn no_rosegment_merges_rodata_into_text() {
+    let bytes = link(&[&code_and_rodata()], &["--no-rosegment"]).unwrap();
+    let flags: Vec<u32> = loads(&bytes).iter().map(|l| l.0).collect();
+    assert_eq!(flags, [PF_R | PF_X]);
+    assert_exits(&bytes, 42);
+}
+
+#[test]
+fn entry_section_precedes_stricter_aligned_text() {
+    let mut object = Object::x86_64();
+    let text = object.text(".text", Code::new().exit(42));
+    object.sections[text - 1].align = 0x1000;
+    object.func("main", text, 0);
+    let entry = object.text(".entry", Code::new().call(sym("main")));
+    object.sections[entry - 1].align = 1;
+    object.func("_start", entry, 0);
+
+    let bytes = link(&[&object], &["--section-headers"]).unwrap();
+    let elf = parse(&bytes);
+    let start = symbol_address(&elf, "_start").unwrap();
+    let main = symbol_address(&elf, "main").unwrap();
+    assert!(start < main, "{start:#x} {main:#x}");
+    // The first contents of the executable segment
+    let first = &program_headers(&elf, PT_LOAD)[0];
+    assert_eq!(first.p_flags, PF_R | PF_X);
+    let lowest = elf
+        .section_headers
+        .iter()
+        .filter(|sh| sh.sh_addr != 0)
+        .map(|sh| sh.sh_addr)
+        .min();
+    assert_eq!(lowest, Some(start));
+    assert_exits(&bytes, 42);
+}
+
+#[test]
+fn orphans_are_placed_by_their_flags() {
+    let mut object = Object::exiting(0);
+    let rw = object.data(".myrw", 8, &[1; 8]);
+    object.object("rw", rw, 0, 8);
+    let ro = object.rodata("", 8, &[2; 8]);
+    object.object("ro", ro, 0, 8);
+    let bytes = link(&[&object], &["--section-headers"]).unwrap();
+    let elf = parse(&bytes);
+
+    let segment_flags = |name: &str| {
+        let address = symbol_address(&elf, name).unwrap();
+        program_headers(&elf, PT_LOAD)
+            .iter()
+            .find(|ph| (ph.p_vaddr..ph.p_vaddr + ph.p_memsz).contains(&address))
+            .map(|ph| ph.p_flags)
+    };
+    assert_eq!(segment_flags("rw"), Some(PF_R | PF_W));
+    assert_eq!(segment_flags("ro"), Some(PF_R));
+    assert_eq!(segment_flags("_start"), Some(PF_R | PF_X));
+}
+
+#[test]
+fn only_allocated_orphans_are_loaded() {
+    let mut object = Object::exiting(0);
+    let mystuff = object.rodata(".mystuff", 8, b"loaded!!");
+    object.object("mystuff", mystuff, 0, 8);
+    object.section(".myinfo", SHT_PROGBITS, 0, 1, b"not loaded");
+    let bytes = link(&[&object], &["--section-headers"]).unwrap();
+    let elf = parse(&bytes);
+    let address = symbol_address(&elf, "mystuff").unwrap();
+    assert!(loads(&bytes)
+        .iter()
+        .any(|&(flags, vaddr, memsz)| flags == PF_R && (vaddr..vaddr + memsz).contains(&address)));
+    assert!(!bytes.windows(10).any(|w| w == b"not loaded"));
+
+    // A name past the end of the section name table
+    let mut bytes = object.bytes();
+    let header = parse(&bytes).header;
+    let name = header.e_shoff as usize + mystuff * header.e_shentsize as usize;
+    bytes[name..name + 4].copy_from_slice(&0xffffu32.to_le_bytes());
+    let dir = TempDir::new();
+    let path = dir.write("names.o", &bytes);
+    let args = parse_args(&dir, &[path.to_str().unwrap()]);
+    let config = linker::config::Config::from_args(&args).unwrap();
+    let err = format!("{:#}", linker::link(&config, &args.inputs).unwrap_err());
+    assert!(
+        err.contains("Section name offset 0xffff is out of bounds in") && err.contains("names.o"),
+        "{err}"
+    );
+}
+
+#[test]
+fn guard_pages_separate_segments() {
+    let mut object = code_and_rodata();
+    object.data(".data", 8, &[1; 8]);
+    let bytes = link(&[&object], &["--guard-page-between-segments"]).unwrap();
+    let loads = loads(&bytes);
+    assert_eq!(loads.len(), 3);
+    for pair in loads.windows(2) {
+        let end = (pair[0].1 + pair[0].2).next_multiple_of(0x1000);
+        assert!(pair[1].1 >= end + 0x1000, "{loads:x?}");
+    }
+    check_with_readelf(&bytes);
+    assert_exits(&bytes, 42);
+}
+
+#[test]
+fn mapping_the_null_page_warns() {
+    let dir = TempDir::new();
+    let object = Object::exiting(0);
+    let output = run_linker(&dir, &[&object], &["--image-base", "0"]);
+    assert!(output.status.success());
+    assert!(
+        stderr(&output).contains("warning: image base 0x0 maps the null page"),
+        "{}",
+        stderr(&output)
+    );
+    for flags in [&[][..], &["--image-base", "0x10000"], &["-pie"]] {
+        let output = run_linker(&dir, &[&object], flags);
+        assert!(
+            !stderr(&output).contains("null page"),
+            "{}",
+            stderr(&output)
+        );
+    }
+}
+
+#[test]
+fn bss_takes_memory_but_no_file_space() {
+    let mut object = Object::x86_64();
+    let text = object.text(
+        ".text",
+        Code::new().load_byte_edi(sym("array"), 0xffff).exit_edi(),
+    );
+    object.func("_start", text, 0);
+    let data = object.data(".data", 8, &[1; 16]);
+    object.object("pointer", data, 0, 8);
+    object.reloc(data, 0, R_X86_64_64, sym("array"), 0);
+    let bss = object.bss(".bss", 16, 0x1_0000);
+    object.object("array", bss, 0, 0x1_0000);
+
+    let bytes = link(&[&object], &["--section-headers"]).unwrap();
+    let elf = parse(&bytes);
+    let load = program_headers(&elf, PT_LOAD)
+        .into_iter()
+        .find(|ph| ph.p_flags == PF_R | PF_W)
+        .unwrap();
+    assert_eq!((load.p_filesz, load.p_memsz), (16, 0x1_0010));
+
+    let array = symbol_address(&elf, "array").unwrap();
+    assert_eq!(array, load.p_vaddr + 16);
+    let pointer = symbol_address(&elf, "pointer").unwrap();
+    assert_eq!(read_at(&elf, &bytes, pointer, 8), array.to_le_bytes());
+    check_with_readelf(&bytes);
+    assert_exits(&bytes, 0);
+}
+
+#[test]
+fn zero_alignment_means_unaligned() {
+    let mut object = Object::x86_64();
+    let text = object.text(
+        ".text",
+        Code::new().load_byte_edi(sym("value"), 0).exit_edi(),
+    );
+    object.func("_start", text, 0);
+    // Odd-sized, so that an alignment would show as padding
+    let rodata = object.rodata(".rodata", 0, &[1, 2, 3]);
+    let value = object.rodata(".rodata.value", 0, &[42]);
+    object.object("value", value, 0, 1);
+    object.object("first", rodata, 0, 3);
+
+    let bytes = link(&[&object], &["--section-headers"]).unwrap();
+    let elf = parse(&bytes);
+    let first = symbol_address(&elf, "first").unwrap();
+    assert_eq!(symbol_address(&elf, "value").unwrap(), first + 3);
+    check_with_readelf(&bytes);
+    assert_exits(&bytes, 42);
+}
+
+/// `_start` exiting with the byte at `value`, in a `.data` aligned to 8 KiB
+fn aligned_data() -> Object {
+    let mut object = Object::x86_64();
+    let text = object.text(
+        ".text",
+        Code::new().load_byte_edi(sym("value"), 0).exit_edi(),
+    );
+    object.func("_start", text, 0);
+    let data = object.data(".data", 0x2000, &[42]);
+    object.object("value", data, 0, 1);
+    object
+}
+
+#[test]
+fn alignment_applies_to_the_final_address() {
+    for base in ["0x400000", "0x401000"] {
+        let flags = ["--image-base", base, "--section-headers"];
+        let bytes = link(&[&aligned_data()], &flags).unwrap();
+        let elf = parse(&bytes);
+        let value = symbol_address(&elf, "value").unwrap();
+        assert_eq!(value % 0x2000, 0, "{value:#x} with base {base}");
+        check_with_readelf(&bytes);
+        assert_exits(&bytes, 42);
+    }
+
+    // Also in the first segment, which starts after the headers
+    let mut object = Object::exiting(42);
+    object.sections[0].align = 0x2000;
+    let bytes = link(
+        &[&object],
+        &["--image-base", "0x401000", "--section-headers"],
+    )
+    .unwrap();
+    let elf = parse(&bytes);
+    assert_eq!(symbol_address(&elf, "_start").unwrap() % 0x2000, 0);
+    check_with_readelf(&bytes);
+    assert_exits(&bytes, 42);
+}
+
+#[test]
+fn max_page_size_separates_segments_by_whole_pages() {
+    let mut object = code_and_rodata();
+    object.data(".data", 8, &[1; 8]);
+    object.bss(".bss", 8, 0x100);
+    for base in ["0x400000", "0x401000"] {
+        let flags = ["-z", "max-page-size=0x10000", "--image-base", base];
+        let bytes = link(&[&object], &flags).unwrap();
+        check_with_readelf(&bytes);
+        let loads = program_headers(&parse(&bytes), PT_LOAD);
+        assert_eq!(loads.len(), 3);
+        for load in &loads {
+            assert_eq!(load.p_align, 0x10000);
+            assert_eq!(load.p_offset % 0x10000, load.p_vaddr % 0x10000);
+        }
+        for pair in loads.windows(2) {
+            let end = pair[0].p_vaddr + pair[0].p_memsz;
+            assert!(
+                end.next_multiple_of(0x10000) <= pair[1].p_vaddr,
+                "LOADs share a page with base {base}"
+            );
+            assert!(pair[0].p_offset + pair[0].p_filesz <= pair[1].p_offset);
+        }
+        assert_exits(&bytes, 42);
+    }
+}
+
+#[test]
+fn file_align_only_aligns_segment_offsets() {
+    let default = link(&[&code_and_rodata()], &[]).unwrap();
+    let bytes = link(&[&code_and_rodata()], &["--file-align", "0x200"]).unwrap();
+    check_with_readelf(&bytes);
+    let loads = program_headers(&parse(&bytes), PT_LOAD);
+    for load in &loads {
+        assert_eq!(load.p_offset % 0x200, 0);
+        assert_eq!(load.p_offset % 0x1000, load.p_vaddr % 0x1000);
+    }
+    // The sizes are those of the contents, not padded to the alignment
+    let rodata = loads.last().unwrap();
+    assert_eq!((rodata.p_filesz, rodata.p_memsz), (1, 1));
+    assert!(bytes.len() < default.len());
+    assert_exits(&bytes, 42);
+}
+
+#[test]
+#[should_panic(expected = "Invalid file alignment")]
+fn file_align_must_be_a_power_of_two() {
+    let _ = link(&[&code_and_rodata()], &["--file-align=0x300"]);
+}
+
+/// `_start` calling `boot`, in a `.boot` section with `sh_addr` of `address`
+fn boot_at(address: u64) -> Object {
+    let mut object = Object::x86_64();
+    let text = object.text(".text", Code::new().call(sym("boot")).exit(0));
+    object.func("_start", text, 0);
+    let boot = object.text(".boot", Code::new().ret());
+    object.sections[boot - 1].addr = address;
+    object.func("boot", boot, 0);
+    object
+}
+
+#[test]
+fn sections_with_an_address_are_placed_there() {
+    let flags = ["--image-base", "0x1000", "--section-headers"];
+    let bytes = link(&[&boot_at(0x7c00)], &flags).unwrap();
+    check_with_readelf(&bytes);
+    let elf = parse(&bytes);
+    assert_eq!(symbol_address(&elf, "boot"), Some(0x7c00));
+    assert!(loads(&bytes).contains(&(PF_R | PF_X, 0x7c00, 1)));
+    assert_eq!(read_at(&elf, &bytes, 0x7c00, 1), [0xc3]);
+
+    // Other code doesn't fit below it
+    let mut big = boot_at(0x2000);
+    big.text(".text.big", Code::new().raw(&[0x90; 0x2000]));
+    let err = link_error(&[&big], &flags);
+    assert!(
+        err.contains("Segment starting with .boot at 0x2000..0x2001 overlaps the one before it, starting with .text at 0x1000.."),
+        "{err}"
+    );
+}
+
+#[test]
+fn fixed_sections_may_not_overlap() {
+    let flags = ["--image-base", "0x1000"];
+    let mut object = boot_at(0x7c00);
+    let second = object.text(".boot2", Code::new().ret());
+    object.sections[second - 1].addr = 0x7c00;
+    let err = link_error(&[&object], &flags);
+    assert!(
+        err.contains("Segment starting with .boot2 at 0x7c00..0x7c01 overlaps the one before it, starting with .boot at 0x7c00..0x7c01"),
+        "{err}"
+    );
+
+    // Loaded memory, including that of the headers, stays disjoint
+    object.sections[second - 1].addr = 0x7c10;
+    let bytes = link(&[&object], &flags).unwrap();
+    let loads = loads(&bytes);
+    assert_eq!(loads[0].1, 0x1000);
+    for pair in loads.windows(2) {
+        assert!(pair[0].1 + pair[0].2 <= pair[1].1, "{loads:x?}");
+    }
+}
+
+#[test]
+fn sort_by_address_orders_fixed_segments() {
+    // Code goes before data, so `.ram` follows `.boot` despite its lower address
+    let mut object = boot_at(0x7c00);
+    let ram = object.data(".ram", 8, &[1; 8]);
+    object.sections[ram - 1].addr = 0x4000;
+    let flags = ["--image-base", "0x1000"];
+    let err = link_error(&[&object], &flags);
+    assert!(
+        err.contains("Segment starting with .ram at 0x4000..0x4008 is below the one before it, starting with .boot at 0x7c00..0x7c01"),
+        "{err}"
+    );
+    assert!(err.contains("--sort-by-address"), "{err}");
+
+    let dir = TempDir::new();
+    let flags = ["--image-base", "0x1000", "--sort-by-address"];
+    let output = run_linker(&dir, &[&object], &flags);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains(".ram at 0x4000 is placed after .boot at 0x7c00"),
+        "{}",
+        stderr(&output)
+    );
+    let bytes = std::fs::read(dir.path().join("out")).unwrap();
+    check_with_readelf(&bytes);
+    let addresses: Vec<u64> = loads(&bytes).iter().map(|l| l.1).collect();
+    assert!(addresses.is_sorted(), "{addresses:x?}");
+    assert!(addresses.contains(&0x4000) && addresses.contains(&0x7c00));
+    assert_exits(&bytes, 0);
+}
+
+#[test]
+fn data_and_bss_are_collected_into_the_writable_segment() {
+    let mut object = code_and_rodata();
+    object.data(".data", 8, &[1; 8]);
+    object.data(".data.counter", 8, &[2; 8]);
+    object.bss(".bss.buffer", 8, 0x40);
+    let bytes = link(&[&object], &["--section-headers"]).unwrap();
+    check_with_readelf(&bytes);
+    let elf = parse(&bytes);
+    let writable = program_headers(&elf, PT_LOAD)
+        .into_iter()
+        .find(|load| load.p_flags == PF_R | PF_W)
+        .unwrap();
+    let section = |name: &str| {
+        elf.section_headers
+            .iter()
+            .find(|sh| elf.shdr_strtab.get_at(sh.sh_name) == Some(name))
+            .unwrap_or_else(|| panic!("missing {name}"))
+    };
+    for (name, size) in [(".data", 16), (".bss", 0x40)] {
+        let section = section(name);
+        assert_eq!(section.sh_size, size);
+        assert!(writable.p_vaddr <= section.sh_addr);
+        assert!(section.sh_addr + size <= writable.p_vaddr + writable.p_memsz);
+    }
+}
+
+#[test]
+fn output_sections_come_from_the_config() {
+    let mut object = code_and_rodata();
+    object.rodata(".table.a", 8, &[1; 8]);
+    object.rodata(".table.b", 8, &[2; 8]);
+    let dir = TempDir::new();
+    let (mut config, inputs) = prepare(&dir, &[&object], &["--section-headers"]).unwrap();
+    config
+        .output_sections
+        .push(linker::linker_script::OutputSection {
+            name: ".tables".to_owned(),
+            address: None,
+            patterns: vec![".table.*".to_owned()],
+            region: None,
+        });
+    let bytes = linker::link(&config, &inputs).unwrap();
+    let elf = parse(&bytes);
+    let tables = elf
+        .section_headers
+        .iter()
+        .find(|sh| elf.shdr_strtab.get_at(sh.sh_name) == Some(".tables"))
+        .expect("missing .tables");
+    assert_eq!(tables.sh_size, 16);
+    assert_eq!(
+        read_at(&elf, &bytes, tables.sh_addr, 16),
+        [[1; 8], [2; 8]].concat()
+    );
+}
+
+#[test]
+fn nothing_to_load_is_an_error() {
+    let error = link_error(&[], &[]);
+    assert!(error.contains("No input files"), "{error}");
+
+    let error = link_error(&[&Object::x86_64()], &["-e", "0x401000"]);
+    assert!(error.contains("Nothing to link"), "{error}");
+}
+
+#[test]
+fn empty_sections_take_no_segment() {
+    let mut object = code_and_rodata();
+    object.data(".data", 8, &[]);
+    object.bss(".bss", 8, 0);
+    let bytes = link(&[&object], &["--section-headers"]).unwrap();
+    check_with_readelf(&bytes);
+    let flags: Vec<u32> = loads(&bytes).iter().map(|l| l.0).collect();
+    assert_eq!(flags, [PF_R | PF_X, PF_R]);
+    assert!(loads(&bytes).iter().all(|&(_, _, memsz)| memsz != 0));
+    assert_exits(&bytes, 42);
+
+    // With only empty sections, just the headers are loaded
+    let mut object = Object::x86_64();
+    object.text(".text", Code::new());
+    object.data(".data", 8, &[]);
+    let bytes = link(&[&object], &["-e", "0x401000"]).unwrap();
+    check_with_readelf(&bytes);
+    let [(flags, 0x40_0000, memsz)] = loads(&bytes)[..] else {
+        panic!("expected one PT_LOAD of the headers");
+    };
+    assert_eq!((flags, memsz), (PF_R, 0x1000));
+}
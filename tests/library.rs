@@ -0,0 +1,120 @@
+mod common;
+
+use common::*;
+
+#[test]
+fn provenance_maps_addresses_to_inputs() {
+    let main = Object::exiting(0);
+    let mut other = Object::x86_64();
+    other.text(".text", Code::new().ret());
+    let text = other.text(".text.foo", Code::new().raw(&[0x90; 8]).ret());
+    other.func("foo", text, 0);
+
+    let link = link_program(&[&main, &other], &["--section-headers"]).unwrap();
+    let bytes = link.bytes().unwrap();
+    let foo = symbol_address(&parse(&bytes), "foo").unwrap();
+
+    let (input, section, offset) = link.provenance(foo + 3).unwrap();
+    assert!(input.ends_with("1.o\""), "{input}");
+    assert_eq!((section, offset), (".text", 3));
+    assert!(link.provenance(0).is_none());
+}
+
+#[test]
+fn resolved_symbols_have_final_addresses() {
+    let mut main = Object::x86_64();
+    let text = main.text(".text", Code::new().call(sym("foo")).exit(0));
+    main.func("_start", text, 0);
+    let mut other = Object::x86_64();
+    let data = other.data(".data", 8, &[0; 24]);
+    other.object("table", data, 8, 16);
+    let text = other.text(".text", Code::new().ret());
+    other.func("foo", text, 0);
+
+    let link = link_program(&[&main, &other], &["--section-headers"]).unwrap();
+    let bytes = link.bytes().unwrap();
+    let elf = parse(&bytes);
+    let symbols = link.resolved_symbols();
+    let find = |name: &str| symbols.iter().find(|s| s.0 == name).unwrap();
+
+    assert_eq!(find("_start").1, elf.entry);
+    let data = elf
+        .section_headers
+        .iter()
+        .find(|sh| elf.shdr_strtab.get_at(sh.sh_name) == Some(".data"))
+        .unwrap();
+    let (_, table, size) = find("table");
+    assert_eq!(*size, 16);
+    assert_eq!(*table, data.sh_addr + 8);
+    assert!(symbols.windows(2).all(|w| w[0].1 <= w[1].1));
+}
+
+/// XOR of every byte of `.rodata` chunks with the key
+struct Xor(u8);
+
+impl linker::SectionTransform for Xor {
+    fn transform(&self, section: &str, _input: &str, bytes: &[u8]) -> Option<Vec<u8>> {
+        (section == ".rodata").then(|| bytes.iter().map(|b| b ^ self.0).collect())
+    }
+}
+
+/// Link with the transforms, like `link` does without them
+fn link_with(
+    objects: &[&Object],
+    transforms: Vec<Box<dyn linker::SectionTransform>>,
+) -> anyhow::Result<Vec<u8>> {
+    let dir = TempDir::new();
+    let (config, inputs) = prepare(&dir, objects, &["--section-headers"])?;
+    linker::link_program_with(&config, &inputs, &transforms)?.bytes()
+}
+
+#[test]
+fn transforms_rewrite_sections_before_layout() {
+    // The obfuscated byte is deobfuscated while linking
+    let mut object = Object::x86_64();
+    let text = object.text(
+        ".text",
+        Code::new().load_byte_edi(sym("value"), 0).exit_edi(),
+    );
+    object.func("_start", text, 0);
+    let rodata = object.rodata(".rodata", 8, &[42 ^ 0x5a, 7 ^ 0x5a]);
+    object.object("value", rodata, 0, 2);
+
+    let plain = link_with(&[&object], vec![]).unwrap();
+    let elf = parse(&plain);
+    let value = symbol_address(&elf, "value").unwrap();
+    assert_eq!(read_at(&elf, &plain, value, 2), [42 ^ 0x5a, 7 ^ 0x5a]);
+
+    let bytes = link_with(&[&object], vec![Box::new(Xor(0x5a))]).unwrap();
+    let elf = parse(&bytes);
+    let value = symbol_address(&elf, "value").unwrap();
+    assert_eq!(read_at(&elf, &bytes, value, 2), [42, 7]);
+    // Transforms apply in order
+    let twice = link_with(&[&object], vec![Box::new(Xor(0x5a)), Box::new(Xor(0x5a))]).unwrap();
+    assert_eq!(twice, plain);
+    assert_exits(&bytes, 42);
+}
+
+/// Truncates `.rodata` to its first byte
+struct Truncate;
+
+impl linker::SectionTransform for Truncate {
+    fn transform(&self, section: &str, _input: &str, bytes: &[u8]) -> Option<Vec<u8>> {
+        (section == ".rodata").then(|| bytes[..1].to_vec())
+    }
+}
+
+#[test]
+fn transforms_must_keep_relocated_fields() {
+    let mut object = Object::exiting(0);
+    let rodata = object.rodata(".rodata", 8, &[0; 16]);
+    object.reloc(rodata, 8, R_X86_64_64, sym("_start"), 0);
+    let err = format!(
+        "{:#}",
+        link_with(&[&object], vec![Box::new(Truncate)]).unwrap_err()
+    );
+    assert!(
+        err.contains("is 0x1 bytes, but has a relocation at offset 0x8"),
+        "{err}"
+    );
+}
@@ -0,0 +1,128 @@
+mod common;
+
+use common::*;
+
+const MEMORY: &str = "
+MEMORY {
+    ROM (rx) : ORIGIN = 0x500000, LENGTH = 4K
+    RAM (rw) : org = 0x600000, len = 0x100
+}
+";
+
+/// `_start` exiting with the first byte of `.data`, which is `data_size` long
+fn object(data_size: usize) -> Object {
+    let mut object = Object::x86_64();
+    let text = object.text(
+        ".text",
+        Code::new().load_byte_edi(sym("value"), 0).exit_edi(),
+    );
+    object.func("_start", text, 0);
+    let mut data = vec![0; data_size];
+    data[0] = 42;
+    let data = object.data(".data", 8, &data);
+    object.object("value", data, 0, 1);
+    object
+}
+
+/// Link with the script, which is given the `MEMORY` regions
+fn link_with_script(object: &Object, sections: &str) -> anyhow::Result<Vec<u8>> {
+    let dir = TempDir::new();
+    let script = dir.write("script.ld", format!("{MEMORY}{sections}").as_bytes());
+    let script = script.to_str().unwrap();
+    link(&[object], &["--script", script, "--section-headers"])
+}
+
+fn section_address(bytes: &[u8], name: &str) -> u64 {
+    let elf = parse(bytes);
+    let section = elf
+        .section_headers
+        .iter()
+        .find(|sh| elf.shdr_strtab.get_at(sh.sh_name) == Some(name))
+        .unwrap();
+    section.sh_addr
+}
+
+const SECTIONS: &str = "
+SECTIONS {
+    .text : { *(.text .text.*) } > ROM
+    .data : { *(.data .data.*) } > RAM
+}
+";
+
+#[test]
+fn sections_start_at_their_region() {
+    let bytes = link_with_script(&object(16), SECTIONS).unwrap();
+    assert_eq!(section_address(&bytes, ".text"), 0x50_0000);
+    assert_eq!(section_address(&bytes, ".data"), 0x60_0000);
+    check_with_readelf(&bytes);
+    assert_exits(&bytes, 42);
+}
+
+#[test]
+fn overflowing_a_region_is_an_error() {
+    let err = format!(
+        "{:#}",
+        link_with_script(&object(0x101), SECTIONS).unwrap_err()
+    );
+    assert!(
+        err.contains("Section .data at 0x600000..0x600101 overflows memory region RAM"),
+        "{err}"
+    );
+    // Exactly full is fine
+    link_with_script(&object(0x100), SECTIONS).unwrap();
+}
+
+#[test]
+fn undefined_regions_are_rejected() {
+    let sections = "SECTIONS { .text : { *(.text) } > FLASH }";
+    let err = format!("{:#}", link_with_script(&object(16), sections).unwrap_err());
+    assert!(err.contains("undefined memory region \"FLASH\""), "{err}");
+}
+
+#[test]
+fn sections_are_placed_at_the_location_counter() {
+    let script = "
+SECTIONS {
+    . = 0x500000;
+    .text : { *(.text .text.*) }
+    __text_end = .;
+}
+";
+    let mut object = object(16);
+    let pointer = object.data(".data.pointer", 8, &[0; 8]);
+    object.reloc(pointer, 0, R_X86_64_64, sym("__text_end"), 0);
+    object.object("pointer", pointer, 0, 8);
+
+    let bytes = link_with_script(&object, script).unwrap();
+    let elf = parse(&bytes);
+    let text = elf
+        .section_headers
+        .iter()
+        .find(|sh| elf.shdr_strtab.get_at(sh.sh_name) == Some(".text"))
+        .unwrap();
+    assert_eq!(text.sh_addr, 0x50_0000);
+    let pointer = symbol_address(&elf, "pointer").unwrap();
+    let text_end = (text.sh_addr + text.sh_size).to_le_bytes();
+    assert_eq!(read_at(&elf, &bytes, pointer, 8), text_end);
+    assert_exits(&bytes, 42);
+}
+
+#[test]
+fn kept_sections_survive_garbage_collection() {
+    let mut object = object(16);
+    object.rodata(".rodata.unused", 1, b"unused marker");
+    object.rodata(".rodata.kept", 1, b"kept marker");
+    let script = "
+SECTIONS {
+    .rodata : { KEEP(*(.rodata.kept)) *(.rodata .rodata.*) }
+}
+";
+    let dir = TempDir::new();
+    let script = dir.write("script.ld", script.as_bytes());
+    let flags = ["--script", script.to_str().unwrap(), "--gc-sections"];
+    let bytes = link(&[&object], &flags).unwrap();
+    let contains = |needle: &[u8]| bytes.windows(needle.len()).any(|w| w == needle);
+    assert!(contains(b"kept marker"));
+    assert!(!contains(b"unused marker"));
+    assert_exits(&bytes, 42);
+}
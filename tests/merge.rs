@@ -0,0 +1,121 @@
+mod common;
+
+use common::*;
+
+/// Two objects with a table of pointers to their strings, one of which
+/// is a suffix of another. `_start` exits with the first byte of the last.
+fn string_objects() -> [Object; 2] {
+    let mut main = Object::x86_64();
+    let strings = main.strings(".rodata.str1.1", b"hello\0world\0");
+    let table = main.data(".data.table", 8, &[0; 16]);
+    main.reloc(table, 0, R_X86_64_64, Ref::Section(strings), 0);
+    main.reloc(table, 8, R_X86_64_64, Ref::Section(strings), 6);
+    main.object("table", table, 0, 16);
+    // mov table+24(%rip), %rax
+    let code = Code::new()
+        .raw(&[0x48, 0x8b, 0x05])
+        .reloc(R_X86_64_PC32, sym("table"), 24 - 4, 4)
+        .deref_byte_rax()
+        .exit_edi();
+    let text = main.text(".text", code);
+    main.func("_start", text, 0);
+
+    let mut other = Object::x86_64();
+    let strings = other.strings(".rodata.str1.1", b"hello\0lo\0");
+    let table = other.data(".data.table", 8, &[0; 16]);
+    other.reloc(table, 0, R_X86_64_64, Ref::Section(strings), 0);
+    other.reloc(table, 8, R_X86_64_64, Ref::Section(strings), 6);
+    [main, other]
+}
+
+/// Strings the tables point to, and the size of the section holding them
+fn link_strings(flags: &[&str]) -> (Vec<Vec<u8>>, usize) {
+    let [main, other] = string_objects();
+    let flags = [flags, &["--section-headers"]].concat();
+    let bytes = link(&[&main, &other], &flags).unwrap();
+    assert_exits(&bytes, b'l' as i32);
+    let elf = parse(&bytes);
+    let table = symbol_address(&elf, "table").unwrap();
+    let strings = (0..4)
+        .map(|i| {
+            let pointer = read_at(&elf, &bytes, table + 8 * i, 8);
+            let pointer = u64::from_le_bytes(pointer.try_into().unwrap());
+            let string = read_at(&elf, &bytes, pointer, 6);
+            string[..string.iter().position(|&b| b == 0).unwrap()].to_vec()
+        })
+        .collect();
+    let pointer = read_at(&elf, &bytes, table, 8);
+    let pointer = u64::from_le_bytes(pointer.try_into().unwrap());
+    let section = elf
+        .section_headers
+        .iter()
+        .find(|sh| (sh.sh_addr..sh.sh_addr + sh.sh_size).contains(&pointer))
+        .unwrap();
+    (strings, section.sh_size as usize)
+}
+
+#[test]
+fn higher_levels_merge_more_strings() {
+    let expected: Vec<Vec<u8>> = ["hello", "world", "hello", "lo"]
+        .map(|s| s.as_bytes().to_vec())
+        .to_vec();
+    let (strings, o0) = link_strings(&["-O0"]);
+    assert_eq!(strings, expected);
+    let (strings, o1) = link_strings(&["-O1"]);
+    assert_eq!(strings, expected);
+    let (strings, o2) = link_strings(&["-O2"]);
+    assert_eq!(strings, expected);
+
+    // "hello" is merged from -O1, and "lo" into it from -O2
+    assert_eq!(o0 - o1, 6);
+    assert_eq!(o1 - o2, 3);
+}
+
+/// `_start` exits with the first byte of the string at `offset` in its own
+/// strings, which are merged into those of another input
+fn string_reference(offset: i64) -> Vec<u8> {
+    let mut other = Object::x86_64();
+    other.strings(".rodata.str1.1", b"hello\0world\0");
+    let mut main = Object::x86_64();
+    let strings = main.strings(".rodata.str1.1", b"abc\0world\0");
+    let code = Code::new()
+        .lea_rdi(Ref::Section(strings), offset)
+        .deref_byte_rdi()
+        .exit_edi();
+    let text = main.text(".text", code);
+    main.func("_start", text, 0);
+    link(&[&other, &main], &["-O1"]).unwrap()
+}
+
+#[test]
+fn pc_relative_references_select_the_merged_string() {
+    assert_exits(&string_reference(0), b'a' as i32);
+    assert_exits(&string_reference(4), b'w' as i32);
+    assert_exits(&string_reference(6), b'r' as i32);
+}
+
+#[test]
+fn rel_addends_select_the_merged_string() {
+    let mut other = Object::i386();
+    other.strings(".rodata.str1.1", b"hello\0");
+    let mut main = Object::i386();
+    let strings = main.strings(".rodata.str1.1", b"abc\0hello\0");
+    let table = main.data(".data.table", 4, &[0; 4]);
+    main.reloc(table, 0, R_386_32, Ref::Section(strings), 4);
+    main.object("table", table, 0, 4);
+    let text = main.text(".text", Code::new().raw(&[0xcc]));
+    main.func("_start", text, 0);
+
+    let bytes = link(&[&other, &main], &["-O1", "--section-headers"]).unwrap();
+    let elf = parse(&bytes);
+    let table = symbol_address(&elf, "table").unwrap();
+    let pointer = read_at(&elf, &bytes, table, 4);
+    let pointer = u32::from_le_bytes(pointer.try_into().unwrap()) as u64;
+    let section = elf
+        .section_headers
+        .iter()
+        .find(|sh| elf.shdr_strtab.get_at(sh.sh_name) == Some(".rodata"))
+        .unwrap();
+    assert_eq!(read_at(&elf, &bytes, section.sh_addr, 10), b"hello\0abc\0");
+    assert_eq!(pointer, section.sh_addr);
+}
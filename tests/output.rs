@@ -0,0 +1,204 @@
+mod common;
+
+use common::*;
+
+#[cfg(unix)]
+#[test]
+fn output_is_executable() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = TempDir::new();
+    let main = Object::exiting(0);
+    let mode = |flags: &[&str]| {
+        let output = run_linker(&dir, &[&main], flags);
+        assert!(output.status.success(), "{}", stderr(&output));
+        let metadata = std::fs::metadata(dir.path().join("out")).unwrap();
+        metadata.permissions().mode() & 0o7777
+    };
+    assert_eq!(mode(&[]), 0o755);
+    assert_eq!(mode(&["--output-mode", "0640"]), 0o640);
+    assert_eq!(mode(&["--output-mode=0o700"]), 0o700);
+}
+
+#[test]
+fn library_links_like_the_command_line() {
+    let dir = TempDir::new();
+    let main = Object::exiting(3);
+    let output = run_linker(&dir, &[&main], &["--section-headers"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let (config, inputs) = prepare(&dir, &[&main], &["--section-headers"]).unwrap();
+    let bytes = linker::link(&config, &inputs).unwrap();
+    let elf = parse(&bytes);
+    assert_eq!(Some(elf.entry), symbol_address(&elf, "_start"));
+    assert_eq!(bytes, std::fs::read(dir.path().join("out")).unwrap());
+    assert_exits(&bytes, 3);
+}
+
+#[test]
+fn dash_writes_the_output_to_stdout() {
+    let dir = TempDir::new();
+    let main = Object::exiting(5);
+    let output = run_linker(&dir, &[&main], &["-o", "-"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(output.stdout, link(&[&main], &[]).unwrap());
+    assert_exits(&output.stdout, 5);
+    assert_eq!(
+        std::fs::read_dir(dir.path()).unwrap().count(),
+        1,
+        "only the input"
+    );
+}
+
+#[test]
+fn check_only_needs_no_output() {
+    let dir = TempDir::new();
+    let output = run_linker(&dir, &[&Object::exiting(0)], &["--check-only"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        std::fs::read_dir(dir.path()).unwrap().count(),
+        1,
+        "only the input"
+    );
+}
+
+#[test]
+fn check_only_reports_undefined_symbols() {
+    let dir = TempDir::new();
+    let mut main = Object::x86_64();
+    let text = main.text(".text", Code::new().call(sym("missing")).exit(0));
+    main.func("_start", text, 0);
+    let out = dir.path().join("out");
+    let output = run_linker(
+        &dir,
+        &[&main],
+        &["--check-only", "-o", out.to_str().unwrap()],
+    );
+    assert!(!output.status.success());
+    assert!(
+        stderr(&output).contains("\"missing\""),
+        "{}",
+        stderr(&output)
+    );
+    assert!(!out.exists());
+}
+
+/// Files in `dir` other than the inputs
+fn outputs(dir: &TempDir) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| !name.ends_with(".o"))
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn failed_links_keep_the_old_output() {
+    let dir = TempDir::new();
+    let out = dir.write("out", b"old");
+    let mut main = Object::x86_64();
+    let text = main.text(".text", Code::new().call(sym("missing")).exit(0));
+    main.func("_start", text, 0);
+    let output = run_linker(&dir, &[&main], &[]);
+    assert!(!output.status.success());
+    assert_eq!(std::fs::read(&out).unwrap(), b"old");
+    assert_eq!(outputs(&dir), ["out"]);
+}
+
+#[test]
+fn failed_writes_leave_no_partial_output() {
+    let dir = TempDir::new();
+    // Renaming the written file over a non-empty directory fails
+    std::fs::create_dir(dir.path().join("out")).unwrap();
+    dir.write("out/file", b"");
+    let output = run_linker(&dir, &[&Object::exiting(0)], &[]);
+    assert!(!output.status.success());
+    assert_eq!(outputs(&dir), ["out"]);
+}
+
+#[test]
+fn binary_output_matches_objcopy() {
+    let mut object = Object::x86_64();
+    let text = object.text(".text", Code::new().load_edi(sym("value"), 0).exit_edi());
+    object.func("_start", text, 0);
+    let data = object.data(".data", 8, &[42, 0, 0, 0]);
+    object.object("value", data, 0, 4);
+    object.bss(".bss", 8, 0x100);
+
+    let elf = link(&[&object], &["--section-headers"]).unwrap();
+    let binary = link(&[&object], &["--oformat", "binary"]).unwrap();
+    // The code loads `value` relative to itself, so it's at the same offset
+    let value = symbol_address(&parse(&elf), "value").unwrap();
+    let start = symbol_address(&parse(&elf), "_start").unwrap();
+    assert_eq!(binary[0..2], [0x8b, 0x3d]);
+    assert_eq!(binary[(value - start) as usize], 42);
+    // Zero-initialized data is left out
+    assert_eq!(binary.len() as u64, value - start + 4);
+
+    let dir = TempDir::new();
+    let path = dir.write("out.elf", &elf);
+    let Ok(status) = std::process::Command::new("objcopy")
+        .args(["-O", "binary"])
+        .arg(&path)
+        .arg(dir.path().join("out.bin"))
+        .status()
+    else {
+        return;
+    };
+    assert!(status.success());
+    let expected = std::fs::read(dir.path().join("out.bin")).unwrap();
+    assert_eq!(binary, expected);
+}
+
+#[test]
+fn link_map_lists_inputs_and_symbols() {
+    let dir = TempDir::new();
+    let main = Object::exiting(0);
+    let mut other = Object::x86_64();
+    let data = other.data(".data", 8, &[0; 8]);
+    other.object("counter", data, 0, 8);
+
+    let map = dir.path().join("out.map");
+    let map_flag = format!("--Map={}", map.display());
+    let output = run_linker(&dir, &[&main, &other], &["--section-headers", &map_flag]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let map = std::fs::read_to_string(map).unwrap();
+    let bytes = std::fs::read(dir.path().join("out")).unwrap();
+    let elf = parse(&bytes);
+    for path in write_objects(&dir, &[&main, &other]) {
+        assert!(map.contains(&path), "{path} missing from:\n{map}");
+    }
+    let start = format!("{:#010x} size 0x0 _start", elf.entry);
+    assert!(map.contains(&start), "{start} missing from:\n{map}");
+
+    // `-M` prints the same map
+    let output = run_linker(&dir, &[&main, &other], &["--section-headers", "-M"]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), map);
+}
+
+#[test]
+fn elf_output_formats_must_match_the_inputs() {
+    let bytes = link(&[&Object::exiting(0)], &["--oformat", "elf64-x86-64"]).unwrap();
+    assert_exits(&bytes, 0);
+    let mut object = Object::i386();
+    let text = object.text(".text", Code::new().raw(&[0xcc]));
+    object.func("_start", text, 0);
+    link(&[&object], &["--oformat", "elf32-i386"]).unwrap();
+
+    let err = link_error(&[&Object::exiting(0)], &["--oformat", "elf32-i386"]);
+    assert!(
+        err.contains("Output format for I386 (Little-endian) doesn't match the inputs, which are X86_64 (Little-endian)"),
+        "{err}"
+    );
+    let err = link_error(
+        &[&Object::aarch64(true)],
+        &["--oformat", "elf64-bigaarch64"],
+    );
+    assert!(
+        err.contains("Output format for Aarch64 (Big-endian)"),
+        "{err}"
+    );
+}
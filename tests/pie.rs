@@ -0,0 +1,89 @@
+mod common;
+
+use common::*;
+use goblin::elf::header::ET_DYN;
+use goblin::elf::program_header::{PT_DYNAMIC, PT_GNU_RELRO};
+
+/// `_start` referring to the bounds of `.rela.dyn`, and a table of two
+/// pointers into the image and one absolute value
+fn pointer_table() -> Object {
+    let mut object = Object::x86_64();
+    let code = Code::new()
+        .lea_rdi(sym("__rela_start"), 0)
+        .lea_rdi(sym("__rela_end"), 0)
+        .exit(0);
+    let text = object.text(".text", code);
+    object.func("_start", text, 0);
+    let rodata = object.rodata(".rodata", 8, &[0; 8]);
+    object.object("value", rodata, 0, 8);
+    object.absolute("constant", 0x1234);
+    let table = object.data(".data.rel.ro", 8, &[0; 24]);
+    object.object("table", table, 0, 24);
+    object.reloc(table, 0, R_X86_64_64, sym("_start"), 0);
+    object.reloc(table, 8, R_X86_64_64, sym("value"), 3);
+    object.reloc(table, 16, R_X86_64_64, sym("constant"), 0);
+    object
+}
+
+/// Contents of the output section `name`, and its address
+fn section<'a>(elf: &goblin::elf::Elf, bytes: &'a [u8], name: &str) -> (u64, &'a [u8]) {
+    let header = elf
+        .section_headers
+        .iter()
+        .find(|sh| elf.shdr_strtab.get_at(sh.sh_name) == Some(name))
+        .unwrap_or_else(|| panic!("missing {name}"));
+    let range = header.sh_offset as usize..(header.sh_offset + header.sh_size) as usize;
+    (header.sh_addr, &bytes[range])
+}
+
+fn words(bytes: &[u8]) -> Vec<u64> {
+    bytes
+        .chunks_exact(8)
+        .map(|word| u64::from_le_bytes(word.try_into().unwrap()))
+        .collect()
+}
+
+#[test]
+fn absolute_references_become_relative_relocations() {
+    let bytes = link(&[&pointer_table()], &["-pie", "--section-headers"]).unwrap();
+    check_with_readelf(&bytes);
+    let elf = parse(&bytes);
+    assert_eq!(elf.header.e_type, ET_DYN);
+
+    let (rela_start, rela) = section(&elf, &bytes, ".rela.dyn");
+    let table = symbol_address(&elf, "table").unwrap();
+    let value = symbol_address(&elf, "value").unwrap();
+    // (offset, type, addend) of each; the absolute value needs none
+    let relative = R_X86_64_RELATIVE as u64;
+    let expected = [table, relative, elf.entry, table + 8, relative, value + 3];
+    assert_eq!(words(rela), expected);
+    assert_eq!(symbol_address(&elf, "__rela_start"), Some(rela_start));
+    assert_eq!(symbol_address(&elf, "__rela_end"), Some(rela_start + 48));
+    assert_eq!(
+        read_at(&elf, &bytes, table + 16, 8),
+        0x1234u64.to_le_bytes()
+    );
+}
+
+#[test]
+fn dynamic_entries_describe_the_relocations() {
+    let flags = ["-pie", "-z", "relro", "--section-headers"];
+    let bytes = link(&[&pointer_table()], &flags).unwrap();
+    let elf = parse(&bytes);
+    let (rela_start, _) = section(&elf, &bytes, ".rela.dyn");
+    let (dynamic_start, dynamic) = section(&elf, &bytes, ".dynamic");
+
+    // DT_RELA, DT_RELASZ, DT_RELAENT, DT_RELACOUNT and DT_NULL
+    let expected = [7, rela_start, 8, 48, 9, 24, 0x6fff_fff9, 2, 0, 0];
+    assert_eq!(words(dynamic), expected);
+    let [header] = &program_headers(&elf, PT_DYNAMIC)[..] else {
+        panic!("expected one PT_DYNAMIC");
+    };
+    assert_eq!((header.p_vaddr, header.p_memsz), (dynamic_start, 80));
+    // Read-only once relocated
+    let [relro] = &program_headers(&elf, PT_GNU_RELRO)[..] else {
+        panic!("expected one PT_GNU_RELRO");
+    };
+    assert!(relro.p_vaddr <= dynamic_start);
+    assert!(dynamic_start + 80 <= relro.p_vaddr + relro.p_memsz);
+}
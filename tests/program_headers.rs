@@ -0,0 +1,269 @@
+mod common;
+
+use common::*;
+use goblin::elf::program_header::{
+    PF_R, PF_W, PF_X, PT_GNU_RELRO, PT_GNU_STACK, PT_LOAD, PT_NOTE, PT_PHDR, PT_TLS,
+};
+
+#[test]
+fn headers_are_mapped_by_the_first_load() {
+    let bytes = link(&[&Object::exiting(42)], &[]).unwrap();
+    let elf = parse(&bytes);
+    let [phdr] = &program_headers(&elf, PT_PHDR)[..] else {
+        panic!("expected one PT_PHDR");
+    };
+    let first = &program_headers(&elf, PT_LOAD)[0];
+    assert_eq!((first.p_offset, first.p_vaddr), (0, 0x40_0000));
+    assert_eq!((phdr.p_offset, phdr.p_vaddr), (0x40, 0x40_0040));
+    assert_eq!(phdr.p_filesz, elf.header.e_phnum as u64 * 0x38);
+    assert!(phdr.p_vaddr + phdr.p_memsz <= first.p_vaddr + first.p_memsz);
+    assert_eq!(
+        first.p_offset % first.p_align,
+        first.p_vaddr % first.p_align
+    );
+    assert_exits(&bytes, 42);
+}
+
+#[test]
+fn phdr_describes_the_whole_table() {
+    // Each of these adds a header, which PT_PHDR must count as well
+    for flags in [&[][..], &["--build-id"], &["--build-id", "-z", "relro"]] {
+        let bytes = link(&[&Object::exiting(0)], flags).unwrap();
+        let elf = parse(&bytes);
+        let phdr = &elf.program_headers[0];
+        assert_eq!(phdr.p_type, PT_PHDR);
+        let size = elf.header.e_phnum as u64 * elf.header.e_phentsize as u64;
+        assert_eq!(phdr.p_offset, elf.header.e_phoff);
+        assert_eq!((phdr.p_filesz, phdr.p_memsz), (size, size));
+        check_with_readelf(&bytes);
+    }
+}
+
+#[test]
+fn headers_of_unaligned_bases_are_mapped_from_the_page_below() {
+    // The headers are at file offset 0, so they can only be mapped at an
+    // address congruent to it: the page boundary below the base
+    let flags = ["-z", "max-page-size=0x10000", "--image-base", "0x401000"];
+    let bytes = link(&[&Object::exiting(42)], &flags).unwrap();
+    check_with_readelf(&bytes);
+    let elf = parse(&bytes);
+    let [phdr] = &program_headers(&elf, PT_PHDR)[..] else {
+        panic!("expected one PT_PHDR");
+    };
+    let first = &program_headers(&elf, PT_LOAD)[0];
+    assert_eq!((first.p_offset, first.p_vaddr), (0, 0x40_0000));
+    assert_eq!((phdr.p_offset, phdr.p_vaddr), (0x40, 0x40_0040));
+    assert!(phdr.p_vaddr + phdr.p_memsz <= first.p_vaddr + first.p_memsz);
+    // The contents still start after the base
+    assert!(elf.entry >= 0x40_1000);
+    assert!(elf.entry < first.p_vaddr + first.p_memsz);
+    assert_exits(&bytes, 42);
+}
+
+#[test]
+fn program_headers_are_in_canonical_order() {
+    let mut object = Object::exiting(0);
+    let relro = object.data(".data.rel.ro", 8, &[0; 8]);
+    object.reloc(relro, 0, R_X86_64_64, sym("_start"), 0);
+    let flags = SHF_ALLOC | SHF_WRITE | SHF_TLS;
+    object.section(".tdata", SHT_PROGBITS, flags, 8, &[1; 8]);
+
+    let flags = ["-z", "relro", "--build-id"];
+    let bytes = link(&[&object], &flags).unwrap();
+    let elf = parse(&bytes);
+    let types: Vec<u32> = elf.program_headers.iter().map(|ph| ph.p_type).collect();
+    let loads = types.iter().filter(|&&t| t == PT_LOAD).count();
+    let mut expected = vec![PT_PHDR];
+    expected.extend(std::iter::repeat_n(PT_LOAD, loads));
+    expected.extend([PT_NOTE, PT_TLS, PT_GNU_RELRO, PT_GNU_STACK]);
+    assert_eq!(types, expected);
+
+    let addresses: Vec<u64> = program_headers(&elf, PT_LOAD)
+        .iter()
+        .map(|ph| ph.p_vaddr)
+        .collect();
+    assert!(addresses.is_sorted());
+    check_with_readelf(&bytes);
+    assert_exits(&bytes, 0);
+}
+
+#[test]
+fn relro_covers_data_rel_ro() {
+    let mut object = Object::exiting(0);
+    let text = object.text(".text.f", Code::new().ret());
+    object.func("f", text, 0);
+    let relro = object.data(".data.rel.ro", 8, &[0; 8]);
+    object.reloc(relro, 0, R_X86_64_64, sym("f"), 0);
+
+    let without = link(&[&object], &[]).unwrap();
+    assert!(program_headers(&parse(&without), PT_GNU_RELRO).is_empty());
+
+    let bytes = link(&[&object], &["-z", "relro", "--section-headers"]).unwrap();
+    let elf = parse(&bytes);
+    let [relro] = &program_headers(&elf, PT_GNU_RELRO)[..] else {
+        panic!("expected one PT_GNU_RELRO");
+    };
+    let section = elf
+        .section_headers
+        .iter()
+        .find(|sh| elf.shdr_strtab.get_at(sh.sh_name) == Some(".data.rel.ro"))
+        .unwrap();
+    assert!(relro.p_vaddr <= section.sh_addr);
+    assert!(section.sh_addr + 8 <= relro.p_vaddr + relro.p_memsz);
+    assert_eq!(relro.p_flags, PF_R);
+    // The pointer is final, as nothing relocates a static executable at runtime
+    let f = symbol_address(&elf, "f").unwrap();
+    assert_eq!(read_at(&elf, &bytes, section.sh_addr, 8), f.to_le_bytes());
+    check_with_readelf(&bytes);
+}
+
+#[test]
+fn stack_size_is_in_the_stack_header() {
+    let object = Object::exiting(0);
+    for (flags, size) in [
+        (&[][..], 0),
+        (&["-z", "stack-size=0x100000"], 0x10_0000),
+        (&["-zstack-size=65536"], 0x1_0000),
+    ] {
+        let bytes = link(&[&object], flags).unwrap();
+        let elf = parse(&bytes);
+        let [stack] = &program_headers(&elf, PT_GNU_STACK)[..] else {
+            panic!("expected one PT_GNU_STACK");
+        };
+        assert_eq!(stack.p_memsz, size);
+        assert_eq!(stack.p_flags, PF_R | PF_W);
+        check_with_readelf(&bytes);
+    }
+}
+
+#[test]
+fn execstack_sets_the_stack_flags() {
+    let object = Object::exiting(0);
+    for (flags, stack_flags) in [
+        (&[][..], PF_R | PF_W),
+        (&["-znoexecstack"], PF_R | PF_W),
+        (&["-z", "execstack"], PF_R | PF_W | PF_X),
+        (&["-zexecstack", "-z", "noexecstack"], PF_R | PF_W),
+    ] {
+        let bytes = link(&[&object], flags).unwrap();
+        let elf = parse(&bytes);
+        let [stack] = &program_headers(&elf, PT_GNU_STACK)[..] else {
+            panic!("expected one PT_GNU_STACK");
+        };
+        assert_eq!(stack.p_flags, stack_flags, "{flags:?}");
+        assert_eq!((stack.p_offset, stack.p_filesz), (0, 0));
+        assert_exits(&bytes, 0);
+    }
+}
+
+#[test]
+#[should_panic(expected = "Invalid stack size")]
+fn unaligned_stack_size_is_rejected() {
+    let _ = link(&[&Object::exiting(0)], &["-z", "stack-size=1000"]);
+}
+
+/// (name, type, descriptor) of the only note
+fn build_id_note(bytes: &[u8]) -> (String, u32, Vec<u8>) {
+    let elf = parse(bytes);
+    let [note] = &program_headers(&elf, PT_NOTE)[..] else {
+        panic!("expected one PT_NOTE");
+    };
+    let note = read_at(&elf, bytes, note.p_vaddr, note.p_filesz as usize);
+    let word = |i: usize| u32::from_le_bytes(note[4 * i..4 * i + 4].try_into().unwrap());
+    let (name_size, desc_size) = (word(0) as usize, word(1) as usize);
+    let name = &note[12..12 + name_size];
+    let desc_start = 12 + name_size.next_multiple_of(4);
+    (
+        String::from_utf8(name.to_vec()).unwrap(),
+        word(2),
+        note[desc_start..desc_start + desc_size].to_vec(),
+    )
+}
+
+#[test]
+fn build_id_notes_hold_the_digest() {
+    let object = Object::exiting(0);
+    let bytes = link(&[&object], &["--build-id=sha1"]).unwrap();
+    check_with_readelf(&bytes);
+    let (name, n_type, sha1) = build_id_note(&bytes);
+    assert_eq!((name.as_str(), n_type, sha1.len()), ("GNU\0", 3, 20));
+    assert!(sha1.iter().any(|&b| b != 0));
+    let (_, _, md5) = build_id_note(&link(&[&object], &["--build-id=md5"]).unwrap());
+    assert_eq!(md5.len(), 16);
+
+    // The same contents give the same digest, and other contents another
+    assert_eq!(
+        build_id_note(&link(&[&object], &["--build-id=sha1"]).unwrap()).2,
+        sha1
+    );
+    let other = link(&[&Object::exiting(1)], &["--build-id=sha1"]).unwrap();
+    assert_ne!(build_id_note(&other).2, sha1);
+
+    for flags in [&[][..], &["--build-id=none"]] {
+        let bytes = link(&[&object], flags).unwrap();
+        assert!(program_headers(&parse(&bytes), PT_NOTE).is_empty());
+    }
+}
+
+/// Note of `n_type` from "GNU", with a 4-byte aligned descriptor
+fn gnu_note(n_type: u32, desc: &[u8]) -> Vec<u8> {
+    let mut note = Vec::new();
+    note.extend(4u32.to_le_bytes());
+    note.extend((desc.len() as u32).to_le_bytes());
+    note.extend(n_type.to_le_bytes());
+    note.extend(b"GNU\0");
+    note.extend(desc);
+    note
+}
+
+#[test]
+fn input_notes_have_their_own_pt_note() {
+    // GNU_PROPERTY_X86_FEATURE_1_AND of IBT and SHSTK, padded to 8 bytes
+    let property = gnu_note(5, &[2, 0, 0, 0xc0, 4, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0]);
+    // Linux 3.2.0
+    let abi_tag = gnu_note(1, &[0, 0, 0, 0, 3, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0]);
+    let mut object = Object::exiting(0);
+    object.section(".note.gnu.property", SHT_NOTE, SHF_ALLOC, 8, &property);
+    let mut other = Object::x86_64();
+    other.section(".note.ABI-tag", SHT_NOTE, SHF_ALLOC, 4, &abi_tag);
+
+    // Nothing refers to the notes, but they are kept
+    let bytes = link(&[&object, &other], &["--gc-sections"]).unwrap();
+    check_with_readelf(&bytes);
+    let elf = parse(&bytes);
+    let mut notes: Vec<(&[u8], u64)> = program_headers(&elf, PT_NOTE)
+        .iter()
+        .map(|note| {
+            let contents = read_at(&elf, &bytes, note.p_vaddr, note.p_filesz as usize);
+            (contents, note.p_align)
+        })
+        .collect();
+    notes.sort();
+    assert_eq!(notes, [(&abi_tag[..], 4), (&property[..], 8)]);
+    assert_exits(&bytes, 0);
+}
+
+#[test]
+fn load_offsets_are_congruent_for_unaligned_bases() {
+    let mut object = Object::exiting(0);
+    object.rodata(".rodata", 8, &[1; 0x20]);
+    object.data(".data", 8, &[2; 0x20]);
+    object.bss(".bss", 8, 0x100);
+    for base in ["0x401000", "0x40f000", "0x401800", "0x400010"] {
+        let flags = ["--image-base", base, "-z", "max-page-size=0x10000"];
+        let bytes = link(&[&object], &flags).unwrap();
+        check_with_readelf(&bytes);
+        let loads = program_headers(&parse(&bytes), PT_LOAD);
+        assert_eq!(loads.len(), 3, "{base}");
+        for load in loads {
+            let (offset, vaddr) = (load.p_offset, load.p_vaddr);
+            assert_eq!(load.p_align, 0x10000);
+            assert_eq!(
+                offset % load.p_align,
+                vaddr % load.p_align,
+                "{base}: {offset:#x} {vaddr:#x}"
+            );
+        }
+        assert_exits(&bytes, 0);
+    }
+}
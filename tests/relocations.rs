@@ -0,0 +1,513 @@
+mod common;
+
+use common::*;
+
+/// `_start` that jumps over `padding` trap bytes with a `jmp rel8`, then exits with 42
+fn short_jump(padding: usize) -> Object {
+    let mut object = Object::x86_64();
+    let code = Code::new()
+        .raw(&[0xeb])
+        .reloc(R_X86_64_PC8, sym("done"), -1, 1)
+        .raw(&vec![0xcc; padding])
+        .exit(42);
+    let text = object.text(".text", code);
+    object.func("_start", text, 0);
+    object.local("done", text, 2 + padding as u64);
+    object
+}
+
+/// Data field of `size` bytes relocated against the absolute symbol `value`
+fn absolute_field(r_type: u32, size: usize, value: u64) -> Object {
+    let mut object = Object::exiting(0);
+    let data = object.data(".data", 8, &vec![0; size]);
+    object.object("field", data, 0, size as u64);
+    object.reloc(data, 0, r_type, sym("value"), 0);
+    object.absolute("value", value);
+    object
+}
+
+fn field_bytes(object: &Object, size: usize) -> Vec<u8> {
+    let bytes = link(&[object], &["--section-headers"]).unwrap();
+    let elf = parse(&bytes);
+    let field = symbol_address(&elf, "field").unwrap();
+    read_at(&elf, &bytes, field, size).to_vec()
+}
+
+#[test]
+fn pc8_jumps_within_range() {
+    let bytes = link(&[&short_jump(100)], &[]).unwrap();
+    assert_exits(&bytes, 42);
+}
+
+#[test]
+fn pc8_overflow_is_an_error() {
+    let error = link_error(&[&short_jump(200)], &[]);
+    assert!(error.contains("X86_64_PC8"), "{error}");
+    assert!(error.contains("overflows"), "{error}");
+}
+
+/// `_start` that jumps with a `jmp rel8` into `.text.done`, which follows it
+/// after `padding` trap bytes and exits with 42
+fn short_jump_across_sections(padding: usize) -> Object {
+    let mut object = Object::x86_64();
+    let code = Code::new()
+        .raw(&[0xeb])
+        .reloc(R_X86_64_PC8, Ref::Section(2), -1, 1)
+        .raw(&vec![0xcc; padding]);
+    let text = object.text(".text", code);
+    object.func("_start", text, 0);
+    let done = object.text(".text.done", Code::new().exit(42));
+    assert_eq!(done, 2);
+    object
+}
+
+#[test]
+fn pc8_jumps_into_the_next_chunk() {
+    let bytes = link(&[&short_jump_across_sections(14)], &[]).unwrap();
+    let elf = parse(&bytes);
+    // `.text.done` is 16-aligned, right after the 16 bytes of the jump
+    assert_eq!(read_at(&elf, &bytes, elf.entry, 2), [0xeb, 14]);
+    assert_exits(&bytes, 42);
+
+    let error = link_error(&[&short_jump_across_sections(200)], &[]);
+    assert!(
+        error.contains("X86_64_PC8 against section .text.done"),
+        "{error}"
+    );
+}
+
+#[test]
+fn pc16_is_the_distance_from_the_field() {
+    let mut object = Object::exiting(0);
+    let rodata = object.rodata(".rodata", 8, &[0; 8]);
+    let data = object.data(".data", 8, &[0; 2]);
+    object.object("field", data, 0, 2);
+    object.reloc(data, 0, R_X86_64_PC16, Ref::Section(rodata), 4);
+    let bytes = link(&[&object], &["--section-headers"]).unwrap();
+    let elf = parse(&bytes);
+    let field = symbol_address(&elf, "field").unwrap();
+    let rodata = elf
+        .section_headers
+        .iter()
+        .find(|sh| elf.shdr_strtab.get_at(sh.sh_name) == Some(".rodata"))
+        .unwrap()
+        .sh_addr;
+    let value = i16::from_le_bytes(read_at(&elf, &bytes, field, 2).try_into().unwrap());
+    assert_eq!(value as i64, rodata as i64 + 4 - field as i64);
+}
+
+#[test]
+fn abs16_is_patched() {
+    let object = absolute_field(R_X86_64_16, 2, 0xbeef);
+    assert_eq!(field_bytes(&object, 2), [0xef, 0xbe]);
+}
+
+#[test]
+fn abs16_overflow_is_an_error() {
+    let error = link_error(&[&absolute_field(R_X86_64_16, 2, 0x1_0000)], &[]);
+    assert!(error.contains("X86_64_16"), "{error}");
+    assert!(error.contains("overflows"), "{error}");
+}
+
+#[test]
+fn abs8_is_patched() {
+    let object = absolute_field(R_X86_64_8, 1, 0xab);
+    assert_eq!(field_bytes(&object, 1), [0xab]);
+    let error = link_error(&[&absolute_field(R_X86_64_8, 1, 0x100)], &[]);
+    assert!(
+        error.contains("X86_64_8 against symbol \"value\""),
+        "{error}"
+    );
+    assert!(error.contains("overflows: 0x100"), "{error}");
+}
+
+#[test]
+fn pc64_is_the_distance_from_the_field() {
+    let mut object = absolute_field(R_X86_64_PC64, 8, 0x10);
+    object.sections[1].relocs[0].addend = 3;
+    let bytes = link(&[&object], &["--section-headers"]).unwrap();
+    let elf = parse(&bytes);
+    let field = symbol_address(&elf, "field").unwrap();
+    let value = i64::from_le_bytes(read_at(&elf, &bytes, field, 8).try_into().unwrap());
+    assert_eq!(value, 0x10 + 3 - field as i64);
+}
+
+#[test]
+fn back_to_back_fields_up_to_the_chunk_end_are_patched() {
+    let mut object = Object::exiting(0);
+    let data = object.data(".data", 8, &[0; 20]);
+    object.object("field", data, 0, 20);
+    object.absolute("value", 0x1122_3344_5566_7788);
+    object.reloc(data, 0, R_X86_64_64, sym("value"), 0);
+    object.reloc(data, 8, R_X86_64_64, sym("value"), 1);
+    object.reloc(data, 16, R_X86_64_32, sym("value"), -0x1122_3344_0000_0000);
+
+    let bytes = field_bytes(&object, 20);
+    assert_eq!(bytes[..8], 0x1122_3344_5566_7788u64.to_le_bytes());
+    assert_eq!(bytes[8..16], 0x1122_3344_5566_7789u64.to_le_bytes());
+    assert_eq!(bytes[16..], 0x5566_7788u32.to_le_bytes());
+}
+
+#[test]
+fn abs32_is_patched_unsigned() {
+    let object = absolute_field(R_X86_64_32, 4, 0xdead_beef);
+    assert_eq!(field_bytes(&object, 4), 0xdead_beefu32.to_le_bytes());
+    let error = link_error(&[&absolute_field(R_X86_64_32, 4, 1 << 32)], &[]);
+    assert!(
+        error.contains("X86_64_32 against symbol") && error.contains("\"value\""),
+        "{error}"
+    );
+    assert!(error.contains("overflows"), "{error}");
+}
+
+#[test]
+fn rel_addends_are_read_from_the_field() {
+    let mut object = absolute_field(R_X86_64_32, 4, 0x1000);
+    object.use_rel = true;
+    object.sections[1].relocs[0].addend = 0x234;
+    assert_eq!(field_bytes(&object, 4), 0x1234u32.to_le_bytes());
+
+    // `call helper`, with the -4 of the PC-relative field stored in it
+    let mut object = Object::x86_64();
+    object.use_rel = true;
+    let text = object.text(".text", Code::new().call(sym("helper")).exit(1));
+    object.func("_start", text, 0);
+    let text = object.text(".text.helper", Code::new().exit(42));
+    object.func("helper", text, 0);
+    assert!(object
+        .bytes()
+        .windows(4)
+        .any(|w| w == (-4i32).to_le_bytes()));
+    assert_exits(&link(&[&object], &[]).unwrap(), 42);
+}
+
+#[test]
+fn plt32_calls_go_directly_to_the_function() {
+    let mut object = Object::x86_64();
+    let text = object.text(".text", Code::new().call_plt(sym("helper")).exit(1));
+    object.func("_start", text, 0);
+    let text = object.text(".text.helper", Code::new().exit(42));
+    object.func("helper", text, 0);
+    let bytes = link(&[&object], &["--section-headers"]).unwrap();
+    let elf = parse(&bytes);
+    let (start, helper) = (elf.entry, symbol_address(&elf, "helper").unwrap());
+    let field = read_at(&elf, &bytes, start + 1, 4);
+    assert_eq!(field, (helper.wrapping_sub(start + 5) as i32).to_le_bytes());
+    assert_exits(&bytes, 42);
+
+    // The same on i386, where the addend is in the field
+    let mut object = Object::i386();
+    let code = Code::new()
+        .raw(&[0xe8])
+        .reloc(R_386_PLT32, sym("helper"), -4, 4)
+        .raw(&[0xcc]);
+    let text = object.text(".text", code);
+    object.func("_start", text, 0);
+    let text = object.text(".text.helper", Code::new().raw(&[0xc3]));
+    object.func("helper", text, 0);
+    let bytes = link(&[&object], &["--section-headers"]).unwrap();
+    let elf = parse(&bytes);
+    let (start, helper) = (elf.entry, symbol_address(&elf, "helper").unwrap());
+    let field = read_at(&elf, &bytes, start + 1, 4);
+    assert_eq!(field, (helper.wrapping_sub(start + 5) as i32).to_le_bytes());
+}
+
+#[test]
+fn i386_rel_addends_are_read_from_the_field() {
+    let mut object = Object::i386();
+    // call helper; int3
+    let code = Code::new()
+        .raw(&[0xe8])
+        .reloc(R_386_PC32, sym("helper"), -4, 4)
+        .raw(&[0xcc]);
+    let text = object.text(".text", code);
+    object.func("_start", text, 0);
+    let text = object.text(".text.helper", Code::new().raw(&[0xc3]));
+    object.func("helper", text, 0);
+    let rodata = object.rodata(".rodata", 4, &[0; 8]);
+    object.object("value", rodata, 0, 8);
+    let data = object.data(".data", 4, &[0; 8]);
+    object.object("table", data, 0, 8);
+    object.reloc(data, 0, R_386_32, sym("value"), 3);
+    object.reloc(data, 4, R_386_PC32, sym("helper"), 0);
+
+    let bytes = link(&[&object], &["--section-headers"]).unwrap();
+    let elf = parse(&bytes);
+    let address = |name| symbol_address(&elf, name).unwrap();
+    let word = |vaddr| u32::from_le_bytes(read_at(&elf, &bytes, vaddr, 4).try_into().unwrap());
+    let (start, helper) = (address("_start"), address("helper"));
+    assert_eq!(word(start + 1), helper.wrapping_sub(start + 5) as u32);
+    let table = address("table");
+    assert_eq!(word(table), address("value") as u32 + 3);
+    assert_eq!(word(table + 4), helper.wrapping_sub(table + 4) as u32);
+}
+
+#[test]
+fn abs32s_is_patched_signed() {
+    let object = absolute_field(R_X86_64_32S, 4, 0x7fff_ffff);
+    assert_eq!(field_bytes(&object, 4), 0x7fff_ffffu32.to_le_bytes());
+    let object = absolute_field(R_X86_64_32S, 4, (-0x8000_0000i64) as u64);
+    assert_eq!(field_bytes(&object, 4), 0x8000_0000u32.to_le_bytes());
+    let error = link_error(&[&absolute_field(R_X86_64_32S, 4, 0x8000_0000)], &[]);
+    assert!(error.contains("X86_64_32S"), "{error}");
+    assert!(error.contains("overflows"), "{error}");
+}
+
+/// `_start` referring to `buf` of 8 bytes at an offset
+fn reference_into_buffer(offset: i64) -> Object {
+    let mut object = Object::x86_64();
+    let text = object.text(".text", Code::new().lea_rdi(sym("buf"), offset).exit(0));
+    object.func("_start", text, 0);
+    let data = object.data(".data", 8, &[0; 8]);
+    object.object("buf", data, 0, 8);
+    object
+}
+
+#[test]
+fn check_symbol_bounds_warns_past_the_size() {
+    let dir = TempDir::new();
+    let flags = ["--check-symbol-bounds"];
+    let output = run_linker(&dir, &[&reference_into_buffer(16)], &flags);
+    assert!(output.status.success());
+    let message = stderr(&output);
+    assert!(
+        message.contains("warning: relocation") && message.contains("outside of its size 8"),
+        "{message}"
+    );
+
+    let output = run_linker(&dir, &[&reference_into_buffer(8)], &flags);
+    assert!(output.status.success());
+    assert_eq!(stderr(&output), "");
+
+    // Calls refer to the start of the function, whatever their relocation
+    let mut object = Object::x86_64();
+    let code = Code::new()
+        .call(sym("helper"))
+        .call_plt(sym("helper"))
+        .exit(0);
+    let text = object.text(".text", code);
+    object.func("_start", text, 0);
+    let text = object.text(".text.helper", Code::new().ret());
+    object.symbol("helper", STB_GLOBAL, STT_FUNC, Shndx::Section(text), 0, 1);
+    let output = run_linker(&dir, &[&object], &flags);
+    assert!(output.status.success());
+    assert_eq!(stderr(&output), "");
+}
+
+#[test]
+fn objects_without_relocations_link() {
+    let object = Object::exiting(42);
+    assert!(object.sections.iter().all(|s| s.relocs.is_empty()));
+    let bytes = link(&[&object, &Object::x86_64()], &["--section-headers"]).unwrap();
+    check_with_readelf(&bytes);
+    assert_eq!(
+        parse(&bytes).header.e_entry,
+        symbol_address(&parse(&bytes), "_start").unwrap()
+    );
+    assert_exits(&bytes, 42);
+}
+
+#[test]
+fn sections_past_4gib_are_addressed_in_64_bits() {
+    const SIZE: u64 = 5 << 30;
+    let mut object = Object::x86_64();
+    let bss = object.bss(".bss", 16, SIZE);
+    object.object("last", bss, SIZE - 8, 8);
+    let pointer = object.data(".data", 8, &[0; 8]);
+    object.reloc(pointer, 0, R_X86_64_64, sym("last"), 0);
+    object.object("pointer", pointer, 0, 8);
+
+    let mut direct = object.clone();
+    let text = direct.text(".text", Code::new().exit(0));
+    direct.func("_start", text, 0);
+    let bytes = link(&[&direct], &["--section-headers"]).unwrap();
+    let elf = parse(&bytes);
+    let last = symbol_address(&elf, "last").unwrap();
+    let pointer = symbol_address(&elf, "pointer").unwrap();
+    assert!(last - pointer > SIZE - 0x1000);
+    assert_eq!(read_at(&elf, &bytes, pointer, 8), last.to_le_bytes());
+    // Only the address space is that large
+    assert!((bytes.len() as u64) < 1 << 20);
+
+    // A 32-bit displacement can't reach it
+    let mut relative = object;
+    let text = relative.text(".text", Code::new().load_edi(sym("last"), 0).exit_edi());
+    relative.func("_start", text, 0);
+    let err = link_error(&[&relative], &[]);
+    assert!(
+        err.contains("X86_64_PC32") && err.contains("overflows"),
+        "{err}"
+    );
+}
+
+#[test]
+fn section_references_resolve_into_each_of_many_sections() {
+    const SECTIONS: usize = 300;
+    let mut object = Object::exiting(0);
+    let table = object.data(".data", 8, &[0; 8 * SECTIONS]);
+    object.object("table", table, 0, 8 * SECTIONS as u64);
+    for i in 0..SECTIONS {
+        let name = format!(".rodata.{i}");
+        let section = object.rodata(&name, 1 << (i % 5), &[i as u8; 3]);
+        object.reloc(table, 8 * i as u64, R_X86_64_64, Ref::Section(section), 1);
+    }
+
+    let bytes = link(&[&object], &["--section-headers"]).unwrap();
+    let elf = parse(&bytes);
+    let table = symbol_address(&elf, "table").unwrap();
+    for i in 0..SECTIONS {
+        let pointer = read_at(&elf, &bytes, table + 8 * i as u64, 8);
+        let pointer = u64::from_le_bytes(pointer.try_into().unwrap());
+        assert_eq!(
+            (pointer - 1) % (1 << (i % 5)),
+            0,
+            "alignment of section {i}"
+        );
+        assert_eq!(read_at(&elf, &bytes, pointer, 2), [i as u8; 2]);
+    }
+}
+
+/// `_start` exiting with the byte at `value`, loading its address from the GOT
+fn got_load(r_type: u32) -> Object {
+    let mut object = Object::x86_64();
+    // mov value@GOTPCREL(%rip), %rax
+    let code = Code::new()
+        .raw(&[0x48, 0x8b, 0x05])
+        .reloc(r_type, sym("value"), -4, 4)
+        .deref_byte_rax()
+        .exit_edi();
+    let text = object.text(".text", code);
+    object.func("_start", text, 0);
+    let rodata = object.rodata(".rodata", 8, &[42]);
+    object.object("value", rodata, 0, 1);
+    object
+}
+
+#[test]
+fn gotpcrel_loads_through_a_got_slot() {
+    let bytes = link(&[&got_load(R_X86_64_GOTPCREL)], &["--section-headers"]).unwrap();
+    let elf = parse(&bytes);
+    let got = elf
+        .section_headers
+        .iter()
+        .find(|sh| elf.shdr_strtab.get_at(sh.sh_name) == Some(".got"))
+        .expect("missing .got");
+    assert_eq!(got.sh_size, 8);
+    let value = symbol_address(&elf, "value").unwrap();
+    assert_eq!(read_at(&elf, &bytes, got.sh_addr, 8), value.to_le_bytes());
+
+    let start = symbol_address(&elf, "_start").unwrap();
+    let field = read_at(&elf, &bytes, start + 3, 4);
+    let displacement = i32::from_le_bytes(field.try_into().unwrap());
+    assert_eq!(start as i64 + 7 + displacement as i64, got.sh_addr as i64);
+    assert_exits(&bytes, 42);
+}
+
+#[test]
+fn relaxable_got_loads_become_lea() {
+    let bytes = link(&[&got_load(R_X86_64_REX_GOTPCRELX)], &["--section-headers"]).unwrap();
+    let elf = parse(&bytes);
+    assert!(elf
+        .section_headers
+        .iter()
+        .all(|sh| elf.shdr_strtab.get_at(sh.sh_name) != Some(".got")));
+    let start = symbol_address(&elf, "_start").unwrap();
+    // lea value(%rip), %rax
+    assert_eq!(read_at(&elf, &bytes, start, 3), [0x48, 0x8d, 0x05]);
+    assert_exits(&bytes, 42);
+}
+
+#[test]
+fn got_loads_of_absolute_symbols_keep_their_slot() {
+    let mut object = got_load(R_X86_64_REX_GOTPCRELX);
+    object.symbols.retain(|s| s.name != "value");
+    object.absolute("value", 0x1234_5678_9abc);
+    let bytes = link(&[&object], &["--section-headers"]).unwrap();
+    let elf = parse(&bytes);
+    let got = elf
+        .section_headers
+        .iter()
+        .find(|sh| elf.shdr_strtab.get_at(sh.sh_name) == Some(".got"))
+        .expect("missing .got");
+    assert_eq!(
+        read_at(&elf, &bytes, got.sh_addr, 8),
+        0x1234_5678_9abcu64.to_le_bytes()
+    );
+    assert_eq!(read_at(&elf, &bytes, elf.entry, 3), [0x48, 0x8b, 0x05]);
+}
+
+#[test]
+fn tpoff32_is_the_offset_from_the_end_of_the_tls_block() {
+    use goblin::elf::program_header::PT_TLS;
+    use goblin::elf::sym::STT_TLS;
+
+    let mut object = Object::x86_64();
+    // mov %fs:a@tpoff, %eax; mov %fs:c@tpoff, %eax
+    let code = Code::new()
+        .raw(&[0x64, 0x8b, 0x04, 0x25])
+        .reloc(R_X86_64_TPOFF32, sym("a"), 0, 4)
+        .raw(&[0x64, 0x8b, 0x04, 0x25])
+        .reloc(R_X86_64_TPOFF32, sym("c"), 0, 4)
+        .exit(0);
+    let text = object.text(".text", code);
+    object.func("_start", text, 0);
+    let flags = SHF_ALLOC | SHF_WRITE | SHF_TLS;
+    let tdata = object.section(".tdata", SHT_PROGBITS, flags, 8, &[1; 8]);
+    object.symbol("a", STB_GLOBAL, STT_TLS, Shndx::Section(tdata), 4, 4);
+    let tbss = object.bss(".tbss", 16, 16);
+    object.sections[tbss - 1].flags |= SHF_TLS as u64;
+    object.symbol("c", STB_GLOBAL, STT_TLS, Shndx::Section(tbss), 8, 4);
+
+    let bytes = link(&[&object], &[]).unwrap();
+    check_with_readelf(&bytes);
+    let elf = parse(&bytes);
+    let [tls] = &program_headers(&elf, PT_TLS)[..] else {
+        panic!("expected one PT_TLS");
+    };
+    // `.tbss` starts 16-aligned after the 8 bytes of `.tdata`
+    assert_eq!((tls.p_filesz, tls.p_memsz, tls.p_align), (8, 32, 16));
+    let field = |offset| {
+        let field = read_at(&elf, &bytes, elf.entry + offset, 4);
+        i32::from_le_bytes(field.try_into().unwrap())
+    };
+    assert_eq!(field(4), 4 - 32);
+    assert_eq!(field(12), 16 + 8 - 32);
+}
+
+/// A pointer in `.data` to `target`, which may be defined in `.comment`,
+/// the second section, of this input or of the next one
+fn pointer_into_comment(target: Ref) -> Object {
+    let mut object = Object::exiting(0);
+    let comment = object.section(".comment", SHT_PROGBITS, 0, 1, b"note\0");
+    object.local("comment", comment, 0);
+    let data = object.data(".data", 8, &[0; 8]);
+    object.reloc(data, 0, R_X86_64_64, target, 0);
+    object
+}
+
+#[test]
+fn relocation_errors_name_the_input() {
+    let error = link_error(&[&pointer_into_comment(sym("missing"))], &[]);
+    assert!(
+        error.contains("Unable to resolve imported symbol \"missing\" in \"")
+            && error.contains("0.o\""),
+        "{error}"
+    );
+
+    for target in [Ref::Section(2), sym("comment")] {
+        let error = link_error(&[&pointer_into_comment(target)], &[]);
+        assert!(error.contains("not in any loadable segment"), "{error}");
+        assert!(error.contains("0.o\""), "{error}");
+    }
+
+    let mut other = Object::x86_64();
+    let comment = other.section(".comment", SHT_PROGBITS, 0, 1, b"other\0");
+    other.object("other", comment, 0, 6);
+    let error = link_error(&[&pointer_into_comment(sym("other")), &other], &[]);
+    assert!(
+        error.contains("Symbol \"other\" defined in \"")
+            && error.contains("1.o\" is not in any loadable segment, but it's referenced from \"")
+            && error.ends_with("0.o\""),
+        "{error}"
+    );
+}
@@ -0,0 +1,137 @@
+mod common;
+
+use common::*;
+use goblin::elf::section_header::{SHT_NULL, SHT_STRTAB};
+
+#[test]
+fn null_section_comes_first() {
+    let bytes = link(&[&Object::exiting(0)], &["--section-headers", "-s"]).unwrap();
+    check_with_readelf(&bytes);
+    let elf = parse(&bytes);
+    let null = &elf.section_headers[0];
+    assert_eq!(null.sh_type, SHT_NULL);
+    assert_eq!(
+        (null.sh_name, null.sh_addr, null.sh_offset, null.sh_size),
+        (0, 0, 0, 0)
+    );
+
+    // Only `.text` and the section names are left with `-s`
+    let names: Vec<_> = elf
+        .section_headers
+        .iter()
+        .map(|sh| elf.shdr_strtab.get_at(sh.sh_name).unwrap())
+        .collect();
+    assert_eq!(names, ["", ".text", ".shstrtab"]);
+    let shstrtab = &elf.section_headers[elf.header.e_shstrndx as usize];
+    assert_eq!(shstrtab.sh_type, SHT_STRTAB);
+}
+
+#[test]
+fn symtab_lists_globals_at_their_addresses() {
+    let mut object = Object::exiting(0);
+    let data = object.data(".data", 8, &[0; 24]);
+    object.object("table", data, 8, 16);
+
+    let bytes = link(&[&object], &["--section-headers"]).unwrap();
+    check_with_readelf(&bytes);
+    let elf = parse(&bytes);
+    let names: Vec<_> = elf
+        .section_headers
+        .iter()
+        .map(|sh| elf.shdr_strtab.get_at(sh.sh_name).unwrap())
+        .collect();
+    for name in [".text", ".data", ".symtab", ".strtab", ".shstrtab"] {
+        assert!(names.contains(&name), "{names:?}");
+    }
+
+    let symbol = |name: &str| {
+        elf.syms
+            .iter()
+            .find(|sym| elf.strtab.get_at(sym.st_name) == Some(name))
+            .unwrap()
+    };
+    let text = elf
+        .section_headers
+        .iter()
+        .find(|sh| elf.shdr_strtab.get_at(sh.sh_name) == Some(".text"))
+        .unwrap();
+    let start = symbol("_start");
+    assert_eq!(start.st_value, elf.header.e_entry);
+    assert_eq!(start.st_value, text.sh_addr);
+    let data = elf
+        .section_headers
+        .iter()
+        .find(|sh| elf.shdr_strtab.get_at(sh.sh_name) == Some(".data"))
+        .unwrap();
+    let table = symbol("table");
+    assert_eq!((table.st_value, table.st_size), (data.sh_addr + 8, 16));
+
+    // Without the flag, there is no table at all
+    let bytes = link(&[&object], &[]).unwrap();
+    let header = parse(&bytes).header;
+    assert_eq!(
+        (header.e_shoff, header.e_shnum, header.e_shstrndx),
+        (0, 0, 0)
+    );
+}
+
+#[test]
+fn strip_flags_control_the_symbol_table() {
+    let mut object = Object::exiting(0);
+    object.section(".debug_info", SHT_PROGBITS, 0, 1, &[0; 16]);
+    let names = |flags: &[&str]| {
+        let flags = [flags, &["--section-headers"]].concat();
+        let bytes = link(&[&object], &flags).unwrap();
+        check_with_readelf(&bytes);
+        let elf = parse(&bytes);
+        elf.section_headers
+            .iter()
+            .map(|sh| elf.shdr_strtab.get_at(sh.sh_name).unwrap().to_owned())
+            .collect::<Vec<_>>()
+    };
+    let stripped = ["", ".text", ".shstrtab"];
+    assert_eq!(names(&["-s"]), stripped);
+    assert_eq!(names(&["--strip-all"]), stripped);
+    let symbols = ["", ".text", ".symtab", ".strtab", ".shstrtab"];
+    assert_eq!(names(&[]), symbols);
+    assert_eq!(names(&["-S"]), symbols);
+    assert_eq!(names(&["--strip-debug"]), symbols);
+}
+
+#[test]
+fn elf32_section_headers_and_symbols() {
+    let mut object = Object::i386();
+    // int3; ret
+    let text = object.text(".text", Code::new().raw(&[0xcc, 0xc3]));
+    object.func("_start", text, 0);
+    let data = object.data(".data", 4, &[0; 8]);
+    object.object("table", data, 4, 4);
+
+    let bytes = link(&[&object], &["--section-headers"]).unwrap();
+    check_with_readelf(&bytes);
+    let elf = parse(&bytes);
+    assert!(!elf.is_64);
+    assert_eq!(elf.header.e_shentsize, 40);
+    let names: Vec<_> = elf
+        .section_headers
+        .iter()
+        .map(|sh| elf.shdr_strtab.get_at(sh.sh_name).unwrap())
+        .collect();
+    assert_eq!(
+        names,
+        ["", ".text", ".data", ".symtab", ".strtab", ".shstrtab"]
+    );
+    let symtab = &elf.section_headers[3];
+    assert_eq!(symtab.sh_entsize, 16);
+
+    let start = symbol_address(&elf, "_start").unwrap();
+    assert_eq!(start, elf.header.e_entry);
+    assert_eq!(start, elf.section_headers[1].sh_addr);
+    let table = elf
+        .syms
+        .iter()
+        .find(|sym| elf.strtab.get_at(sym.st_name) == Some("table"))
+        .unwrap();
+    assert_eq!(table.st_value, elf.section_headers[2].sh_addr + 4);
+    assert_eq!((table.st_size, table.st_shndx), (4, 2));
+}
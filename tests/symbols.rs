@@ -0,0 +1,302 @@
+mod common;
+
+use common::*;
+use goblin::elf::program_header::PT_LOAD;
+
+/// Data and bss, with a pointer to `target` in `.data` at `pointer`
+fn pointer_to(target: &str) -> Object {
+    let mut object = Object::exiting(0);
+    let data = object.data(".data", 8, &[0; 8]);
+    object.object("pointer", data, 0, 8);
+    object.reloc(data, 0, R_X86_64_64, sym(target), 0);
+    object.bss(".bss", 8, 0x100);
+    object
+}
+
+/// Value of the pointer defined by [`pointer_to`]
+fn pointer_value(bytes: &[u8]) -> u64 {
+    let elf = parse(bytes);
+    let pointer = symbol_address(&elf, "pointer").unwrap();
+    u64::from_le_bytes(read_at(&elf, bytes, pointer, 8).try_into().unwrap())
+}
+
+#[test]
+fn end_is_past_the_highest_loaded_address() {
+    let bytes = link(&[&pointer_to("_end")], &["--section-headers"]).unwrap();
+    let elf = parse(&bytes);
+    let highest = program_headers(&elf, PT_LOAD)
+        .iter()
+        .map(|ph| ph.p_vaddr + ph.p_memsz)
+        .max()
+        .unwrap();
+    assert_eq!(pointer_value(&bytes), highest.next_multiple_of(0x1000));
+}
+
+#[test]
+fn boundary_symbols_follow_the_data() {
+    let bytes = link(&[&pointer_to("__bss_start")], &["--section-headers"]).unwrap();
+    let elf = parse(&bytes);
+    let bss = elf
+        .section_headers
+        .iter()
+        .find(|sh| elf.shdr_strtab.get_at(sh.sh_name) == Some(".bss"))
+        .unwrap();
+    assert_eq!(pointer_value(&bytes), bss.sh_addr);
+
+    let bytes = link(&[&pointer_to("_edata")], &["--section-headers"]).unwrap();
+    let pointer = symbol_address(&parse(&bytes), "pointer").unwrap();
+    assert_eq!(pointer_value(&bytes), pointer + 8);
+}
+
+#[test]
+fn inputs_can_define_end() {
+    let mut object = pointer_to("_end");
+    object.absolute("_end", 0x1234_5678);
+    let bytes = link(&[&object], &["--section-headers"]).unwrap();
+    assert_eq!(pointer_value(&bytes), 0x1234_5678);
+}
+
+#[test]
+fn boundary_symbols_are_only_defined_when_referenced() {
+    let bytes = link(&[&pointer_to("_end")], &["--section-headers"]).unwrap();
+    let elf = parse(&bytes);
+    assert!(symbol_address(&elf, "_end").is_some());
+    assert_eq!(symbol_address(&elf, "_edata"), None);
+    assert_eq!(symbol_address(&elf, "__init_array_start"), None);
+}
+
+/// Pointers to `rom_read` and `rom_write`, and a function calling `rom_reset`
+fn rom_calls() -> Object {
+    let mut object = Object::exiting(0);
+    let data = object.data(".data", 8, &[0; 16]);
+    object.object("pointers", data, 0, 16);
+    object.reloc(data, 0, R_X86_64_64, sym("rom_read"), 0);
+    object.reloc(data, 8, R_X86_64_64, sym("rom_write"), 0);
+    let text = object.text(".text.reset", Code::new().call(sym("rom_reset")).ret());
+    object.func("reset", text, 0);
+    object
+}
+
+#[test]
+fn symbol_maps_define_rom_calls() {
+    let dir = TempDir::new();
+    let map = dir.write(
+        "rom.map",
+        b"# ROM entry points\nrom_read 0x10000000\nrom_write 0x10000100\n\nrom_reset 268435968 # 0x10000200\n",
+    );
+    let map = map.to_str().unwrap();
+    let bytes = link(&[&rom_calls()], &["--section-headers", "--symbol-map", map]).unwrap();
+    let elf = parse(&bytes);
+    let pointers = symbol_address(&elf, "pointers").unwrap();
+    let pointers = read_at(&elf, &bytes, pointers, 16);
+    assert_eq!(pointers[..8], 0x1000_0000u64.to_le_bytes());
+    assert_eq!(pointers[8..], 0x1000_0100u64.to_le_bytes());
+    // The call displacement is relative to the end of the instruction
+    let reset = symbol_address(&elf, "reset").unwrap();
+    let displacement = read_at(&elf, &bytes, reset + 1, 4);
+    let displacement = i32::from_le_bytes(displacement.try_into().unwrap());
+    assert_eq!(reset as i64 + 5 + displacement as i64, 0x1000_0200);
+    assert_exits(&bytes, 0);
+}
+
+#[test]
+fn symbol_maps_conflict_with_definitions() {
+    let dir = TempDir::new();
+    let map = dir.write("rom.map", b"rom_read 0x10000000\n_start 0x10000100\n");
+    let map = format!("--symbol-map={}", map.to_str().unwrap());
+    let error = link_error(&[&rom_calls()], &[&map]);
+    assert!(
+        error.contains("rom.map:2: symbol \"_start\" is already defined in"),
+        "{error}"
+    );
+}
+
+/// `_start` calling `first` then `second`, exiting with the result of the last.
+/// Both inputs have a static `helper`, returning 1 in the first and 42 in the other.
+fn static_helpers(first: &str, second: &str) -> [Object; 2] {
+    let mut main = Object::x86_64();
+    let code = Code::new()
+        .call(sym(first))
+        .call(sym(second))
+        .eax_to_edi()
+        .exit_edi();
+    let text = main.text(".text", code);
+    main.func("_start", text, 0);
+    let helper = main.text(".text.helper", Code::new().set_eax(1).ret());
+    main.local("helper", helper, 0);
+
+    let mut other = Object::x86_64();
+    let text = other.text(".text", Code::new().call(sym("helper")).ret());
+    other.func("other", text, 0);
+    let helper = other.text(".text.helper", Code::new().set_eax(42).ret());
+    other.local("helper", helper, 0);
+    [main, other]
+}
+
+#[test]
+fn relocations_resolve_static_functions_of_their_input() {
+    let [main, other] = static_helpers("other", "helper");
+    assert_exits(&link(&[&main, &other], &[]).unwrap(), 1);
+    let [main, other] = static_helpers("helper", "other");
+    assert_exits(&link(&[&main, &other], &[]).unwrap(), 42);
+}
+
+/// `_start` exiting with 42 if the weak `hook` is null, otherwise with its result
+fn optional_hook() -> Object {
+    let mut object = Object::x86_64();
+    // lea hook(%rip), %rax; test %rax, %rax; jnz 1f
+    let code = Code::new()
+        .raw(&[0x48, 0x8d, 0x05])
+        .reloc(R_X86_64_PC32, sym("hook"), -4, 4)
+        .raw(&[0x48, 0x85, 0xc0, 0x75, 0x0c])
+        .exit(42)
+        // 1: call *%rax
+        .raw(&[0xff, 0xd0])
+        .eax_to_edi()
+        .exit_edi();
+    let text = object.text(".text", code);
+    object.func("_start", text, 0);
+    object.weak_undefined("hook");
+    object
+}
+
+#[test]
+fn undefined_weak_references_are_null() {
+    assert_exits(&link(&[&optional_hook()], &[]).unwrap(), 42);
+
+    let mut pointer = pointer_to("hook");
+    pointer.weak_undefined("hook");
+    let bytes = link(&[&pointer], &["--section-headers"]).unwrap();
+    assert_eq!(pointer_value(&bytes), 0);
+    // Nor does loading a position-independent executable relocate it
+    let bytes = link(&[&pointer], &["-pie", "--section-headers"]).unwrap();
+    assert_eq!(pointer_value(&bytes), 0);
+    let elf = parse(&bytes);
+    assert!(elf
+        .section_headers
+        .iter()
+        .all(|sh| elf.shdr_strtab.get_at(sh.sh_name) != Some(".rela.dyn") || sh.sh_size == 0));
+
+    // Only weak references may stay undefined
+    let error = link_error(&[&pointer_to("hook")], &[]);
+    assert!(
+        error.contains("Unable to resolve imported symbol \"hook\""),
+        "{error}"
+    );
+}
+
+#[test]
+fn weak_references_use_a_definition() {
+    let mut hook = Object::x86_64();
+    let text = hook.text(".text", Code::new().set_eax(7).ret());
+    hook.func("hook", text, 0);
+    assert_exits(&link(&[&optional_hook(), &hook], &[]).unwrap(), 7);
+}
+
+/// Object with an `.init_array` entry in `section` pointing at function `name`
+fn constructor(name: &str, section: &str) -> Object {
+    let mut object = Object::x86_64();
+    let text = object.text(".text", Code::new().ret());
+    object.func(name, text, 0);
+    let array = object.section(
+        section,
+        goblin::elf::section_header::SHT_INIT_ARRAY,
+        SHF_ALLOC | SHF_WRITE,
+        8,
+        &[0; 8],
+    );
+    object.reloc(array, 0, R_X86_64_64, sym(name), 0);
+    object
+}
+
+#[test]
+fn init_array_entries_are_contiguous_between_their_bounds() {
+    let mut main = Object::x86_64();
+    let text = main.text(".text", Code::new().load_edi(sym("bounds"), 0).exit(0));
+    main.func("_start", text, 0);
+    let data = main.data(".data", 8, &[0; 16]);
+    main.object("bounds", data, 0, 16);
+    main.reloc(data, 0, R_X86_64_64, sym("__init_array_start"), 0);
+    main.reloc(data, 8, R_X86_64_64, sym("__init_array_end"), 0);
+    let late = constructor("late", ".init_array");
+    let second = constructor("second", ".init_array.00200");
+    let first = constructor("first", ".init_array.00100");
+
+    let flags = ["--section-headers", "--gc-sections"];
+    let bytes = link(&[&main, &late, &second, &first], &flags).unwrap();
+    let elf = parse(&bytes);
+    let word = |vaddr| u64::from_le_bytes(read_at(&elf, &bytes, vaddr, 8).try_into().unwrap());
+    let bounds = symbol_address(&elf, "bounds").unwrap();
+    let (start, end) = (word(bounds), word(bounds + 8));
+    assert_eq!(end - start, 24);
+    // Prioritized fragments come first, in priority order, and keep their
+    // constructors alive
+    for (i, name) in ["first", "second", "late"].iter().enumerate() {
+        assert_eq!(
+            word(start + 8 * i as u64),
+            symbol_address(&elf, name).unwrap()
+        );
+    }
+}
+
+#[test]
+fn defsym_defines_aliases_and_constants() {
+    let flags = ["--section-headers", "--defsym", "alias=_start"];
+    let bytes = link(&[&pointer_to("alias")], &flags).unwrap();
+    assert_eq!(pointer_value(&bytes), parse(&bytes).entry);
+    let flags = ["--section-headers", "--defsym=magic=0x1234"];
+    let bytes = link(&[&pointer_to("magic")], &flags).unwrap();
+    assert_eq!(pointer_value(&bytes), 0x1234);
+
+    let error = link_error(&[&pointer_to("_end")], &["--defsym", "_start=0x1000"]);
+    assert!(
+        error.contains("--defsym symbol \"_start\" is already defined in"),
+        "{error}"
+    );
+    let error = link_error(&[&pointer_to("_end")], &["--defsym", "alias=missing"]);
+    assert!(
+        error.contains("--defsym alias: symbol \"missing\" is undefined"),
+        "{error}"
+    );
+}
+
+/// `_start` exiting with the last byte of `counter`, a common symbol
+fn counter_user(size: u64, align: u64) -> Object {
+    let mut object = Object::x86_64();
+    let code = Code::new()
+        .load_byte_edi(sym("counter"), size as i64 - 1)
+        .exit_edi();
+    let text = object.text(".text", code);
+    object.func("_start", text, 0);
+    object.bss(".bss", 8, 0x10);
+    object.common("counter", size, align);
+    object
+}
+
+#[test]
+fn common_symbols_are_allocated_in_bss() {
+    let mut other = Object::x86_64();
+    other.common("counter", 0x40, 0x20);
+    let bytes = link(&[&counter_user(0x10, 8), &other], &["--section-headers"]).unwrap();
+    let elf = parse(&bytes);
+    let bss = elf
+        .section_headers
+        .iter()
+        .find(|sh| elf.shdr_strtab.get_at(sh.sh_name) == Some(".bss"))
+        .unwrap();
+    // The largest definition, with its alignment, after the input `.bss`
+    let counter = symbol_address(&elf, "counter").unwrap();
+    assert_eq!(counter % 0x20, 0);
+    assert!(bss.sh_addr + 0x10 <= counter);
+    assert!(counter + 0x40 <= bss.sh_addr + bss.sh_size);
+    assert_exits(&bytes, 0);
+
+    // Definitions in sections win, whichever input comes first
+    let common = counter_user(0x10, 8);
+    let mut data = Object::x86_64();
+    let section = data.data(".data", 8, &[7; 0x10]);
+    data.object("counter", section, 0, 0x10);
+    for inputs in [[&common, &data], [&data, &common]] {
+        assert_exits(&link(&inputs, &[]).unwrap(), 7);
+    }
+}